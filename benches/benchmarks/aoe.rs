@@ -33,7 +33,7 @@ fn from_elem(c: &mut Criterion) {
                         AoeRequest {
                             player_id,
                             radius: 10.0,
-                            coord: None,
+                            ..Default::default()
                         }
                         .into_request(),
                     )
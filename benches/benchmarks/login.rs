@@ -33,6 +33,7 @@ fn from_elem(c: &mut Criterion) {
                             x,
                             y,
                             money: 99,
+                            ..Default::default()
                         }
                         .into_request(),
                     )
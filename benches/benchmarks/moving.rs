@@ -32,7 +32,15 @@ fn from_elem(c: &mut Criterion) {
             let dy = rng.gen_range(-500.0..500.0);
             async move {
                 if let Err(e) = rpc_cli
-                    .moving(MovingRequest { player_id, dx, dy }.into_request())
+                    .moving(
+                        MovingRequest {
+                            player_id,
+                            dx,
+                            dy,
+                            ..Default::default()
+                        }
+                        .into_request(),
+                    )
                     .await
                 {
                     println!("{:?}", e);
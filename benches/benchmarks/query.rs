@@ -30,6 +30,7 @@ fn query(c: &mut Criterion, x: f32, y: f32) {
                             xmax: x,
                             ymin: -y,
                             ymax: y,
+                            ..Default::default()
                         }
                         .into_request(),
                     )
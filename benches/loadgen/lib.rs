@@ -0,0 +1,75 @@
+//! 负载生成器的配置、场景与统计，loadgen的main只负责发请求
+pub mod scenario;
+pub mod stats;
+
+use common::DEFAULT_GAME_PORT;
+
+use anyhow::{ensure, Result};
+use econf::LoadEnv;
+
+#[derive(LoadEnv)]
+pub struct LoadConfig {
+    pub addr: String,          // game-server地址
+    pub scenario: String,      // 阶段以`;`或换行分隔
+    pub scenario_path: String, // 非空时从文件读取场景
+    pub players: u64,          // 玩家数，player_id为player_id_base..player_id_base+players
+    pub player_id_base: u64,
+    pub concurrency: usize, // worker数，即同时在途的请求数上限，玩家按id分给各worker
+    pub tick: u64,          // 每个在线玩家每tick移动一次(ms)
+    pub area: f32,          // 玩家活动在[-area, area]的正方形内
+    pub speed: f32,         // 每秒最大移动距离
+    pub query_ratio: f64,   // 每次移动后发query的概率
+    pub query_size: f32,    // query为以玩家为中心、边长2*query_size的正方形
+    pub aoe_ratio: f64,     // 每次移动后发aoe的概率
+    pub aoe_radius: f32,
+    pub seed: u64,
+    pub auth_key: String, // game-server开启认证时与之相同，请求都带admin token
+}
+
+impl Default for LoadConfig {
+    fn default() -> Self {
+        Self {
+            addr: format!("http://127.0.0.1:{DEFAULT_GAME_PORT}"),
+            scenario: "login_storm 10000; hotspot 60000; migration 30000; flocking 60000; churn 30000; logout 10000".to_string(),
+            scenario_path: String::new(),
+            players: 2000,
+            player_id_base: 0,
+            concurrency: 64,
+            tick: 1000,
+            area: 100_000.0,
+            speed: 500.0,
+            query_ratio: 0.05,
+            query_size: 1000.0,
+            aoe_ratio: 0.05,
+            aoe_radius: 100.0,
+            seed: 0,
+            auth_key: String::new(),
+        }
+    }
+}
+
+impl LoadConfig {
+    /// 启动时检查一次，之后生成坐标、按概率发请求时不再检查
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.area > 0.0 && self.area.is_finite(),
+            "area:{} must be a finite positive number",
+            self.area
+        );
+        ensure!(
+            self.speed >= 0.0 && self.speed.is_finite(),
+            "speed:{} must be a finite non-negative number",
+            self.speed
+        );
+        for (name, ratio) in [
+            ("query_ratio", self.query_ratio),
+            ("aoe_ratio", self.aoe_ratio),
+        ] {
+            ensure!(
+                (0.0..=1.0).contains(&ratio),
+                "{name}:{ratio} must be within [0, 1]"
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+//! # 场景负载生成器
+//! 按场景(见[`loadgen::scenario::Scenario`])驱动一批玩家对运行中的game-server发请求，复现活动时的聚集、迁移、登录高峰等，
+//! 观察扩容与合并，输出各阶段各RPC的延迟分位数。
+//! 参数为LOAD_*；结果输出到stdout，日志输出到stderr。
+use loadgen::scenario::{unit, Phase, PhaseKind, Scenario};
+use loadgen::stats::{Rpc, Stats};
+use loadgen::LoadConfig;
+
+use common::proto::game_service::game_service_client::GameServiceClient;
+use common::proto::game_service::{
+    AoeRequest, MovingRequest, PlayerIdRequest, PlayerInfo, QueryRequest,
+};
+use common::PlayerId;
+use game_server::auth::{Claims, Scope, TokenAuth};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::time::{interval, Instant, MissedTickBehavior};
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::Channel;
+use tonic::{Code, Request, Status};
+use tracing::*;
+
+use std::f32::consts::TAU;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug)]
+struct Bot {
+    player_id: PlayerId,
+    online: bool,
+    x: f32,
+    y: f32,
+}
+
+/// 一个worker负责一部分玩家，按tick依次驱动，同一玩家的请求不会并发
+struct Worker {
+    cli: GameServiceClient<Channel>,
+    token: Option<MetadataValue<Ascii>>,
+    config: Arc<LoadConfig>,
+    rng: StdRng,
+    stats: Stats,
+}
+
+impl Worker {
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(token) = &self.token {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        request
+    }
+
+    async fn call<T>(
+        &mut self,
+        phase: usize,
+        rpc: Rpc,
+        fut: impl Future<Output = Result<T, Status>>,
+    ) -> Result<T, Status> {
+        let begin = Instant::now();
+        let res = fut.await;
+        self.stats.record(phase, rpc, begin.elapsed(), res.is_ok());
+        if let Err(status) = &res {
+            debug!(?rpc, ?status);
+        }
+        res
+    }
+
+    async fn login(&mut self, phase: usize, bot: &mut Bot) {
+        let area = self.config.area;
+        let (x, y) = (
+            self.rng.gen_range(-area..area),
+            self.rng.gen_range(-area..area),
+        );
+        let request = self.request(PlayerInfo {
+            player_id: bot.player_id,
+            x,
+            y,
+            ..Default::default()
+        });
+        let mut cli = self.cli.clone();
+        let res = self.call(phase, Rpc::Login, cli.login(request)).await;
+        // 已登录(例如上次运行未登出)的沿用服务端的会话
+        if res.is_ok() || res.is_err_and(|status| status.code() == Code::AlreadyExists) {
+            *bot = Bot {
+                online: true,
+                x,
+                y,
+                ..*bot
+            };
+        }
+    }
+
+    async fn logout(&mut self, phase: usize, bot: &mut Bot) {
+        let request = self.request(PlayerIdRequest {
+            player_id: bot.player_id,
+            ..Default::default()
+        });
+        let mut cli = self.cli.clone();
+        let _ = self.call(phase, Rpc::Logout, cli.logout(request)).await;
+        bot.online = false;
+    }
+
+    // 向目标移动一步，没有目标或已到达时随机游走；之后按概率query/aoe
+    async fn step(&mut self, index: usize, phase: &Phase, t: u64, bot: &mut Bot) {
+        let step = self.config.speed * self.config.tick as f32 / 1000.0;
+        let (dx, dy) = match phase.target(bot.player_id, t, self.config.area) {
+            Some((x, y)) if (x - bot.x).hypot(y - bot.y) > step => {
+                let dist = (x - bot.x).hypot(y - bot.y);
+                ((x - bot.x) / dist * step, (y - bot.y) / dist * step)
+            }
+            Some((x, y)) => (x - bot.x, y - bot.y),
+            None => {
+                let angle = self.rng.gen_range(0.0..TAU);
+                (step * angle.cos(), step * angle.sin())
+            }
+        };
+        let request = self.request(MovingRequest {
+            player_id: bot.player_id,
+            dx,
+            dy,
+            ..Default::default()
+        });
+        let mut cli = self.cli.clone();
+        match self.call(index, Rpc::Moving, cli.moving(request)).await {
+            // 按服务端截断后的实际坐标
+            Ok(coord) => {
+                let coord = coord.into_inner();
+                (bot.x, bot.y) = (coord.x, coord.y);
+            }
+            // 被空闲超时等登出了
+            Err(status) if status.code() == Code::NotFound => bot.online = false,
+            Err(_) => {}
+        }
+
+        let size = self.config.query_size;
+        if self.rng.gen_bool(self.config.query_ratio) {
+            let request = self.request(QueryRequest {
+                xmin: bot.x - size,
+                xmax: bot.x + size,
+                ymin: bot.y - size,
+                ymax: bot.y + size,
+                ..Default::default()
+            });
+            let mut cli = self.cli.clone();
+            let _ = self.call(index, Rpc::Query, cli.query(request)).await;
+        }
+        if self.rng.gen_bool(self.config.aoe_ratio) {
+            let request = self.request(AoeRequest {
+                player_id: bot.player_id,
+                radius: self.config.aoe_radius,
+                ..Default::default()
+            });
+            let mut cli = self.cli.clone();
+            let _ = self.call(index, Rpc::Aoe, cli.aoe(request)).await;
+        }
+    }
+
+    async fn run(mut self, scenario: Arc<Scenario>, mut bots: Vec<Bot>, start: Instant) -> Stats {
+        let mut ticker = interval(Duration::from_millis(self.config.tick.max(1)));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let elapsed = start.elapsed().as_millis() as u64;
+            let Some((index, phase, t)) = scenario.phase_at(elapsed) else {
+                break;
+            };
+            for bot in &mut bots {
+                match phase.kind {
+                    PhaseKind::LoginStorm { spread } => {
+                        // 每个玩家在spread内的固定时刻登录
+                        let at = (unit(bot.player_id, 3) * spread as f32) as u64;
+                        if bot.online {
+                            self.step(index, phase, t, bot).await;
+                        } else if t >= at {
+                            self.login(index, bot).await;
+                        }
+                    }
+                    PhaseKind::Churn { rate } => {
+                        if !bot.online {
+                            self.login(index, bot).await;
+                        } else if self.rng.gen_bool(rate.clamp(0.0, 1.0)) {
+                            self.logout(index, bot).await;
+                        } else {
+                            self.step(index, phase, t, bot).await;
+                        }
+                    }
+                    PhaseKind::Logout => {
+                        // 留出最后一个tick，阶段结束时全部登出
+                        let span = phase.duration.saturating_sub(self.config.tick);
+                        let at = (unit(bot.player_id, 4) * span as f32) as u64;
+                        if bot.online && t >= at {
+                            self.logout(index, bot).await;
+                        }
+                    }
+                    _ if bot.online => self.step(index, phase, t, bot).await,
+                    _ => {}
+                }
+            }
+        }
+        self.stats
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    let config = econf::load(LoadConfig::default(), "LOAD");
+    config.validate().unwrap();
+    let text = if config.scenario_path.is_empty() {
+        config.scenario.clone()
+    } else {
+        std::fs::read_to_string(&config.scenario_path).unwrap()
+    };
+    let scenario = Arc::new(Scenario::parse(&text, &config).unwrap());
+    info!(
+        config.addr,
+        config.players,
+        config.concurrency,
+        duration = scenario.duration(),
+        "{text}"
+    );
+
+    let cli = GameServiceClient::connect(config.addr.clone())
+        .await
+        .unwrap();
+    let token = (!config.auth_key.is_empty()).then(|| {
+        let token = TokenAuth::new(config.auth_key.as_str()).issue(&Claims {
+            player_id: 0,
+            scope: Scope::Admin,
+            expire_at: 0,
+        });
+        format!("Bearer {token}").parse().unwrap()
+    });
+    let concurrency = config.concurrency.max(1);
+    let mut groups = (0..concurrency).map(|_| vec![]).collect::<Vec<_>>();
+    for i in 0..config.players {
+        groups[i as usize % concurrency].push(Bot {
+            player_id: config.player_id_base + i,
+            online: false,
+            x: 0.0,
+            y: 0.0,
+        });
+    }
+
+    let config = Arc::new(config);
+    let start = Instant::now();
+    let tasks = groups
+        .into_iter()
+        .enumerate()
+        .map(|(i, bots)| {
+            let worker = Worker {
+                cli: cli.clone(),
+                token: token.clone(),
+                config: config.clone(),
+                rng: StdRng::seed_from_u64(config.seed.wrapping_add(i as u64)),
+                stats: Stats::default(),
+            };
+            tokio::spawn(worker.run(scenario.clone(), bots, start))
+        })
+        .collect::<Vec<_>>();
+    let mut stats = Stats::default();
+    for task in tasks {
+        stats.merge(task.await.unwrap());
+    }
+    print!("{}", stats.report(&scenario));
+}
@@ -0,0 +1,240 @@
+use crate::LoadConfig;
+
+use common::PlayerId;
+
+use anyhow::{bail, Context, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+
+/// 按顺序执行的阶段，每个阶段一行(或以`;`分隔)：`<kind> <duration_ms> [key=value ...]`，`#`开头为注释
+/// ```text
+/// login_storm 10000 spread=0      # 未登录的玩家在spread(ms)内陆续登录，0为同时，之后随机游走
+/// wander 30000                    # 随机游走
+/// hotspot 60000 spots=3 radius=2000  # 按player_id分组聚集到spots个随机热点
+/// migration 30000 x=0 y=0 radius=500 # 全体向一点迁移
+/// flocking 60000 groups=10 radius=1000 # 分群跟随各自漂移的头领
+/// churn 30000 rate=0.05           # 每tick按概率登出，已登出的下一tick重新登录
+/// logout 10000                    # 在阶段内陆续全部登出
+/// ```
+/// 只有login_storm与churn会登录，其他阶段只驱动已登录的玩家
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub phases: Vec<Phase>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Phase {
+    pub kind: PhaseKind,
+    pub duration: u64, // ms
+}
+
+#[derive(Debug, Clone)]
+pub enum PhaseKind {
+    LoginStorm { spread: u64 },
+    Wander,
+    Hotspot { spots: Vec<(f32, f32)>, radius: f32 },
+    Migration { x: f32, y: f32, radius: f32 },
+    Flocking { leaders: Vec<Leader>, radius: f32 },
+    Churn { rate: f64 },
+    Logout,
+}
+
+/// 头领从起点匀速移动，碰到边界反弹，位置只由时间决定，各worker不用同步
+#[derive(Debug, Clone, Copy)]
+pub struct Leader {
+    x: f32,
+    y: f32,
+    vx: f32, // 每秒
+    vy: f32,
+}
+
+impl PhaseKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PhaseKind::LoginStorm { .. } => "login_storm",
+            PhaseKind::Wander => "wander",
+            PhaseKind::Hotspot { .. } => "hotspot",
+            PhaseKind::Migration { .. } => "migration",
+            PhaseKind::Flocking { .. } => "flocking",
+            PhaseKind::Churn { .. } => "churn",
+            PhaseKind::Logout => "logout",
+        }
+    }
+}
+
+// key=value参数，未知的key报错
+struct Params<'a>(HashMap<&'a str, &'a str>);
+
+impl<'a> Params<'a> {
+    fn parse(fields: &[&'a str]) -> Result<Self> {
+        fields
+            .iter()
+            .map(|field| {
+                field
+                    .split_once('=')
+                    .with_context(|| format!("expected key=value, got {field:?}"))
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+
+    fn get<T: std::str::FromStr>(&mut self, key: &str, default: T) -> Result<T>
+    where
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        match self.0.remove(key) {
+            Some(value) => value.parse().with_context(|| format!("invalid {key}")),
+            None => Ok(default),
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        if let Some(key) = self.0.keys().next() {
+            bail!("unknown parameter {key:?}");
+        }
+        Ok(())
+    }
+}
+
+impl Scenario {
+    /// 热点位置、头领起点与方向由seed决定，同一配置可重复；config须已通过validate
+    pub fn parse(text: &str, config: &LoadConfig) -> Result<Self> {
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let area = config.area;
+        let mut phases = vec![];
+        for (no, line) in text.split(['\n', ';']).enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let phase = (|| -> Result<_> {
+                let [kind, duration, params @ ..] = &fields[..] else {
+                    bail!("expected <kind> <duration_ms>");
+                };
+                let duration = duration.parse().context("invalid duration")?;
+                let mut params = Params::parse(params)?;
+                let kind = match *kind {
+                    "login_storm" => PhaseKind::LoginStorm {
+                        spread: params.get("spread", 0)?,
+                    },
+                    "wander" => PhaseKind::Wander,
+                    "hotspot" => {
+                        let spots = params.get("spots", 3u32)?.max(1);
+                        PhaseKind::Hotspot {
+                            spots: (0..spots)
+                                .map(|_| (rng.gen_range(-area..area), rng.gen_range(-area..area)))
+                                .collect(),
+                            radius: params.get("radius", 2000.0)?,
+                        }
+                    }
+                    "migration" => PhaseKind::Migration {
+                        x: params.get("x", 0.0)?,
+                        y: params.get("y", 0.0)?,
+                        radius: params.get("radius", 500.0)?,
+                    },
+                    "flocking" => {
+                        let groups = params.get("groups", 10u32)?.max(1);
+                        // 头领比玩家慢，玩家追得上
+                        let speed = config.speed / 2.0;
+                        PhaseKind::Flocking {
+                            leaders: (0..groups)
+                                .map(|_| {
+                                    let angle = rng.gen_range(0.0..TAU);
+                                    Leader {
+                                        x: rng.gen_range(-area..area),
+                                        y: rng.gen_range(-area..area),
+                                        vx: speed * angle.cos(),
+                                        vy: speed * angle.sin(),
+                                    }
+                                })
+                                .collect(),
+                            radius: params.get("radius", 1000.0)?,
+                        }
+                    }
+                    "churn" => PhaseKind::Churn {
+                        rate: params.get("rate", 0.05)?,
+                    },
+                    "logout" => PhaseKind::Logout,
+                    _ => bail!("unknown phase {kind:?}"),
+                };
+                params.finish()?;
+                Ok(Phase { kind, duration })
+            })()
+            .with_context(|| format!("phase {}: {line:?}", no + 1))?;
+            phases.push(phase);
+        }
+        if phases.is_empty() {
+            bail!("empty scenario");
+        }
+        Ok(Self { phases })
+    }
+
+    /// elapsed所在的阶段及阶段内已经过的时间，全部结束时为None
+    pub fn phase_at(&self, mut elapsed: u64) -> Option<(usize, &Phase, u64)> {
+        for (index, phase) in self.phases.iter().enumerate() {
+            if elapsed < phase.duration {
+                return Some((index, phase, elapsed));
+            }
+            elapsed -= phase.duration;
+        }
+        None
+    }
+
+    pub fn duration(&self) -> u64 {
+        self.phases.iter().map(|phase| phase.duration).sum()
+    }
+}
+
+/// 由player_id决定的[0, 1)伪随机数，seq区分同一玩家的不同用途
+pub fn unit(player_id: PlayerId, seq: u64) -> f32 {
+    let h =
+        (player_id ^ seq.wrapping_mul(0xD6E8_FEB8_6659_FD93)).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
+
+// 半径内均匀分布的偏移，每个玩家固定
+fn offset(player_id: PlayerId, radius: f32) -> (f32, f32) {
+    let angle = unit(player_id, 1) * TAU;
+    let dist = unit(player_id, 2).sqrt() * radius;
+    (dist * angle.cos(), dist * angle.sin())
+}
+
+// 在[-area, area]内往返
+pub fn bounce(start: f32, distance: f32, area: f32) -> f32 {
+    let span = 2.0 * area;
+    let p = (start + area + distance).rem_euclid(2.0 * span);
+    if p > span {
+        2.0 * span - p - area
+    } else {
+        p - area
+    }
+}
+
+impl Phase {
+    /// 玩家在阶段内t时刻要去的位置，None为随机游走
+    pub fn target(&self, player_id: PlayerId, t: u64, area: f32) -> Option<(f32, f32)> {
+        let (x, y, radius) = match &self.kind {
+            PhaseKind::Hotspot { spots, radius } => {
+                let (x, y) = spots[player_id as usize % spots.len()];
+                (x, y, *radius)
+            }
+            PhaseKind::Migration { x, y, radius } => (*x, *y, *radius),
+            PhaseKind::Flocking { leaders, radius } => {
+                let leader = leaders[player_id as usize % leaders.len()];
+                let secs = t as f32 / 1000.0;
+                (
+                    bounce(leader.x, leader.vx * secs, area),
+                    bounce(leader.y, leader.vy * secs, area),
+                    *radius,
+                )
+            }
+            _ => return None,
+        };
+        let (dx, dy) = offset(player_id, radius);
+        Some(((x + dx).clamp(-area, area), (y + dy).clamp(-area, area)))
+    }
+}
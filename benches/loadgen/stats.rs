@@ -0,0 +1,108 @@
+use crate::scenario::Scenario;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rpc {
+    Login,
+    Logout,
+    Moving,
+    Query,
+    Aoe,
+}
+
+impl Rpc {
+    fn name(self) -> &'static str {
+        match self {
+            Rpc::Login => "login",
+            Rpc::Logout => "logout",
+            Rpc::Moving => "moving",
+            Rpc::Query => "query",
+            Rpc::Aoe => "aoe",
+        }
+    }
+}
+
+/// 按(阶段, RPC)记录成功请求的延迟(us)与失败数，各worker各自记录，结束后合并
+#[derive(Debug, Default)]
+pub struct Stats {
+    entries: BTreeMap<(usize, Rpc), Entry>,
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    latencies: Vec<u32>,
+    errors: u64,
+}
+
+// 最近秩法，latencies已排序
+pub fn percentile(latencies: &[u32], p: f64) -> f64 {
+    if latencies.is_empty() {
+        return 0.0;
+    }
+    let rank = (p * latencies.len() as f64).ceil().max(1.0) as usize;
+    latencies[rank.min(latencies.len()) - 1] as f64 / 1000.0
+}
+
+impl Stats {
+    pub fn record(&mut self, phase: usize, rpc: Rpc, latency: Duration, ok: bool) {
+        let entry = self.entries.entry((phase, rpc)).or_default();
+        if ok {
+            entry
+                .latencies
+                .push(latency.as_micros().min(u32::MAX as u128) as u32);
+        } else {
+            entry.errors += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: Stats) {
+        for (key, other) in other.entries {
+            let entry = self.entries.entry(key).or_default();
+            entry.latencies.extend(other.latencies);
+            entry.errors += other.errors;
+        }
+    }
+
+    /// 每个阶段每种RPC一行csv，延迟为ms；最后一行以`#`开头为全程汇总
+    pub fn report(mut self, scenario: &Scenario) -> String {
+        let mut out =
+            String::from("phase,rpc,count,errors,rps,p50_ms,p90_ms,p99_ms,p999_ms,max_ms\n");
+        let mut all = vec![];
+        let mut errors = 0;
+        for (&(index, rpc), entry) in &mut self.entries {
+            let phase = &scenario.phases[index];
+            entry.latencies.sort_unstable();
+            let count = entry.latencies.len() as u64 + entry.errors;
+            let latencies = &entry.latencies;
+            let _ = writeln!(
+                out,
+                "{index}:{},{},{count},{},{:.1},{:.2},{:.2},{:.2},{:.2},{:.2}",
+                phase.kind.name(),
+                rpc.name(),
+                entry.errors,
+                count as f64 * 1000.0 / phase.duration.max(1) as f64,
+                percentile(latencies, 0.5),
+                percentile(latencies, 0.9),
+                percentile(latencies, 0.99),
+                percentile(latencies, 0.999),
+                percentile(latencies, 1.0),
+            );
+            all.extend_from_slice(latencies);
+            errors += entry.errors;
+        }
+        all.sort_unstable();
+        let count = all.len() as u64 + errors;
+        let _ = writeln!(
+            out,
+            "# requests:{count} errors:{errors} rps:{:.1} p50_ms:{:.2} p99_ms:{:.2} max_ms:{:.2}",
+            count as f64 * 1000.0 / scenario.duration().max(1) as f64,
+            percentile(&all, 0.5),
+            percentile(&all, 0.99),
+            percentile(&all, 1.0),
+        );
+        out
+    }
+}
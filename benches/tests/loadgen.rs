@@ -0,0 +1,136 @@
+use loadgen::scenario::{bounce, unit, PhaseKind, Scenario};
+use loadgen::stats::percentile;
+use loadgen::LoadConfig;
+
+// 默认场景可解析，同一种子热点位置相同，阶段按时间先后查找
+#[test]
+fn test_scenario() {
+    let config = LoadConfig::default();
+    let scenario = Scenario::parse(&config.scenario, &config).unwrap();
+    let names = scenario
+        .phases
+        .iter()
+        .map(|phase| phase.kind.name())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "login_storm",
+            "hotspot",
+            "migration",
+            "flocking",
+            "churn",
+            "logout"
+        ]
+    );
+    assert_eq!(scenario.duration(), 200_000);
+
+    let text = "# 注释
+        login_storm 1000 spread=500
+        hotspot 2000 spots=2 radius=100; churn 500 rate=0.5";
+    let a = Scenario::parse(text, &config).unwrap();
+    let b = Scenario::parse(text, &config).unwrap();
+    let spots = |scenario: &Scenario| match &scenario.phases[1].kind {
+        PhaseKind::Hotspot { spots, radius } => {
+            assert_eq!(*radius, 100.0);
+            spots.clone()
+        }
+        kind => panic!("{kind:?}"),
+    };
+    assert_eq!(spots(&a), spots(&b));
+    assert_eq!(spots(&a).len(), 2);
+    assert!(matches!(
+        a.phases[0].kind,
+        PhaseKind::LoginStorm { spread: 500 }
+    ));
+    assert!(matches!(a.phases[2].kind, PhaseKind::Churn { rate } if rate == 0.5));
+
+    let at = |elapsed| a.phase_at(elapsed).map(|(index, _, t)| (index, t));
+    assert_eq!(at(0), Some((0, 0)));
+    assert_eq!(at(999), Some((0, 999)));
+    assert_eq!(at(1000), Some((1, 0)));
+    assert_eq!(at(3499), Some((2, 499)));
+    assert_eq!(at(3500), None);
+
+    for text in [
+        "",
+        "# 只有注释",
+        "wander",
+        "wander x",
+        "teleport 1000",
+        "wander 1000 x=1",
+        "hotspot 1000 spots",
+        "churn 1000 rate=fast",
+    ] {
+        assert!(Scenario::parse(text, &config).is_err(), "{text:?}");
+    }
+}
+
+#[test]
+fn test_validate() {
+    assert!(LoadConfig::default().validate().is_ok());
+    let invalid = [
+        LoadConfig {
+            area: 0.0,
+            ..Default::default()
+        },
+        LoadConfig {
+            area: -1.0,
+            ..Default::default()
+        },
+        LoadConfig {
+            speed: f32::NAN,
+            ..Default::default()
+        },
+        LoadConfig {
+            query_ratio: 1.5,
+            ..Default::default()
+        },
+        LoadConfig {
+            aoe_ratio: -0.1,
+            ..Default::default()
+        },
+    ];
+    for config in invalid {
+        assert!(config.validate().is_err());
+    }
+}
+
+// 在[-area, area]内往返，一个来回回到起点
+#[test]
+fn test_bounce() {
+    let area = 100.0;
+    assert_eq!(bounce(0.0, 50.0, area), 50.0);
+    assert_eq!(bounce(0.0, 150.0, area), 50.0);
+    assert_eq!(bounce(0.0, -150.0, area), -50.0);
+    assert_eq!(bounce(0.0, 250.0, area), -50.0);
+    assert_eq!(bounce(10.0, 800.0, area), 10.0);
+    for i in 0..1000 {
+        let x = bounce(-30.0, i as f32 * 7.3, area);
+        assert!((-area..=area).contains(&x), "{x}");
+    }
+}
+
+#[test]
+fn test_unit() {
+    for player_id in 0..1000 {
+        let u = unit(player_id, 1);
+        assert!((0.0..1.0).contains(&u));
+        assert_eq!(u, unit(player_id, 1));
+    }
+    assert_ne!(unit(1, 1), unit(1, 2));
+    assert_ne!(unit(1, 1), unit(2, 1));
+}
+
+// 最近秩法，延迟为us，结果为ms
+#[test]
+fn test_percentile() {
+    assert_eq!(percentile(&[], 0.5), 0.0);
+    let latencies = (1..=100).map(|ms| ms * 1000).collect::<Vec<u32>>();
+    assert_eq!(percentile(&latencies, 0.0), 1.0);
+    assert_eq!(percentile(&latencies, 0.5), 50.0);
+    assert_eq!(percentile(&latencies, 0.99), 99.0);
+    assert_eq!(percentile(&latencies, 0.999), 100.0);
+    assert_eq!(percentile(&latencies, 1.0), 100.0);
+    assert_eq!(percentile(&[1500], 0.5), 1.5);
+}
@@ -0,0 +1,51 @@
+use crate::ServerId;
+
+use anyhow::{bail, ensure, Context, Result};
+use tokio::net::TcpListener;
+
+use std::ops::RangeInclusive;
+
+// map-server绑定端口后输出到stdout的一行，dispatcher读到后按其中的地址连接
+pub const READY_PREFIX: &str = "MAP_SERVER_READY";
+pub const DEFAULT_PORT_RANGE: &str = "0";
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+
+/// # map-server启动握手
+/// dispatcher分配server_id并传入端口配置，启动map-server进程后读取它的stdout，
+/// map-server绑定端口后输出`MAP_SERVER_READY <server_id> <addr>`，dispatcher连接实际绑定的地址。
+/// 端口配置：`0`由系统分配，`5000`固定端口，`5000-5100`取范围内第一个空闲的
+pub fn parse_port_range(s: &str) -> Result<RangeInclusive<u16>> {
+    let parse = |s: &str| {
+        s.trim()
+            .parse::<u16>()
+            .with_context(|| format!("invalid port {s:?}"))
+    };
+    let range = match s.split_once('-') {
+        Some((start, end)) => parse(start)?..=parse(end)?,
+        None => parse(s)?..=parse(s)?,
+    };
+    ensure!(!range.is_empty(), "empty port range {s:?}");
+    Ok(range)
+}
+
+pub fn ready_line(server_id: ServerId, addr: &str) -> String {
+    format!("{READY_PREFIX} {server_id} {addr}")
+}
+
+/// 不是握手行时为None
+pub fn parse_ready_line(line: &str) -> Option<(ServerId, &str)> {
+    let mut fields = line.strip_prefix(READY_PREFIX)?.split_whitespace();
+    let server_id = fields.next()?.parse().ok()?;
+    let addr = fields.next()?;
+    fields.next().is_none().then_some((server_id, addr))
+}
+
+/// 按顺序尝试绑定host上范围内的端口，0由系统分配
+pub async fn bind(host: &str, ports: RangeInclusive<u16>) -> Result<TcpListener> {
+    for port in ports.clone() {
+        if let Ok(listener) = TcpListener::bind((host, port)).await {
+            return Ok(listener);
+        }
+    }
+    bail!("no free port in {host}:{ports:?}")
+}
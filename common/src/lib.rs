@@ -1,30 +1,48 @@
+pub mod launch;
 pub mod proto;
+pub mod tls;
+pub mod world_map;
+pub mod zone_id;
+
+pub use zone_id::ZoneId;
 
 use tonic::{Response, Status};
 
 pub type RPCResult<T> = Result<Response<T>, Status>;
 
 pub type PlayerId = u64;
-pub type ZoneId = u64;
 pub type ServerId = u32;
-pub type GridId = (usize, usize);
+pub type GridId = (usize, usize, usize);
+pub type WorldId = u32;
 
 // 世界地图尺寸
 pub const WORLD_X_MAX: f32 = 1_000_000.0;
 pub const WORLD_Y_MAX: f32 = 1_000_000.0;
 pub const WORLD_X_MIN: f32 = -WORLD_X_MAX;
 pub const WORLD_Y_MIN: f32 = -WORLD_Y_MAX;
+pub const WORLD_Z_MAX: f32 = 1_000_000.0; // 只有3D世界使用z轴
+pub const WORLD_Z_MIN: f32 = -WORLD_Z_MAX;
 pub const DEFAULT_MAX_PLAYERS: u32 = 1000; // 服务器最大用户数，触发扩容
 pub const DEFAULT_MIN_PLAYERS: u32 = DEFAULT_MAX_PLAYERS / 4; // 服务器最小用户数，触发缩容
 pub const DEFAULT_MAX_ZONE_DEPTH: u32 = 10; // 四叉树最大深度
 pub const GRID_LENGTH: usize = 100; // Grid边长
 pub const AOE_MONEY: u64 = 1; // 每次aoe给周边玩家增加的钱数
-pub const ROOT_ZONE_ID: ZoneId = 1;
+pub const EVENT_CHANNEL_SIZE: usize = 64; // 玩家事件流缓冲，满了丢弃新事件
+pub const DEFAULT_DEDUP_WINDOW: usize = 16; // 每个玩家缓存最近多少个request_id的结果，用于重试去重
+pub const DEFAULT_WORLD_ID: WorldId = 0;
 
 pub const GAME_PORT_ENV_NAME: &str = "GAME_SERVER_PORT";
+pub const MAP_HOST_ENV_NAME: &str = "MAP_SERVER_HOST";
 pub const MAP_PORT_ENV_NAME: &str = "MAP_SERVER_PORT";
+pub const SERVER_ID_ENV_NAME: &str = "MAP_SERVER_ID";
+pub const WORLD_MAP_ENV_NAME: &str = "MAP_WORLD_MAP_PATH";
+pub const TICK_INTERVAL_ENV_NAME: &str = "MAP_TICK_INTERVAL";
+pub const TLS_CA_ENV_NAME: &str = "MAP_TLS_CA_PATH";
+pub const TLS_CERT_ENV_NAME: &str = "MAP_TLS_CERT_PATH";
+pub const TLS_KEY_ENV_NAME: &str = "MAP_TLS_KEY_PATH";
+pub const TLS_DOMAIN_ENV_NAME: &str = "MAP_TLS_DOMAIN";
+pub const DIMENSION_ENV_NAME: &str = "MAP_DIMENSION";
 pub const DEFAULT_GAME_PORT: u32 = 4880;
-pub const DEFAULT_MAP_PORT: u32 = 5000;
 
 pub trait ErrHandle {
     type S;
@@ -69,141 +87,163 @@ impl<T, E: std::fmt::Debug> ErrHandle for Result<T, E> {
     }
 }
 
-// zone范围均为左闭右开，根节点depth=1
-pub fn xy_to_zone_id(x: f32, y: f32, depth: u32) -> ZoneId {
-    assert_ne!(depth, 0);
-    let mut id = ROOT_ZONE_ID;
-    let mut origin_x = 0.0;
-    let mut origin_y = 0.0;
-    let mut length = WORLD_X_MAX;
-    let mut height = WORLD_Y_MAX;
-    for _ in 1..depth {
-        length /= 2.0;
-        height /= 2.0;
-        let quadrant = if y >= origin_y {
-            origin_y += height;
-            if x >= origin_x {
-                origin_x += length;
-                1 // 第1象限
-            } else {
-                origin_x -= length;
-                2
-            }
-        } else {
-            origin_y -= height;
-            if x < origin_x {
-                origin_x -= length;
-                3
-            } else {
-                origin_x += length;
-                4
-            }
-        };
-        id = id * 10 + quadrant;
-    }
-    id
+/// 世界的空间维度，按世界配置
+/// * D2：z恒为0，zone为四叉树，aoe为圆
+/// * D3：zone为八叉树，aoe为球，query为长方体
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dimension {
+    #[default]
+    D2,
+    D3,
 }
 
-#[inline]
-pub fn zone_depth(id: ZoneId) -> u32 {
-    id.ilog10() + 1
+impl Dimension {
+    // 空串为D2
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "" | "2" => Ok(Self::D2),
+            "3" => Ok(Self::D3),
+            _ => anyhow::bail!("Invalid dimension {s}"),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::D2 => "2",
+            Self::D3 => "3",
+        }
+    }
+
+    // 每个zone的子节点数
+    #[inline]
+    pub fn children(self) -> u64 {
+        match self {
+            Self::D2 => 4,
+            Self::D3 => 8,
+        }
+    }
+
+    // 2D世界没有z轴，z及z方向的位移、速度恒为0
+    #[inline]
+    pub fn flatten_z(self, z: f32) -> f32 {
+        match self {
+            Self::D2 => 0.0,
+            Self::D3 => z,
+        }
+    }
+
+    #[inline]
+    pub fn zone_id(self, x: f32, y: f32, z: f32, depth: u32) -> ZoneId {
+        ZoneId::from_coord(x, y, z, depth, self)
+    }
 }
 
+// 2D世界z恒为0，grid只有一层。世界之外的坐标截到边界上的grid
 #[inline]
-pub fn xy_to_grid(x: f32, y: f32) -> GridId {
+pub fn xyz_to_grid(x: f32, y: f32, z: f32) -> GridId {
     (
-        (x - WORLD_X_MIN) as usize / GRID_LENGTH,
-        (y - WORLD_Y_MIN) as usize / GRID_LENGTH,
+        (x.clamp(WORLD_X_MIN, WORLD_X_MAX) - WORLD_X_MIN) as usize / GRID_LENGTH,
+        (y.clamp(WORLD_Y_MIN, WORLD_Y_MAX) - WORLD_Y_MIN) as usize / GRID_LENGTH,
+        (z.clamp(WORLD_Z_MIN, WORLD_Z_MAX) - WORLD_Z_MIN) as usize / GRID_LENGTH,
     )
 }
 
+/// 2D范围(障碍物、四叉树zone)的z取整个世界高度
 #[derive(Debug, Clone, PartialEq)]
 pub struct AABB {
     pub xmin: f32,
     pub xmax: f32,
     pub ymin: f32,
     pub ymax: f32,
+    pub zmin: f32,
+    pub zmax: f32,
 }
 impl AABB {
-    pub fn from_zone_id(id: ZoneId) -> Self {
-        // return (xmin,ymin,xmax,ymax)
-        let mut xmin = WORLD_X_MIN;
-        let mut ymin = WORLD_Y_MIN;
-        let mut xmax = WORLD_X_MAX;
-        let mut ymax = WORLD_Y_MAX;
-        if id > 1 {
-            let s = id
-                .to_string()
-                .chars()
-                .map(|d| d.to_digit(10).unwrap())
-                .collect::<Vec<_>>();
-            for quadrant in &s[1..] {
-                match quadrant {
-                    1 => {
-                        xmin = (xmin + xmax) / 2.0;
-                        ymin = (ymin + ymax) / 2.0;
-                    }
-                    2 => {
-                        xmax = (xmin + xmax) / 2.0;
-                        ymin = (ymin + ymax) / 2.0;
-                    }
-                    3 => {
-                        xmax = (xmin + xmax) / 2.0;
-                        ymax = (ymin + ymax) / 2.0;
-                    }
-                    4 => {
-                        xmin = (xmin + xmax) / 2.0;
-                        ymax = (ymin + ymax) / 2.0;
-                    }
-                    _ => unreachable!(),
-                }
-            }
-        }
-        Self {
-            xmin,
-            ymin,
-            xmax,
-            ymax,
-        }
-    }
-
     // 获取AABB范围内所有grids
     pub fn get_grids_in_aabb(&self) -> Vec<GridId> {
-        let grid_min = xy_to_grid(self.xmin, self.ymin);
-        let grid_max = xy_to_grid(self.xmax, self.ymax);
+        let grid_min = xyz_to_grid(self.xmin, self.ymin, self.zmin);
+        let grid_max = xyz_to_grid(self.xmax, self.ymax, self.zmax);
         let mut set = Vec::new();
         for x in grid_min.0..=grid_max.0 {
             for y in grid_min.1..=grid_max.1 {
-                set.push((x, y));
+                for z in grid_min.2..=grid_max.2 {
+                    set.push((x, y, z));
+                }
             }
         }
         set
     }
 
-    // 判断点是否在AABB内
+    // AABB范围内grid的数量，不用逐个生成。跨越全世界的3D范围在32位下也不会溢出
+    pub fn grid_count(&self) -> usize {
+        let grid_min = xyz_to_grid(self.xmin, self.ymin, self.zmin);
+        let grid_max = xyz_to_grid(self.xmax, self.ymax, self.zmax);
+        (grid_max.0 + 1)
+            .saturating_sub(grid_min.0)
+            .saturating_mul((grid_max.1 + 1).saturating_sub(grid_min.1))
+            .saturating_mul((grid_max.2 + 1).saturating_sub(grid_min.2))
+    }
+
+    // 判断点是否在AABB的xy平面范围内，不考虑z
     #[inline]
     pub fn contains(&self, x: f32, y: f32) -> bool {
         x >= self.xmin && x <= self.xmax && y >= self.ymin && y <= self.ymax
     }
 
-    // 判断2个AABB是否有交集
+    #[inline]
+    pub fn contains_xyz(&self, x: f32, y: f32, z: f32) -> bool {
+        self.contains(x, y) && z >= self.zmin && z <= self.zmax
+    }
+
+    // 与球(2D中z相同，即圆)是否相交：AABB内离球心最近的点在半径内
+    pub fn intersects_sphere(&self, x: f32, y: f32, z: f32, radius: f32) -> bool {
+        let dx = x - x.clamp(self.xmin, self.xmax);
+        let dy = y - y.clamp(self.ymin, self.ymax);
+        let dz = z - z.clamp(self.zmin, self.zmax);
+        dx * dx + dy * dy + dz * dz <= radius * radius
+    }
+
+    // 判断2个AABB是否有交集：三个轴上的区间都重叠(含边界相接)
     pub fn has_intersection(&self, other: &Self) -> bool {
-        [
-            (self.xmin, self.ymin),
-            (self.xmin, self.ymax),
-            (self.xmax, self.ymin),
-            (self.xmax, self.ymax),
-        ]
-        .into_iter()
-        .any(|(x, y)| other.contains(x, y))
-            || [
-                (other.xmin, other.ymin),
-                (other.xmin, other.ymax),
-                (other.xmax, other.ymin),
-                (other.xmax, other.ymax),
-            ]
-            .into_iter()
-            .any(|(x, y)| self.contains(x, y))
+        self.xmin <= other.xmax
+            && other.xmin <= self.xmax
+            && self.ymin <= other.ymax
+            && other.ymin <= self.ymax
+            && self.zmin <= other.zmax
+            && other.zmin <= self.zmax
+    }
+
+    // 两个轴上范围相同、第三个轴上重叠或相接时，并集仍是AABB
+    pub fn try_union(&self, other: &Self) -> Option<AABB> {
+        let same_x = self.xmin == other.xmin && self.xmax == other.xmax;
+        let same_y = self.ymin == other.ymin && self.ymax == other.ymax;
+        let same_z = self.zmin == other.zmin && self.zmax == other.zmax;
+        let same_axes = [same_x, same_y, same_z].into_iter().filter(|&b| b).count();
+        let mergeable = same_axes >= 2 && self.has_intersection(other);
+        mergeable.then(|| AABB {
+            xmin: self.xmin.min(other.xmin),
+            xmax: self.xmax.max(other.xmax),
+            ymin: self.ymin.min(other.ymin),
+            ymax: self.ymax.max(other.ymax),
+            zmin: self.zmin.min(other.zmin),
+            zmax: self.zmax.max(other.zmax),
+        })
+    }
+
+    // 反复合并可以拼成AABB的两块，直到不能再合并。并集不变，块数减少
+    pub fn merge_adjacent(mut areas: Vec<AABB>) -> Vec<AABB> {
+        'merge: loop {
+            for i in 0..areas.len() {
+                for j in i + 1..areas.len() {
+                    if let Some(union) = areas[i].try_union(&areas[j]) {
+                        areas[i] = union;
+                        areas.swap_remove(j);
+                        continue 'merge;
+                    }
+                }
+            }
+            return areas;
+        }
     }
 
     // 两个AABB的交集
@@ -214,6 +254,8 @@ impl AABB {
                 xmax: self.xmax.min(other.xmax),
                 ymin: self.ymin.max(other.ymin),
                 ymax: self.ymax.min(other.ymax),
+                zmin: self.zmin.max(other.zmin),
+                zmax: self.zmax.min(other.zmax),
             })
         } else {
             None
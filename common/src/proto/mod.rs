@@ -1,2 +1,3 @@
+pub mod dispatcher_service;
 pub mod game_service;
 pub mod map_service;
@@ -0,0 +1,111 @@
+use crate::{TLS_CA_ENV_NAME, TLS_CERT_ENV_NAME, TLS_DOMAIN_ENV_NAME, TLS_KEY_ENV_NAME};
+
+use anyhow::{bail, Context, Result};
+use tonic::transport::{
+    Certificate, Channel, ClientTlsConfig, Endpoint, Identity, ServerTlsConfig,
+};
+
+use std::path::Path;
+
+// 证书中的域名，连接时按此校验，与实际连接的ip无关
+pub const DEFAULT_TLS_DOMAIN: &str = "localhost";
+
+/// dispatcher与map-server之间的双向TLS
+/// 所有进程共用一个CA，每个进程的证书同时用于服务端与客户端身份
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    ca: Certificate,
+    identity: Identity,
+    domain: String,
+}
+
+impl TlsConfig {
+    pub fn load(
+        ca_path: impl AsRef<Path>,
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+        domain: &str,
+    ) -> Result<Self> {
+        let read = |path: &Path| {
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+        };
+        Ok(Self {
+            ca: Certificate::from_pem(read(ca_path.as_ref())?),
+            identity: Identity::from_pem(read(cert_path.as_ref())?, read(key_path.as_ref())?),
+            domain: if domain.is_empty() {
+                DEFAULT_TLS_DOMAIN.to_string()
+            } else {
+                domain.to_string()
+            },
+        })
+    }
+
+    // 三个路径都为空时不开启TLS，只配置了一部分视为错误
+    pub fn load_optional(
+        ca_path: &str,
+        cert_path: &str,
+        key_path: &str,
+        domain: &str,
+    ) -> Result<Option<Self>> {
+        match (
+            ca_path.is_empty(),
+            cert_path.is_empty(),
+            key_path.is_empty(),
+        ) {
+            (true, true, true) => Ok(None),
+            (false, false, false) => Self::load(ca_path, cert_path, key_path, domain).map(Some),
+            _ => bail!("TLS needs ca, cert and key files together"),
+        }
+    }
+
+    pub fn from_env() -> Result<Option<Self>> {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        Self::load_optional(
+            &var(TLS_CA_ENV_NAME),
+            &var(TLS_CERT_ENV_NAME),
+            &var(TLS_KEY_ENV_NAME),
+            &var(TLS_DOMAIN_ENV_NAME),
+        )
+    }
+
+    // 要求客户端出示同一CA签发的证书
+    pub fn server_config(&self) -> ServerTlsConfig {
+        ServerTlsConfig::new()
+            .identity(self.identity.clone())
+            .client_ca_root(self.ca.clone())
+    }
+
+    pub fn client_config(&self) -> ClientTlsConfig {
+        ClientTlsConfig::new()
+            .ca_certificate(self.ca.clone())
+            .identity(self.identity.clone())
+            .domain_name(self.domain.clone())
+    }
+}
+
+#[inline]
+pub fn scheme(tls: Option<&TlsConfig>) -> &'static str {
+    if tls.is_some() {
+        "https"
+    } else {
+        "http"
+    }
+}
+
+// 开启TLS时带上客户端证书
+pub async fn connect(addr: String, tls: Option<&TlsConfig>) -> Result<Channel> {
+    let mut endpoint = Endpoint::from_shared(addr)?;
+    if let Some(tls) = tls {
+        endpoint = endpoint.tls_config(tls.client_config())?;
+    }
+    Ok(endpoint.connect().await?)
+}
+
+// 不立即连接，第一次请求时再连。用于启动顺序不确定的对端
+pub fn connect_lazy(addr: String, tls: Option<&TlsConfig>) -> Result<Channel> {
+    let mut endpoint = Endpoint::from_shared(addr)?;
+    if let Some(tls) = tls {
+        endpoint = endpoint.tls_config(tls.client_config())?;
+    }
+    Ok(endpoint.connect_lazy())
+}
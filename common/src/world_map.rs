@@ -0,0 +1,225 @@
+use crate::{AABB, WORLD_Z_MAX, WORLD_Z_MIN};
+
+use anyhow::{bail, ensure, Context, Result};
+
+use std::path::Path;
+
+// 撞到障碍物时停在障碍物外的距离
+const STOP_DISTANCE: f32 = 0.01;
+
+/// 静态世界地图，game-server与map-server加载同一份文件
+/// 只作用于xy平面：3D世界中障碍物为贯穿整个高度的柱体，speed_limit只限制水平位移
+/// 文件为文本格式，每行一条，`#`开头为注释：
+/// ```text
+/// # 单次移动最大距离，不设置则不限
+/// speed_limit 500
+/// # 矩形障碍物：xmin ymin xmax ymax
+/// rect -100 -100 100 100
+/// # 多边形障碍物：至少3个顶点 x1 y1 x2 y2 x3 y3 ...
+/// polygon 0 0 100 0 50 80
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorldMap {
+    pub speed_limit: Option<f32>,
+    pub obstacles: Vec<Obstacle>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Obstacle {
+    Rect(AABB),
+    Polygon(Vec<(f32, f32)>),
+}
+
+impl WorldMap {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read world map {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Invalid world map {}", path.display()))
+    }
+
+    // 从环境变量指定的路径加载，未设置时返回空地图
+    pub fn from_env(env_name: &str) -> Result<Self> {
+        match std::env::var(env_name) {
+            Ok(path) if !path.is_empty() => Self::load(path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut map = Self::default();
+        for (line_no, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let kind = words.next().unwrap();
+            let nums = words
+                .map(|w| w.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("line {}: {line}", line_no + 1))?;
+            match kind {
+                "speed_limit" => {
+                    ensure!(
+                        nums.len() == 1 && nums[0] > 0.0,
+                        "line {}: speed_limit needs one positive number",
+                        line_no + 1
+                    );
+                    map.speed_limit = Some(nums[0]);
+                }
+                "rect" => {
+                    ensure!(
+                        nums.len() == 4 && nums[0] < nums[2] && nums[1] < nums[3],
+                        "line {}: rect needs xmin ymin xmax ymax",
+                        line_no + 1
+                    );
+                    map.obstacles.push(Obstacle::Rect(AABB {
+                        xmin: nums[0],
+                        ymin: nums[1],
+                        xmax: nums[2],
+                        ymax: nums[3],
+                        zmin: WORLD_Z_MIN,
+                        zmax: WORLD_Z_MAX,
+                    }));
+                }
+                "polygon" => {
+                    ensure!(
+                        nums.len() >= 6 && nums.len() % 2 == 0,
+                        "line {}: polygon needs at least 3 vertices",
+                        line_no + 1
+                    );
+                    map.obstacles.push(Obstacle::Polygon(
+                        nums.chunks(2).map(|xy| (xy[0], xy[1])).collect(),
+                    ));
+                }
+                _ => bail!("line {}: unknown item {kind}", line_no + 1),
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn is_blocked(&self, x: f32, y: f32) -> bool {
+        self.obstacles.iter().any(|o| o.contains(x, y))
+    }
+
+    /// 从(x,y)移动(dx,dy)，返回实际到达的坐标
+    /// 1. 超过speed_limit时沿原方向缩短到speed_limit
+    /// 2. 路径穿过障碍物时停在第一个障碍物外
+    /// 起点已在某障碍物内时（例如地图更新后），忽略该障碍物，允许走出来
+    pub fn clamp_move(&self, x: f32, y: f32, mut dx: f32, mut dy: f32) -> (f32, f32) {
+        let len = (dx * dx + dy * dy).sqrt();
+        if len == 0.0 {
+            return (x, y);
+        }
+        if let Some(limit) = self.speed_limit {
+            if len > limit {
+                dx *= limit / len;
+                dy *= limit / len;
+            }
+        }
+        let len = len.min(self.speed_limit.unwrap_or(f32::MAX));
+
+        let Some(t) = self
+            .obstacles
+            .iter()
+            .filter(|o| !o.contains(x, y))
+            .filter_map(|o| o.first_hit(x, y, dx, dy))
+            .min_by(|a, b| a.total_cmp(b)) else {
+            return (x + dx, y + dy);
+        };
+        let t = ((t * len - STOP_DISTANCE) / len).max(0.0);
+        let target = (x + dx * t, y + dy * t);
+        if self
+            .obstacles
+            .iter()
+            .any(|o| !o.contains(x, y) && o.contains(target.0, target.1))
+        {
+            // 浮点误差导致仍在障碍物内，原地不动
+            (x, y)
+        } else {
+            target
+        }
+    }
+}
+
+impl Obstacle {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        match self {
+            Obstacle::Rect(aabb) => aabb.contains(x, y),
+            Obstacle::Polygon(points) => {
+                // 射线法
+                let mut inside = false;
+                let mut j = points.len() - 1;
+                for i in 0..points.len() {
+                    let (xi, yi) = points[i];
+                    let (xj, yj) = points[j];
+                    if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+                        inside = !inside;
+                    }
+                    j = i;
+                }
+                inside
+            }
+        }
+    }
+
+    // 线段(x,y)->(x+dx,y+dy)第一次碰到障碍物的位置，返回线段参数t∈[0,1]
+    fn first_hit(&self, x: f32, y: f32, dx: f32, dy: f32) -> Option<f32> {
+        match self {
+            Obstacle::Rect(aabb) => {
+                // Liang–Barsky
+                let mut t0 = 0.0_f32;
+                let mut t1 = 1.0_f32;
+                for (p, q) in [
+                    (-dx, x - aabb.xmin),
+                    (dx, aabb.xmax - x),
+                    (-dy, y - aabb.ymin),
+                    (dy, aabb.ymax - y),
+                ] {
+                    if p == 0.0 {
+                        if q < 0.0 {
+                            return None;
+                        }
+                    } else {
+                        let r = q / p;
+                        if p < 0.0 {
+                            t0 = t0.max(r);
+                        } else {
+                            t1 = t1.min(r);
+                        }
+                    }
+                }
+                (t0 <= t1).then_some(t0)
+            }
+            Obstacle::Polygon(points) => (0..points.len())
+                .filter_map(|i| {
+                    let (ax, ay) = points[i];
+                    let (bx, by) = points[(i + 1) % points.len()];
+                    segment_intersection(x, y, dx, dy, ax, ay, bx - ax, by - ay)
+                })
+                .min_by(|a, b| a.total_cmp(b)),
+        }
+    }
+}
+
+// 线段p+t*r与q+u*s的交点，返回t
+#[allow(clippy::too_many_arguments)]
+fn segment_intersection(
+    px: f32,
+    py: f32,
+    rx: f32,
+    ry: f32,
+    qx: f32,
+    qy: f32,
+    sx: f32,
+    sy: f32,
+) -> Option<f32> {
+    let denom = rx * sy - ry * sx;
+    if denom == 0.0 {
+        return None;
+    }
+    let t = ((qx - px) * sy - (qy - py) * sx) / denom;
+    let u = ((qx - px) * ry - (qy - py) * rx) / denom;
+    ((0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)).then_some(t)
+}
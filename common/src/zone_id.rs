@@ -0,0 +1,267 @@
+use crate::{Dimension, AABB};
+use crate::{WORLD_X_MAX, WORLD_X_MIN, WORLD_Y_MAX, WORLD_Y_MIN, WORLD_Z_MAX, WORLD_Z_MIN};
+
+use std::fmt;
+
+// 最高位为八叉树标记
+const OCTREE_FLAG: u64 = 1 << 63;
+
+/// # 四叉树/八叉树节点编号
+/// 从高位到低位依次为：八叉树标记、深度标记位`1`、根节点往下每层的象限号。
+/// 四叉树每层2位，八叉树每层3位；象限号对外为1~4(八叉树1~8)，存储时减1。
+/// 根节点只有深度标记位，深度由标记位所在位置得出，四叉树最深32层，八叉树21层。
+/// 划分在f64下计算，深层的边界是精确的，转为f32的AABB时单调舍入，坐标所在zone的范围总包含该坐标。
+/// proto中按u64原样传输，旧的十进制编号(每层一位数字，例如`123`)用[`ZoneId::from_decimal`]转换
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ZoneId(u64);
+
+#[inline]
+fn level_bits(dimension: Dimension) -> u32 {
+    dimension.children().trailing_zeros()
+}
+
+impl ZoneId {
+    pub const fn root(dimension: Dimension) -> Self {
+        match dimension {
+            Dimension::D2 => Self(1),
+            Dimension::D3 => Self(OCTREE_FLAG | 1),
+        }
+    }
+
+    pub fn max_depth(dimension: Dimension) -> u32 {
+        (u64::BITS - 2) / level_bits(dimension) + 1
+    }
+
+    // proto中的u64，标记位不合法时为None
+    pub fn from_raw(raw: u64) -> Option<Self> {
+        let path = raw & !OCTREE_FLAG;
+        if path == 0 {
+            return None;
+        }
+        let id = Self(raw);
+        let marker = u64::BITS - 1 - path.leading_zeros();
+        (marker % level_bits(id.dimension()) == 0).then_some(id)
+    }
+
+    // 旧的十进制编号，首位为根节点`1`，之后每位一个象限号
+    pub fn from_decimal(decimal: u64, dimension: Dimension) -> Option<Self> {
+        let s = decimal.to_string();
+        let mut digits = s.chars().map(|c| c.to_digit(10).unwrap() as u64);
+        if digits.next() != Some(1) {
+            return None;
+        }
+        digits.try_fold(Self::root(dimension), |id, quadrant| {
+            (1..=dimension.children())
+                .contains(&quadrant)
+                .then(|| id.child(quadrant))
+        })
+    }
+
+    // zone范围均为左闭右开，根节点depth=1
+    pub fn from_coord(x: f32, y: f32, z: f32, depth: u32, dimension: Dimension) -> Self {
+        assert!(depth >= 1 && depth <= Self::max_depth(dimension));
+        let (x, y, z) = (x as f64, y as f64, z as f64);
+        let mut id = Self::root(dimension);
+        let mut origin_x = 0.0;
+        let mut origin_y = 0.0;
+        let mut origin_z = 0.0;
+        let mut length = WORLD_X_MAX as f64;
+        let mut height = WORLD_Y_MAX as f64;
+        let mut width = WORLD_Z_MAX as f64;
+        for _ in 1..depth {
+            let quadrant =
+                next_quadrant(x, y, &mut origin_x, &mut origin_y, &mut length, &mut height);
+            // 八叉树，z>=中点时与四叉树象限编号相同(1~4)，z<中点时+4(5~8)
+            let quadrant = match dimension {
+                Dimension::D2 => quadrant,
+                Dimension::D3 => {
+                    width /= 2.0;
+                    if z >= origin_z {
+                        origin_z += width;
+                        quadrant
+                    } else {
+                        origin_z -= width;
+                        quadrant + 4
+                    }
+                }
+            };
+            id = id.child(quadrant);
+        }
+        id
+    }
+
+    pub fn dimension(self) -> Dimension {
+        if self.0 & OCTREE_FLAG == 0 {
+            Dimension::D2
+        } else {
+            Dimension::D3
+        }
+    }
+
+    #[inline]
+    fn bits(self) -> u32 {
+        level_bits(self.dimension())
+    }
+
+    #[inline]
+    fn path(self) -> u64 {
+        self.0 & !OCTREE_FLAG
+    }
+
+    #[inline]
+    fn with_path(self, path: u64) -> Self {
+        Self(self.0 & OCTREE_FLAG | path)
+    }
+
+    pub fn depth(self) -> u32 {
+        (u64::BITS - 1 - self.path().leading_zeros()) / self.bits() + 1
+    }
+
+    pub fn is_root(self) -> bool {
+        self.path() == 1
+    }
+
+    pub fn parent(self) -> Option<Self> {
+        (!self.is_root()).then(|| self.with_path(self.path() >> self.bits()))
+    }
+
+    // 所在depth层的祖先节点，depth不小于自身深度时为自身
+    pub fn ancestor(self, depth: u32) -> Self {
+        let up = self.depth().saturating_sub(depth.max(1));
+        self.with_path(self.path() >> (up * self.bits()))
+    }
+
+    // quadrant为1~4，八叉树为1~8
+    pub fn child(self, quadrant: u64) -> Self {
+        let dimension = self.dimension();
+        debug_assert!((1..=dimension.children()).contains(&quadrant));
+        debug_assert!(self.depth() < Self::max_depth(dimension));
+        self.with_path(self.path() << self.bits() | (quadrant - 1))
+    }
+
+    pub fn children(self) -> impl Iterator<Item = Self> {
+        (1..=self.dimension().children()).map(move |quadrant| self.child(quadrant))
+    }
+
+    // 自身在父节点中的象限号，根节点为None
+    pub fn quadrant(self) -> Option<u64> {
+        (!self.is_root()).then(|| (self.path() & (self.dimension().children() - 1)) + 1)
+    }
+
+    // 从根节点往下每层的象限号
+    pub fn quadrants(self) -> impl Iterator<Item = u64> {
+        let bits = self.bits();
+        let mask = self.dimension().children() - 1;
+        let path = self.path();
+        (0..self.depth() - 1)
+            .rev()
+            .map(move |level| (path >> (level * bits) & mask) + 1)
+    }
+
+    /// zone的范围，四叉树zone的z为整个世界高度
+    pub fn bounds(self) -> AABB {
+        let mut xmin = WORLD_X_MIN as f64;
+        let mut ymin = WORLD_Y_MIN as f64;
+        let mut zmin = WORLD_Z_MIN as f64;
+        let mut xmax = WORLD_X_MAX as f64;
+        let mut ymax = WORLD_Y_MAX as f64;
+        let mut zmax = WORLD_Z_MAX as f64;
+        for quadrant in self.quadrants() {
+            // 八叉树5~8为z轴下半部分的1~4象限
+            let quadrant = match (self.dimension(), quadrant) {
+                (Dimension::D3, 5..=8) => {
+                    zmax = (zmin + zmax) / 2.0;
+                    quadrant - 4
+                }
+                (Dimension::D3, _) => {
+                    zmin = (zmin + zmax) / 2.0;
+                    quadrant
+                }
+                (Dimension::D2, _) => quadrant,
+            };
+            match quadrant {
+                1 => {
+                    xmin = (xmin + xmax) / 2.0;
+                    ymin = (ymin + ymax) / 2.0;
+                }
+                2 => {
+                    xmax = (xmin + xmax) / 2.0;
+                    ymin = (ymin + ymax) / 2.0;
+                }
+                3 => {
+                    xmax = (xmin + xmax) / 2.0;
+                    ymax = (ymin + ymax) / 2.0;
+                }
+                4 => {
+                    xmin = (xmin + xmax) / 2.0;
+                    ymax = (ymin + ymax) / 2.0;
+                }
+                _ => unreachable!(),
+            }
+        }
+        AABB {
+            xmin: xmin as f32,
+            ymin: ymin as f32,
+            xmax: xmax as f32,
+            ymax: ymax as f32,
+            zmin: zmin as f32,
+            zmax: zmax as f32,
+        }
+    }
+}
+
+impl From<ZoneId> for u64 {
+    fn from(id: ZoneId) -> Self {
+        id.0
+    }
+}
+
+// 与旧的十进制编号写法相同，例如`123`
+impl fmt::Display for ZoneId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "1")?;
+        self.quadrants()
+            .try_for_each(|quadrant| write!(f, "{quadrant}"))
+    }
+}
+
+impl fmt::Debug for ZoneId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.dimension() {
+            Dimension::D2 => write!(f, "ZoneId({self})"),
+            Dimension::D3 => write!(f, "ZoneId(3D:{self})"),
+        }
+    }
+}
+
+// 下降一层，返回所在象限并更新中心点
+fn next_quadrant(
+    x: f64,
+    y: f64,
+    origin_x: &mut f64,
+    origin_y: &mut f64,
+    length: &mut f64,
+    height: &mut f64,
+) -> u64 {
+    *length /= 2.0;
+    *height /= 2.0;
+    if y >= *origin_y {
+        *origin_y += *height;
+        if x >= *origin_x {
+            *origin_x += *length;
+            1 // 第1象限
+        } else {
+            *origin_x -= *length;
+            2
+        }
+    } else {
+        *origin_y -= *height;
+        if x < *origin_x {
+            *origin_x -= *length;
+            3
+        } else {
+            *origin_x += *length;
+            4
+        }
+    }
+}
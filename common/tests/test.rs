@@ -1,4 +1,5 @@
-use common::AABB;
+use common::launch::{parse_port_range, parse_ready_line, ready_line};
+use common::{xyz_to_grid, Dimension, ZoneId, AABB, GRID_LENGTH, WORLD_X_MAX, WORLD_X_MIN};
 
 #[test]
 fn test_aabb() {
@@ -7,6 +8,8 @@ fn test_aabb() {
         xmax: 100.0,
         ymin: -100.0,
         ymax: 0.0,
+        zmin: 0.0,
+        zmax: 0.0,
     };
 
     assert!(aabb1.contains(100.0, -100.0));
@@ -17,6 +20,8 @@ fn test_aabb() {
         xmax: 120.0,
         ymin: -50.0,
         ymax: 1.0,
+        zmin: 0.0,
+        zmax: 0.0,
     };
     assert_eq!(
         aabb1.get_intersection(&aabb2).unwrap(),
@@ -25,6 +30,8 @@ fn test_aabb() {
             xmax: 100.0,
             ymin: -50.0,
             ymax: 0.0,
+            zmin: 0.0,
+            zmax: 0.0,
         }
     );
 
@@ -33,6 +40,264 @@ fn test_aabb() {
         xmax: -5.0,
         ymin: 5.0,
         ymax: 100.0,
+        zmin: 0.0,
+        zmax: 0.0,
     };
     assert!(aabb1.get_intersection(&aabb3).is_none());
+
+    // 圆心在外，离最近的边/角的距离
+    assert!(aabb1.intersects_sphere(105.0, -50.0, 0.0, 5.0));
+    assert!(!aabb1.intersects_sphere(105.0, -50.0, 0.0, 4.9));
+    assert!(aabb1.intersects_sphere(103.0, 4.0, 0.0, 5.0));
+    assert!(!aabb1.intersects_sphere(104.0, 4.0, 0.0, 5.0));
+    assert!(aabb1.intersects_sphere(50.0, -50.0, 0.0, 0.0));
+    // z方向超出
+    assert!(!aabb1.intersects_sphere(50.0, -50.0, 6.0, 5.0));
+
+    // 十字形相交，双方的顶点都不在对方内部
+    let horizontal = AABB {
+        xmin: -100.0,
+        xmax: 100.0,
+        ymin: -10.0,
+        ymax: 10.0,
+        zmin: 0.0,
+        zmax: 0.0,
+    };
+    let vertical = AABB {
+        xmin: -10.0,
+        xmax: 10.0,
+        ymin: -100.0,
+        ymax: 100.0,
+        zmin: 0.0,
+        zmax: 0.0,
+    };
+    assert!(horizontal.has_intersection(&vertical));
+    assert_eq!(
+        horizontal.get_intersection(&vertical).unwrap(),
+        AABB {
+            xmin: -10.0,
+            xmax: 10.0,
+            ymin: -10.0,
+            ymax: 10.0,
+            zmin: 0.0,
+            zmax: 0.0,
+        }
+    );
+    // 不同z层
+    let upper = AABB {
+        zmin: 1.0,
+        zmax: 2.0,
+        ..vertical
+    };
+    assert!(!horizontal.has_intersection(&upper));
+
+    // 合并相邻的块：上下两块合成一列，右边的块y范围不同，不能合并
+    let area = |xmin, xmax, ymin, ymax| AABB {
+        xmin,
+        xmax,
+        ymin,
+        ymax,
+        zmin: 0.0,
+        zmax: 0.0,
+    };
+    let merged = AABB::merge_adjacent(vec![
+        area(-10.0, 0.0, 0.0, 10.0),
+        area(0.0, 10.0, -10.0, 0.0),
+        area(-10.0, 0.0, -10.0, 0.0),
+    ]);
+    assert_eq!(merged.len(), 2);
+    assert!(merged.contains(&area(-10.0, 0.0, -10.0, 10.0)));
+    assert!(merged.contains(&area(0.0, 10.0, -10.0, 0.0)));
+    // 四块拼成整块
+    let merged = AABB::merge_adjacent(vec![
+        area(0.0, 10.0, 0.0, 10.0),
+        area(-10.0, 0.0, -10.0, 0.0),
+        area(-10.0, 0.0, 0.0, 10.0),
+        area(0.0, 10.0, -10.0, 0.0),
+    ]);
+    assert_eq!(merged, [area(-10.0, 10.0, -10.0, 10.0)]);
+}
+
+#[test]
+fn test_world_map() {
+    use common::world_map::{Obstacle, WorldMap};
+
+    let map = WorldMap::parse(
+        "# wall
+        speed_limit 50
+        rect 10 -100 20 100
+        polygon -10 -10 -20 -10 -15 -20
+        ",
+    )
+    .unwrap();
+    assert_eq!(map.speed_limit, Some(50.0));
+    assert_eq!(map.obstacles.len(), 2);
+    assert!(matches!(map.obstacles[1], Obstacle::Polygon(ref p) if p.len() == 3));
+    assert!(WorldMap::parse("rect 1 2 3").is_err());
+    assert!(WorldMap::parse("circle 1 2 3").is_err());
+
+    assert!(map.is_blocked(15.0, 0.0));
+    assert!(map.is_blocked(-15.0, -12.0));
+    assert!(!map.is_blocked(-15.0, -9.0));
+
+    // 超速按方向截断
+    assert_eq!(map.clamp_move(0.0, 200.0, 0.0, -80.0), (0.0, 150.0));
+    // 停在墙前
+    let (x, y) = map.clamp_move(0.0, 0.0, 30.0, 0.0);
+    assert!(x < 10.0 && x > 9.9 && y == 0.0);
+    // 斜穿多边形
+    let (x, y) = map.clamp_move(-15.0, 0.0, 0.0, -20.0);
+    assert!(!map.is_blocked(x, y) && y > -10.0 && y < -9.9);
+    // 未碰到障碍物
+    assert_eq!(map.clamp_move(0.0, 0.0, 5.0, 5.0), (5.0, 5.0));
+}
+
+#[test]
+fn test_octree_zone() {
+    let octree = |decimal| ZoneId::from_decimal(decimal, Dimension::D3).unwrap();
+    // 八叉树z>=0为1~4，z<0为5~8
+    assert_eq!(Dimension::D3.zone_id(10.0, 10.0, 10.0, 2), octree(11));
+    assert_eq!(Dimension::D3.zone_id(10.0, 10.0, -10.0, 2), octree(15));
+    assert_eq!(Dimension::D3.zone_id(-10.0, -10.0, -10.0, 3), octree(171));
+    assert_eq!(
+        Dimension::D2.zone_id(-10.0, -10.0, -10.0, 3),
+        ZoneId::from_decimal(131, Dimension::D2).unwrap()
+    );
+
+    let aabb = octree(171).bounds();
+    assert!(aabb.contains_xyz(-10.0, -10.0, -10.0));
+    assert_eq!((aabb.zmin, aabb.zmax), (-500_000.0, 0.0));
+    assert_eq!((aabb.xmin, aabb.xmax), (-500_000.0, 0.0));
+    // 四叉树zone的z为整个世界高度
+    let aabb = ZoneId::from_decimal(131, Dimension::D2).unwrap().bounds();
+    assert!(aabb.contains_xyz(-10.0, -10.0, 999_999.0));
+
+    let aabb = AABB {
+        xmin: 0.0,
+        xmax: 100.0,
+        ymin: 0.0,
+        ymax: 100.0,
+        zmin: -50.0,
+        zmax: 150.0,
+    };
+    assert_eq!(aabb.grid_count(), 2 * 2 * 3);
+    assert_eq!(aabb.get_grids_in_aabb().len(), aabb.grid_count());
+
+    // 超出世界的范围截到世界边界，计数不溢出
+    let huge = AABB {
+        xmin: -1e30,
+        xmax: 1e30,
+        ymin: -1e30,
+        ymax: 1e30,
+        zmin: -1e30,
+        zmax: 1e30,
+    };
+    let per_axis = (WORLD_X_MAX - WORLD_X_MIN) as usize / GRID_LENGTH + 1;
+    assert_eq!(huge.grid_count(), per_axis * per_axis * per_axis);
+    assert_eq!(xyz_to_grid(f32::INFINITY, 0.0, 0.0).0, per_axis - 1);
+}
+
+#[test]
+fn test_zone_id() {
+    let root = ZoneId::root(Dimension::D2);
+    assert!(root.is_root());
+    assert_eq!(root.depth(), 1);
+    assert_eq!(root.parent(), None);
+    assert_eq!(root.quadrant(), None);
+
+    let id = ZoneId::from_decimal(1423, Dimension::D2).unwrap();
+    assert_eq!(id.depth(), 4);
+    assert_eq!(id.quadrant(), Some(3));
+    assert_eq!(id.quadrants().collect::<Vec<_>>(), [4, 2, 3]);
+    assert_eq!(id.to_string(), "1423");
+    assert_eq!(id.parent(), ZoneId::from_decimal(142, Dimension::D2));
+    assert_eq!(
+        id.ancestor(2),
+        ZoneId::from_decimal(14, Dimension::D2).unwrap()
+    );
+    assert_eq!(id.ancestor(10), id);
+    assert_eq!(root.child(4).child(2).child(3), id);
+    assert_eq!(id.children().count(), 4);
+    assert!(id.children().all(|child| child.parent() == Some(id)));
+    assert_eq!(
+        id.bounds(),
+        Dimension::D2
+            .zone_id(100_000.0, -400_000.0, 0.0, 4)
+            .bounds()
+    );
+
+    // 旧十进制编号中非法的象限号
+    assert_eq!(ZoneId::from_decimal(15, Dimension::D2), None);
+    assert_eq!(ZoneId::from_decimal(21, Dimension::D2), None);
+    assert!(ZoneId::from_decimal(18, Dimension::D3).is_some());
+
+    // proto编码
+    assert_eq!(ZoneId::from_raw(id.into()), Some(id));
+    let octree = ZoneId::root(Dimension::D3).child(8);
+    assert_eq!(ZoneId::from_raw(octree.into()), Some(octree));
+    assert_eq!(octree.dimension(), Dimension::D3);
+    assert_ne!(u64::from(octree), u64::from(root.child(4)));
+    assert_eq!(ZoneId::from_raw(0), None);
+    assert_eq!(ZoneId::from_raw(0b10), None); // 标记位不在层边界上
+
+    // 超过十进制编号上限的深度
+    assert_eq!(ZoneId::max_depth(Dimension::D2), 32);
+    assert_eq!(ZoneId::max_depth(Dimension::D3), 21);
+    let deep = Dimension::D2.zone_id(12.3, 45.6, 0.0, 32);
+    assert_eq!(deep.depth(), 32);
+    assert_eq!(ZoneId::from_raw(deep.into()), Some(deep));
+    assert_eq!(
+        deep.ancestor(10),
+        Dimension::D2.zone_id(12.3, 45.6, 0.0, 10)
+    );
+    let deep = deep.ancestor(25);
+    assert!(deep.bounds().contains(12.3, 45.6));
+    let deep = Dimension::D3.zone_id(12.3, 45.6, -7.8, 21);
+    assert_eq!(deep.depth(), 21);
+    assert!(deep.bounds().contains_xyz(12.3, 45.6, -7.8));
+
+    // 远离原点时f32精度不足以二分，每一层的范围仍包含坐标且不超出父节点
+    let (x, y, z) = (999_999.9, -999_999.9, 999_999.9);
+    for dimension in [Dimension::D2, Dimension::D3] {
+        for depth in 1..=ZoneId::max_depth(dimension) {
+            let id = dimension.zone_id(x, y, z, depth);
+            let bounds = id.bounds();
+            assert!(bounds.contains_xyz(x, y, z), "{id:?}");
+            if let Some(parent) = id.parent() {
+                let outer = parent.bounds();
+                assert!(
+                    outer.xmin <= bounds.xmin && bounds.xmax <= outer.xmax,
+                    "{id:?}"
+                );
+                assert!(
+                    outer.ymin <= bounds.ymin && bounds.ymax <= outer.ymax,
+                    "{id:?}"
+                );
+                assert!(
+                    outer.zmin <= bounds.zmin && bounds.zmax <= outer.zmax,
+                    "{id:?}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_launch() {
+    assert_eq!(parse_port_range("0").unwrap(), 0..=0);
+    assert_eq!(parse_port_range("5000").unwrap(), 5000..=5000);
+    assert_eq!(parse_port_range("5000-5100").unwrap(), 5000..=5100);
+    assert!(parse_port_range("5100-5000").is_err());
+    assert!(parse_port_range("").is_err());
+    assert!(parse_port_range("70000").is_err());
+
+    let line = ready_line(7, "127.0.0.1:43210");
+    assert_eq!(parse_ready_line(&line), Some((7, "127.0.0.1:43210")));
+    // 其他输出(日志)不是握手行
+    assert_eq!(
+        parse_ready_line("2023-01-01 INFO starting at 127.0.0.1:5000"),
+        None
+    );
+    assert_eq!(parse_ready_line("MAP_SERVER_READY x 127.0.0.1:5000"), None);
+    assert_eq!(parse_ready_line(&format!("{line} extra")), None);
 }
@@ -0,0 +1,202 @@
+use crate::cluster::FORWARDED_BY_HEADER;
+
+use common::PlayerId;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Player, // 只能操作绑定的player_id
+    Admin,  // 可操作任意玩家，可调用broadcast等运维接口
+}
+
+/// 会话token携带的信息，验证通过后放入request extensions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Claims {
+    pub player_id: PlayerId,
+    pub scope: Scope,
+    pub expire_at: u64, // unix秒，0为不过期
+}
+
+/// # token格式
+/// `{player_id}.{player|admin}.{expire_at}.{signature}`
+/// signature为前三段HMAC-SHA256的base64url（无padding）
+/// 本地配置密钥，签发与验证在同一处，不需要额外的认证服务
+#[derive(Clone)]
+pub struct TokenAuth {
+    key: Vec<u8>,
+}
+
+impl TokenAuth {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self { key: key.into() }
+    }
+
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    pub fn issue(&self, claims: &Claims) -> String {
+        let scope = match claims.scope {
+            Scope::Player => "player",
+            Scope::Admin => "admin",
+        };
+        let payload = format!("{}.{scope}.{}", claims.player_id, claims.expire_at);
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&payload).finalize().into_bytes());
+        format!("{payload}.{signature}")
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Claims, Status> {
+        let invalid = || Status::unauthenticated("invalid token");
+        let (payload, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+
+        let mut parts = payload.split('.');
+        let (Some(player_id), Some(scope), Some(expire_at), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let claims = Claims {
+            player_id: player_id.parse().map_err(|_| invalid())?,
+            scope: match scope {
+                "player" => Scope::Player,
+                "admin" => Scope::Admin,
+                _ => return Err(invalid()),
+            },
+            expire_at: expire_at.parse().map_err(|_| invalid())?,
+        };
+        if claims.expire_at != 0 && claims.expire_at <= now_secs() {
+            return Err(Status::unauthenticated("token expired"));
+        }
+        Ok(claims)
+    }
+}
+
+// metadata `authorization: Bearer <token>`
+impl Interceptor for TokenAuth {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("missing token"))?;
+        let claims = self.verify(token)?;
+        request.extensions_mut().insert(claims);
+        Ok(request)
+    }
+}
+
+/// 请求的player_id必须与token绑定的一致，admin不限
+/// 没有Claims说明未开启认证，直接放行
+pub fn authorize<T>(request: &Request<T>, player_id: PlayerId) -> Result<(), Status> {
+    match request.extensions().get::<Claims>() {
+        Some(claims) if claims.scope == Scope::Player && claims.player_id != player_id => Err(
+            Status::permission_denied(format!("token is not for player_id:{player_id}")),
+        ),
+        _ => Ok(()),
+    }
+}
+
+pub fn authorize_admin<T>(request: &Request<T>) -> Result<(), Status> {
+    match request.extensions().get::<Claims>() {
+        Some(claims) if claims.scope != Scope::Admin => {
+            Err(Status::permission_denied("admin scope required"))
+        }
+        _ => Ok(()),
+    }
+}
+
+// dispatcher之间的请求携带的集群密钥
+pub const CLUSTER_SECRET_HEADER: &str = "x-cluster-secret";
+
+/// # dispatcher之间的认证
+/// DispatcherService与转发标记只信任带正确集群密钥的请求，
+/// 对外端口上的客户端不知道密钥，不能推送拓扑、迁移玩家，也不能冒充转发绕过玩家归属。
+/// 密钥为空时不信任任何请求，只有一个dispatcher时不需要配置。
+#[derive(Clone)]
+pub struct ClusterAuth {
+    secret: Vec<u8>,
+}
+
+impl ClusterAuth {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+
+    pub fn verify(&self, metadata: &MetadataMap) -> bool {
+        let Some(value) = metadata.get(CLUSTER_SECRET_HEADER) else {
+            return false;
+        };
+        let value = value.as_bytes();
+        // 逐字节比较完再返回，耗时与密钥内容无关
+        !self.secret.is_empty()
+            && value.len() == self.secret.len()
+            && value
+                .iter()
+                .zip(&self.secret)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    /// 其他dispatcher转发来的请求：带转发标记且密钥正确
+    pub fn is_forwarded(&self, metadata: &MetadataMap) -> bool {
+        metadata.contains_key(FORWARDED_BY_HEADER) && self.verify(metadata)
+    }
+
+    pub fn sign(&self, metadata: &mut MetadataMap) {
+        if let Ok(value) = MetadataValue::try_from(self.secret.as_slice()) {
+            metadata.insert(CLUSTER_SECRET_HEADER, value);
+        }
+    }
+
+    // 发往其他dispatcher的DispatcherService客户端
+    pub fn signer(&self) -> ClusterSigner {
+        ClusterSigner(self.clone())
+    }
+}
+
+// DispatcherService服务端，密钥不对时拒绝
+impl Interceptor for ClusterAuth {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        if self.verify(request.metadata()) {
+            Ok(request)
+        } else {
+            Err(Status::unauthenticated("invalid cluster secret"))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ClusterSigner(ClusterAuth);
+
+impl Interceptor for ClusterSigner {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        self.0.sign(request.metadata_mut());
+        Ok(request)
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,20 @@
+use game_server::replay::{self, ReplayConfig};
+
+use tracing::*;
+
+// 参数为REPLAY_*；结果输出到stdout，日志输出到stderr。与基线有差异时退出码为1
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    let config = econf::load(ReplayConfig::default(), "REPLAY");
+    info!(config.addr, config.path, config.speed, "replay");
+
+    let report = replay::run(&config).await.unwrap();
+    print!("{report}");
+    if !report.diffs.is_empty() {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,19 @@
+use game_server::simulator::{self, SimConfig};
+use game_server::util::Config;
+
+use tracing::*;
+
+// 扩缩容参数同game-server(GAME_*)，模拟参数为SIM_*；结果输出到stdout，日志输出到stderr
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    let config = econf::load(Config::default(), "GAME");
+    let sim = econf::load(SimConfig::default(), "SIM");
+    info!("simulating {sim:?} with {config:?}");
+
+    let report = simulator::run(config, &sim).await.unwrap();
+    print!("{report}");
+}
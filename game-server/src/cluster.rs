@@ -0,0 +1,281 @@
+use crate::auth::{ClusterAuth, ClusterSigner};
+use crate::election::{LeaderElection, Leadership, Term};
+use crate::util::Config;
+
+use common::proto::dispatcher_service::dispatcher_service_client::DispatcherServiceClient;
+use common::proto::game_service::game_service_client::GameServiceClient;
+use common::{tls, ErrHandle, PlayerId, RPCResult};
+
+use anyhow::{ensure, Result};
+use tonic::metadata::MetadataValue;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::Channel;
+use tonic::{Extensions, Request};
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub type DispatcherId = u32;
+
+// 不选举时固定为coordinator的dispatcher
+pub const COORDINATOR_ID: DispatcherId = 0;
+
+// 还不知道谁是leader
+const NO_LEADER: DispatcherId = DispatcherId::MAX;
+
+// 转发的请求带上来源dispatcher与集群密钥，对端不再转发，避免两边配置不一致时来回转发
+pub const FORWARDED_BY_HEADER: &str = "x-forwarded-by";
+
+// 每个dispatcher在环上的虚拟节点数，越多分布越均匀
+const VIRTUAL_NODES: u64 = 128;
+
+/// 一致性哈希环，按player_id划分玩家所属的dispatcher
+/// 增减dispatcher时只有相邻区间的玩家换归属
+#[derive(Debug, Clone, Default)]
+pub struct HashRing {
+    nodes: BTreeMap<u64, DispatcherId>,
+}
+
+impl HashRing {
+    pub fn new(ids: impl IntoIterator<Item = DispatcherId>) -> Self {
+        // 虚拟节点的输入先混合一次，避免与较小的player_id直接重合
+        let nodes = ids
+            .into_iter()
+            .flat_map(|id| {
+                let seed = mix(id as u64);
+                (0..VIRTUAL_NODES).map(move |i| (mix(seed.wrapping_add(i)), id))
+            })
+            .collect();
+        Self { nodes }
+    }
+
+    // 顺时针方向第一个虚拟节点，环为空时为None
+    pub fn owner(&self, player_id: PlayerId) -> Option<DispatcherId> {
+        let hash = mix(player_id);
+        self.nodes
+            .range(hash..)
+            .next()
+            .or_else(|| self.nodes.iter().next())
+            .map(|(_, id)| *id)
+    }
+}
+
+// splitmix64，各进程结果一致，不能用std带随机种子的哈希
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+pub type DispatcherClient = DispatcherServiceClient<InterceptedService<Channel, ClusterSigner>>;
+
+// 其他dispatcher，首次请求时才连接，不要求启动顺序
+#[derive(Clone)]
+pub struct Peer {
+    pub id: DispatcherId,
+    pub addr: String,
+    pub game_cli: GameServiceClient<Channel>,
+    pub dispatcher_cli: DispatcherClient,
+    auth: ClusterAuth,
+}
+
+impl Peer {
+    /// 原样带上metadata(含token)转发，由对端做认证与限流；客户端伪造的集群密钥被覆盖
+    pub async fn forward<T, R, F, Fut>(
+        &self,
+        from: DispatcherId,
+        request: Request<T>,
+        call: F,
+    ) -> RPCResult<R>
+    where
+        F: FnOnce(GameServiceClient<Channel>, Request<T>) -> Fut,
+        Fut: Future<Output = RPCResult<R>>,
+    {
+        let (mut metadata, _, message) = request.into_parts();
+        metadata.insert(FORWARDED_BY_HEADER, MetadataValue::from(from));
+        self.auth.sign(&mut metadata);
+        let request = Request::from_parts(metadata, Extensions::default(), message);
+        call(self.game_cli.clone(), request).await
+    }
+}
+
+/// dispatcher集群，dispatcher_addrs按序号排列，为空时只有自身
+/// coordinator为选举出的leader：启动map-server、扩缩容，拓扑变化后推送给其他dispatcher
+pub struct Cluster {
+    pub id: DispatcherId,
+    ring: HashRing,
+    peers: HashMap<DispatcherId, Peer>, // 不含自身
+    pub auth: ClusterAuth,
+    election: Arc<dyn LeaderElection>,
+    leader: AtomicU32,                      // 最近一次竞选得知的leader
+    term: AtomicU64,                        // 该leader的任期
+    lease_deadline: Mutex<Option<Instant>>, // 自身为leader时，租约在本地的到期时间，None为不过期
+    led_term: AtomicU64,                    // 已接管拓扑的任期
+}
+
+impl Cluster {
+    pub fn new(config: &Config, election: Arc<dyn LeaderElection>) -> Result<Self> {
+        let addrs = config
+            .dispatcher_addrs
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+        let count = addrs.len().max(1) as DispatcherId;
+        ensure!(
+            config.dispatcher_id < count,
+            "dispatcher_id:{} out of {count} dispatchers",
+            config.dispatcher_id
+        );
+        ensure!(
+            count == 1 || !config.cluster_secret.is_empty(),
+            "cluster_secret is required with {count} dispatchers"
+        );
+        let auth = ClusterAuth::new(config.cluster_secret.as_str());
+        let mut peers = HashMap::new();
+        for (id, addr) in addrs.into_iter().enumerate() {
+            let id = id as DispatcherId;
+            if id == config.dispatcher_id {
+                continue;
+            }
+            // dispatcher之间不走TLS，与客户端访问dispatcher相同
+            let channel = tls::connect_lazy(addr.to_string(), None)?;
+            peers.insert(
+                id,
+                Peer {
+                    id,
+                    addr: addr.to_string(),
+                    game_cli: GameServiceClient::new(channel.clone()),
+                    dispatcher_cli: DispatcherServiceClient::with_interceptor(
+                        channel,
+                        auth.signer(),
+                    ),
+                    auth: auth.clone(),
+                },
+            );
+        }
+        Ok(Self {
+            id: config.dispatcher_id,
+            ring: HashRing::new(0..count),
+            peers,
+            auth,
+            election,
+            leader: AtomicU32::new(NO_LEADER),
+            term: AtomicU64::new(0),
+            lease_deadline: Mutex::new(None),
+            led_term: AtomicU64::new(0),
+        })
+    }
+
+    /// 自身为leader且租约在本地还没到期
+    pub fn is_coordinator(&self) -> bool {
+        self.leader.load(Ordering::Acquire) == self.id
+            && self
+                .lease_deadline
+                .lock()
+                .unwrap()
+                .map_or(true, |deadline| Instant::now() < deadline)
+    }
+
+    pub fn term(&self) -> Term {
+        self.term.load(Ordering::Acquire)
+    }
+
+    /// 竞选或续约，返回自身是否为leader。出错时视为不是leader，避免两个leader同时扩缩容
+    /// 租约从发起竞选时起算，不晚于其他dispatcher认定的过期时间
+    pub async fn campaign(&self, ttl: Duration) -> bool {
+        let started_at = Instant::now();
+        let Leadership { leader, term } = self
+            .election
+            .campaign(self.id, ttl)
+            .await
+            .log_err()
+            .ok()
+            .flatten()
+            .unwrap_or(Leadership {
+                leader: NO_LEADER,
+                term: self.term(),
+            });
+        let is_leader = leader == self.id;
+        *self.lease_deadline.lock().unwrap() =
+            (is_leader && self.election.expires()).then(|| started_at + ttl);
+        self.term.store(term, Ordering::Release);
+        self.leader.store(leader, Ordering::Release);
+        is_leader
+    }
+
+    /// 扩缩容中途续约，任期变了说明中间失去过leader，拓扑可能已被接管，不能继续
+    pub async fn renew(&self, ttl: Duration) -> bool {
+        let term = self.term();
+        self.is_coordinator() && self.campaign(ttl).await && self.term() == term
+    }
+
+    // 本任期还没有接管拓扑
+    pub fn needs_take_over(&self) -> bool {
+        self.is_coordinator() && self.led_term.load(Ordering::Acquire) != self.term()
+    }
+
+    pub fn took_over(&self) {
+        self.led_term.store(self.term(), Ordering::Release);
+    }
+
+    // 退出前主动让出，其他dispatcher不必等租约过期
+    pub async fn resign(&self) {
+        if self.is_coordinator() {
+            let _ = self.election.resign(self.id).await.log_err();
+        }
+        self.leader.store(NO_LEADER, Ordering::Release);
+        *self.lease_deadline.lock().unwrap() = None;
+    }
+
+    // 收到更新的leader推送的拓扑，说明租约已被接管
+    pub fn step_down(&self) {
+        let _ =
+            self.leader
+                .compare_exchange(self.id, NO_LEADER, Ordering::AcqRel, Ordering::Acquire);
+    }
+
+    pub fn owner(&self, player_id: PlayerId) -> DispatcherId {
+        self.ring.owner(player_id).unwrap_or(self.id)
+    }
+
+    pub fn is_local(&self, player_id: PlayerId) -> bool {
+        self.owner(player_id) == self.id
+    }
+
+    // 玩家归其他dispatcher时返回对方
+    pub fn remote_owner(&self, player_id: PlayerId) -> Option<&Peer> {
+        self.peers.get(&self.owner(player_id))
+    }
+
+    // 已被其他dispatcher转发过的请求在本地处理，客户端自己带上转发标记不算
+    pub fn forward_target<T>(&self, request: &Request<T>, player_id: PlayerId) -> Option<&Peer> {
+        if self.auth.is_forwarded(request.metadata()) {
+            None
+        } else {
+            self.remote_owner(player_id)
+        }
+    }
+
+    // 只在leader处理的请求，已被转发过的在本地处理
+    pub fn leader_target<T>(&self, request: &Request<T>) -> Option<&Peer> {
+        if self.auth.is_forwarded(request.metadata()) {
+            None
+        } else {
+            self.coordinator()
+        }
+    }
+
+    // 自身为coordinator或还没有leader时为None
+    pub fn coordinator(&self) -> Option<&Peer> {
+        self.peers.get(&self.leader.load(Ordering::Acquire))
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &Peer> {
+        self.peers.values()
+    }
+}
@@ -2,6 +2,7 @@ use common::proto::game_service::game_service_client::GameServiceClient;
 use common::proto::map_service::map_service_client::MapServiceClient;
 use common::*;
 
+use crossbeam_skiplist::SkipMap;
 use tonic::transport::Channel;
 
 use std::ops::Deref;
@@ -22,6 +23,8 @@ impl Deref for ServerInfo {
 #[derive(Clone)]
 pub struct ServerInfoInner {
     pub server_id: ServerId,
+    pub world_id: WorldId,    // 所属世界，扩缩容只在同一世界内进行
+    pub dimension: Dimension, // 2D四叉树或3D八叉树，与所属世界一致
     pub zones: Vec<ZoneId>,
     pub map_cli: MapServiceClient<Channel>,
     pub game_cli: GameServiceClient<Channel>,
@@ -38,6 +41,22 @@ impl ServerInfo {
     pub fn contains_zone(&self, zone_id: ZoneId) -> bool {
         self.zones.iter().any(|id| &zone_id == id)
     }
+
+    // server负责的范围，有多个zone时取父节点zone
+    pub fn get_zone_aabb(&self) -> AABB {
+        let zone_id = if self.zones.len() == 1 {
+            self.zones[0]
+        } else {
+            self.zones[0].parent().unwrap()
+        };
+        zone_id.bounds()
+    }
+}
+
+/// 一个世界：2D为四叉树，3D为八叉树
+pub struct World {
+    pub dimension: Dimension,
+    pub zone_server_map: SkipMap<ZoneId, ZoneServers>, // 通过Zone定位server
 }
 
 // 一个叶子节点除了有自身服务器，可能还有一台正在给起导入用户的服务器。未指定用户的请求要两个都发送，e.g. aoe/query
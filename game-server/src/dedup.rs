@@ -0,0 +1,35 @@
+use common::proto::game_service::Coord;
+
+use std::collections::VecDeque;
+
+/// 变更类请求的执行结果，重试时原样返回
+#[derive(Debug, Clone, PartialEq)]
+pub enum DedupReply {
+    Moving(Coord),
+    Aoe,
+}
+
+/// 单个玩家的去重窗口，按到达顺序保存最近capacity个request_id的结果，超出后淘汰最早的
+#[derive(Debug, Clone, Default)]
+pub struct DedupWindow {
+    entries: VecDeque<(u64, DedupReply)>,
+}
+
+impl DedupWindow {
+    pub fn get(&self, request_id: u64) -> Option<&DedupReply> {
+        self.entries
+            .iter()
+            .find(|(id, _)| *id == request_id)
+            .map(|(_, reply)| reply)
+    }
+
+    pub fn push(&mut self, request_id: u64, reply: DedupReply, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        while self.entries.len() >= capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((request_id, reply));
+    }
+}
@@ -1,33 +1,67 @@
+use crate::cluster::Cluster;
 use crate::data::*;
+use crate::dedup::{DedupReply, DedupWindow};
+use crate::election::{self, LeaderElection};
+use crate::rate_limit::{RateLimiter, RpcKind};
+use crate::recorder::Recorder;
+use crate::scaling_log::{self, ScalingLog};
 use crate::server_scaling::ServerScaling;
 use crate::util::*;
 
+use common::proto::game_service::{PlayerIdRequest, PlayerInfo, ScalingEventKind};
+use common::proto::map_service::{ExportRequest, OverheadReply, WatchLeavingRequest};
+use common::world_map::WorldMap;
 use common::*;
 
-use anyhow::{Context, Result};
-use crossbeam_skiplist::SkipMap;
+use anyhow::{ensure, Context, Result};
+use crossbeam_skiplist::{SkipMap, SkipSet};
+use tokio::task::JoinHandle;
+use tonic::Status;
 use tracing::*;
 
 use std::collections::HashMap;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 /// # 地图分割方法
 /// 将地图分割为4象限，每个象限递归向下划分4象限。可以得到一个类似四叉树的结构。
-/// ZoneId表示四叉树节点编号，从高位到低位表示根节点到叶子结点。
+/// ZoneId表示四叉树节点编号，从高位到低位表示根节点到叶子结点，按位编码见[`ZoneId`]。
 /// 例如：`123`:`1`代表根节点世界地图，划分四象限中第`2`象限中再划分四象限中的`3`象限
-/// 树高1~MAX_ZONE_DEPTH层，ZoneId位数与节点所处高度相等。
+/// 树高1~max_zone_depth层，四叉树最深32层，八叉树21层。
+/// 每个世界(副本、分线)各有一棵树及自己的根服务器，一台服务器只属于一个世界。
+/// 3D世界同理划分为8卦限，得到八叉树，z轴上半部分为`1~4`，下半部分为`5~8`。
+
+/// # 多个dispatcher
+/// 玩家按player_id一致性哈希分到各dispatcher，player_map等玩家数据只在所属dispatcher，其他dispatcher收到请求时转发。
+/// 拓扑(worlds)只由coordinator修改，变化后推送给其他dispatcher，其他dispatcher只读。
+/// query/broadcast不指定玩家，按本地拓扑直接发给map-server。
 
 /// # 并发读写保证：
-/// ## zone_server_map
-/// * 只有一个线程在增删server；
+/// ## worlds
+/// * 世界在启动时创建，之后不增删
+/// * coordinator只有一个线程在增删server；其他dispatcher只在应用拓扑时增删，先增后删
 /// * 删除前通过转移到exporting_server来拒绝新增用户；
 /// * 删除时用户已清零，没有并发访问了
 /// ## player_map
 /// * API以用户为单位串行
+/// ## dedup_map/last_active_map/npc_set
+/// * 与player_map相同，以用户为单位串行
+/// * 保存在dispatcher而不是map-server，用户在map-server间导出导入时去重记录不受影响
 pub struct DispatcherInner {
-    pub zone_server_map: SkipMap<ZoneId, ZoneServers>, // 通过Zone定位server
-    pub player_map: SkipMap<PlayerId, (ServerInfo, f32, f32)>, // 定位Player所属server,x,y
+    pub worlds: HashMap<WorldId, World>,
+    pub player_map: SkipMap<PlayerId, (ServerInfo, f32, f32, f32)>, // 定位Player所属server,x,y,z
+    pub dedup_map: SkipMap<PlayerId, DedupWindow>, // 玩家最近变更请求的结果，重试去重
+    pub last_active_map: SkipMap<PlayerId, Instant>, // 玩家最后一次请求的时间，空闲超时登出
+    pub npc_set: SkipSet<PlayerId>,                // player_map中的NPC，不参与登录/登出与空闲超时
+    pub world_map: WorldMap, // 与map-server加载同一份，moving时先截断再定位目标server
+    pub rate_limiter: RateLimiter,
+    pub cluster: Cluster,
+    pub topology_version: Mutex<u64>, // coordinator每次变化+1，其他dispatcher为已应用的版本
+    pub topology_term: AtomicU64,     // 发布当前拓扑的leader任期，持有topology_version的锁时修改
+    pub scaling_log: ScalingLog,      // 本dispatcher作为leader时的扩缩容事件
+    pub recorder: Recorder,           // 录制收到的请求，供replay重放
     pub config: Config,
 }
 
@@ -45,52 +79,341 @@ impl Deref for Dispatcher {
 
 impl Dispatcher {
     pub async fn new(config: Config) -> Result<Self> {
+        let election = election::from_config(&config);
+        Self::with_election(config, election).await
+    }
+
+    /// 启动时竞选一次，成为leader则接管拓扑，没有现成的拓扑时为各世界启动根服务器
+    pub async fn with_election(config: Config, election: Arc<dyn LeaderElection>) -> Result<Self> {
         // TODO: 将zone等配置传递给server
-        let server = start_map_server(vec![ROOT_ZONE_ID]).await?;
-        let zone_server_map = SkipMap::new();
-        zone_server_map.insert(
-            ROOT_ZONE_ID,
-            ZoneServers {
-                server,
-                exporting_server: None,
-            },
-        );
+        let world_map = load_world_map(&config.world_map_path)?;
+        ensure!(config.world_count > 0, "world_count must be positive");
+        launch::parse_port_range(&config.map_port)?;
+        let octree_worlds = parse_world_ids(&config.octree_worlds)?;
+        let cluster = Cluster::new(&config, election)?;
+        let mut worlds = HashMap::new();
+        for world_id in 0..config.world_count {
+            let dimension = if octree_worlds.contains(&world_id) {
+                Dimension::D3
+            } else {
+                Dimension::D2
+            };
+            ensure!(
+                config.max_zone_depth <= ZoneId::max_depth(dimension),
+                "max_zone_depth must not exceed {} in world:{world_id}",
+                ZoneId::max_depth(dimension)
+            );
+            worlds.insert(
+                world_id,
+                World {
+                    dimension,
+                    zone_server_map: SkipMap::new(),
+                },
+            );
+        }
 
-        Ok(Self {
+        let dispatcher = Self {
             inner: DispatcherInner {
-                zone_server_map,
+                worlds,
                 player_map: SkipMap::new(),
+                dedup_map: SkipMap::new(),
+                last_active_map: SkipMap::new(),
+                npc_set: SkipSet::new(),
+                world_map,
+                rate_limiter: RateLimiter::new(&config),
+                scaling_log: ScalingLog::new(&config)?,
+                recorder: Recorder::new(&config)?,
+                topology_version: Mutex::new(0), // 0表示还没有拓扑
+                topology_term: AtomicU64::new(0),
+                cluster,
                 config,
             }
             .into(),
-        })
+        };
+        dispatcher.lead().await;
+        Ok(dispatcher)
+    }
+
+    /// 竞选或续约leader，每个新任期接管一次拓扑，接管失败则让出
+    pub async fn lead(&self) -> bool {
+        let was_leader = self.cluster.is_coordinator();
+        let is_leader = self
+            .cluster
+            .campaign(Duration::from_millis(self.config.leader_lease_ttl))
+            .await;
+        if self.cluster.needs_take_over() {
+            info!(
+                id = self.cluster.id,
+                term = self.cluster.term(),
+                "became leader"
+            );
+            if self.take_over().await.log_err().is_err() {
+                self.cluster.resign().await;
+                return false;
+            }
+            self.cluster.took_over();
+        } else if was_leader && !is_leader {
+            warn!(id = self.cluster.id, "lost leadership");
+        }
+        is_leader
+    }
+
+    /// 扩缩容的每一步之前续约，已不是本任期的leader则中止，由新leader完成中断的导出
+    pub async fn ensure_leader(&self) -> Result<()> {
+        ensure!(
+            self.cluster
+                .renew(Duration::from_millis(self.config.leader_lease_ttl))
+                .await,
+            "lost leadership of term:{}",
+            self.cluster.term()
+        );
+        Ok(())
+    }
+
+    /// 刚成为leader时：
+    /// 1. 从其他dispatcher取版本最新的拓扑，都没有时为各世界启动根服务器
+    /// 2. server_id从现有server之后分配，避免与上任leader启动的冲突
+    /// 3. 完成上任leader中断的导出，推送拓扑
+    async fn take_over(&self) -> Result<()> {
+        self.pull_latest_topology().await;
+        if *self.topology_version.lock().unwrap() == 0 {
+            for (&world_id, world) in &self.worlds {
+                let root = ZoneId::root(world.dimension);
+                let server =
+                    start_map_server(world_id, world.dimension, vec![root], &self.config).await?;
+                world.zone_server_map.insert(
+                    root,
+                    ZoneServers {
+                        server,
+                        exporting_server: None,
+                    },
+                );
+            }
+        } else {
+            for server in self.get_all_servers() {
+                reserve_server_id(server.server_id + 1);
+            }
+            self.finish_exports().await?;
+        }
+        self.publish_topology().await;
+        Ok(())
+    }
+
+    pub fn world(&self, world_id: WorldId) -> Result<&World, Status> {
+        self.worlds
+            .get(&world_id)
+            .ok_or_else(|| Status::not_found(format!("world_id:{world_id} not found")))
+    }
+
+    pub fn zone_server_map(
+        &self,
+        world_id: WorldId,
+    ) -> Result<&SkipMap<ZoneId, ZoneServers>, Status> {
+        self.world(world_id).map(|world| &world.zone_server_map)
     }
 
-    // 逐层向下，找到为止
-    pub fn get_server_of_coord(&self, x: f32, y: f32) -> (ZoneId, ZoneServers) {
+    pub fn dimension(&self, world_id: WorldId) -> Result<Dimension, Status> {
+        self.world(world_id).map(|world| world.dimension)
+    }
+
+    // 取最深一层的zone，从根节点逐层向下找它的祖先，找到为止
+    pub fn get_server_of_coord(
+        &self,
+        world_id: WorldId,
+        x: f32,
+        y: f32,
+        z: f32,
+    ) -> Result<(ZoneId, ZoneServers), Status> {
+        let World {
+            dimension,
+            zone_server_map,
+        } = self.world(world_id)?;
+        let leaf = dimension.zone_id(x, y, z, self.config.max_zone_depth);
         for depth in 1..=self.config.max_zone_depth {
-            let zone_id = xy_to_zone_id(x, y, depth);
-            if let Some(server) = self
-                .zone_server_map
+            let zone_id = leaf.ancestor(depth);
+            if let Some(server) = zone_server_map
                 .get(&zone_id)
                 .map(|entry| entry.value().clone())
             {
-                return (zone_id, server);
+                return Ok((zone_id, server));
             }
         }
-        panic!("Root zone server not found");
+        // 还没从coordinator同步到拓扑
+        Err(Status::unavailable("root zone server not found"))
+    }
+
+    /// 从根节点向下遍历四叉树(3D为八叉树)，对与范围相交的每个叶子节点调用visit
+    /// 不相交的子树整个跳过
+    fn visit_leaves(
+        &self,
+        world_id: WorldId,
+        intersects: impl Fn(&AABB) -> bool,
+        mut visit: impl FnMut(ZoneId, AABB, ZoneServers),
+    ) -> Result<(), Status> {
+        let World {
+            dimension,
+            zone_server_map,
+        } = self.world(world_id)?;
+        let mut stack = vec![ZoneId::root(*dimension)];
+        while let Some(zone_id) = stack.pop() {
+            let bounds = zone_id.bounds();
+            if !intersects(&bounds) {
+                continue;
+            }
+            if let Some(entry) = zone_server_map.get(&zone_id) {
+                visit(zone_id, bounds, entry.value().clone());
+            } else if zone_id.depth() < self.config.max_zone_depth {
+                stack.extend(zone_id.children());
+            }
+        }
+        Ok(())
+    }
+
+    /// 与圆(球)相交的所有叶子节点的servers（含正在导出的），用于aoe/say
+    pub fn get_servers_in_circle(
+        &self,
+        world_id: WorldId,
+        x: f32,
+        y: f32,
+        z: f32,
+        radius: f32,
+    ) -> Result<Vec<ServerInfo>, Status> {
+        let mut servers = HashMap::new();
+        self.visit_leaves(
+            world_id,
+            |bounds| bounds.intersects_sphere(x, y, z, radius),
+            |_, _, zone_servers| {
+                for server in zone_servers.into_vec() {
+                    servers.insert(server.server_id, server);
+                }
+            },
+        )?;
+        Ok(servers.into_values().collect())
+    }
+
+    /// 与aabb相交的叶子节点逐个取交集，按server(含正在导出的)汇总后合并相邻的块，用于query
+    pub fn get_query_areas(
+        &self,
+        world_id: WorldId,
+        aabb: &AABB,
+    ) -> Result<Vec<(ServerInfo, Vec<AABB>)>, Status> {
+        let mut areas: HashMap<ServerId, (ServerInfo, Vec<AABB>)> = HashMap::new();
+        self.visit_leaves(
+            world_id,
+            |bounds| bounds.has_intersection(aabb),
+            |_, bounds, zone_servers| {
+                let Some(area) = bounds.get_intersection(aabb) else {
+                    return;
+                };
+                for server in zone_servers.into_vec() {
+                    areas
+                        .entry(server.server_id)
+                        .or_insert_with(|| (server, vec![]))
+                        .1
+                        .push(area.clone());
+                }
+            },
+        )?;
+        Ok(areas
+            .into_values()
+            .map(|(server, areas)| (server, AABB::merge_adjacent(areas)))
+            .collect())
     }
 
-    pub fn get_server_of_player(&self, player_id: &PlayerId) -> Result<(ServerInfo, f32, f32)> {
+    pub fn get_server_of_player(
+        &self,
+        player_id: &PlayerId,
+    ) -> Result<(ServerInfo, f32, f32, f32)> {
         self.player_map
             .get(player_id)
             .map(|entry| entry.value().clone())
             .context("player no in cache")
     }
 
+    // request_id为0表示客户端不需要去重
+    pub fn get_dedup_reply(&self, player_id: &PlayerId, request_id: u64) -> Option<DedupReply> {
+        if request_id == 0 {
+            return None;
+        }
+        self.dedup_map
+            .get(player_id)
+            .and_then(|entry| entry.value().get(request_id).cloned())
+    }
+
+    pub fn record_dedup_reply(&self, player_id: PlayerId, request_id: u64, reply: DedupReply) {
+        if request_id == 0 {
+            return;
+        }
+        let mut window = self
+            .dedup_map
+            .get(&player_id)
+            .map(|entry| entry.value().clone())
+            .unwrap_or_default();
+        window.push(request_id, reply, self.config.dedup_window);
+        self.dedup_map.insert(player_id, window);
+    }
+
+    // 只记录已登录的玩家，调用方需已在该玩家的串行队列中，与idle_reaper的确认与登出互斥
+    pub fn touch(&self, player_id: PlayerId) {
+        if self.player_map.contains_key(&player_id) && !self.npc_set.contains(&player_id) {
+            self.last_active_map.insert(player_id, Instant::now());
+        }
+    }
+
+    // 不以玩家串行的请求(heartbeat/say等)排队更新活跃时间
+    pub async fn touch_serialized(&self, player_id: PlayerId) {
+        use ert::prelude::RunVia;
+
+        let dsp = self.clone();
+        async move { dsp.touch(player_id) }.via_g(player_id).await
+    }
+
+    // 只为已登录的玩家建限流桶，其他的只检查全局桶，避免任意player_id撑大桶表
+    pub fn check_rate(&self, kind: RpcKind, player_id: Option<PlayerId>) -> Result<(), Status> {
+        let player_id = player_id.filter(|id| self.player_map.contains_key(id));
+        self.rate_limiter.check(kind, player_id)
+    }
+
+    // logout与空闲超时共用
+    pub async fn logout_player(&self, player_id: PlayerId) -> Result<(), Status> {
+        use ert::prelude::RunVia;
+
+        let dsp = self.clone();
+        // ert: serialized by player_id
+        async move { dsp.end_session(player_id).await }
+            .via_g(player_id)
+            .await
+    }
+
+    // 登出并清理限流状态，调用方需已在该玩家的串行队列中
+    async fn end_session(&self, player_id: PlayerId) -> Result<(), Status> {
+        self.clear_session(player_id).await?;
+        self.rate_limiter.remove_player(player_id);
+        Ok(())
+    }
+
+    // 从map-server与各缓存中移除玩家，调用方需已在该玩家的串行队列中
+    // 重复登录踢掉旧会话时也用，保留限流状态，避免靠重新登录绕过限流
+    pub async fn clear_session(&self, player_id: PlayerId) -> Result<(), Status> {
+        let (server, ..) = self.get_server_of_player(&player_id).map_err_unknown()?;
+        server
+            .game_cli
+            .clone()
+            .logout(PlayerIdRequest {
+                player_id,
+                world_id: server.world_id,
+            })
+            .await?;
+        self.player_map.remove(&player_id);
+        self.last_active_map.remove(&player_id);
+        self.dedup_map.remove(&player_id);
+        Ok(())
+    }
+
     pub fn get_all_servers(&self) -> Vec<ServerInfo> {
-        self.zone_server_map
-            .iter()
+        self.worlds
+            .values()
+            .flat_map(|world| world.zone_server_map.iter())
             .flat_map(|entry| entry.value().clone().into_vec())
             .map(|server| (server.server_id, server))
             .collect::<HashMap<_, _>>()
@@ -98,7 +421,22 @@ impl Dispatcher {
             .collect()
     }
 
+    pub fn get_world_servers(&self, world_id: WorldId) -> Result<Vec<ServerInfo>, Status> {
+        Ok(self
+            .zone_server_map(world_id)?
+            .iter()
+            .flat_map(|entry| entry.value().clone().into_vec())
+            .map(|server| (server.server_id, server))
+            .collect::<HashMap<_, _>>()
+            .into_values()
+            .collect())
+    }
+
+    // map-server由coordinator启动，也只由它关闭
     pub async fn shutdown_all_map_server(&self) {
+        if !self.cluster.is_coordinator() {
+            return;
+        }
         info!("shutdown_all_map_server");
         for server in self.get_all_servers() {
             if let Err(e) = shutdown_map_server(&server).await {
@@ -107,44 +445,257 @@ impl Dispatcher {
         }
     }
 
+    /// 每个dispatcher都运行，只有leader扩缩容，其他dispatcher在leader下线后接管
     #[instrument(skip_all)]
     pub async fn scaling_moniter(self) {
+        use tokio::time::sleep;
+
+        loop {
+            if self.lead().await {
+                self.scaling_round().await;
+            }
+            sleep(Duration::from_millis(self.config.scaling_interval)).await;
+        }
+    }
+
+    /// 一轮扩缩容：取各server负载，逐台扩容或缩容
+    /// scaling_moniter按scaling_interval定期调用，模拟器按模拟时间调用
+    pub async fn scaling_round(&self) {
+        info!(
+            "checking, totally {} players, {} npcs, rate limited {:?}, {} buckets, {:?}",
+            self.player_map.len().saturating_sub(self.npc_set.len()),
+            self.npc_set.len(),
+            self.rate_limiter.rejected_counts(),
+            self.rate_limiter.player_bucket_count(),
+            self.scaling_log.totals()
+        );
+        let servers = self.get_all_servers();
+        let mut overhead_map = HashMap::with_capacity(servers.len());
+        for server in &servers {
+            let _ = server
+                .map_cli
+                .clone()
+                .get_overhead(())
+                .await
+                .map(|res| {
+                    let OverheadReply { count, npc_count } = res.into_inner();
+                    let overhead = if self.config.npc_as_load {
+                        count + npc_count
+                    } else {
+                        count
+                    };
+                    overhead_map.insert(server.server_id, overhead)
+                })
+                .log_err();
+        }
+        for (server_id, &overhead) in &overhead_map {
+            // 扩缩容可能耗时较长，每台server之前续约，已不是leader则停止
+            if !self.lead().await {
+                break;
+            }
+            // 本轮前面的扩缩容可能已合并或关闭该server，按最新的拓扑处理
+            let Some(server) = self.find_server(*server_id) else {
+                continue;
+            };
+            info!(?server_id, ?overhead, ?server.world_id, ?server.zones);
+            if overhead >= self.config.max_players {
+                if let Err(e) = self.expand_overload_server(&server).await.log_err() {
+                    self.record_failure(&server, None, "expand", e);
+                }
+            }
+            if overhead <= self.config.min_players {
+                if let Ok(Some(export_to)) = self
+                    .get_merge_target_server(&server, overhead, &overhead_map)
+                    .log_err()
+                {
+                    if let Err(e) = self.close_idle_server(&server, &export_to).await.log_err() {
+                        self.record_failure(&server, Some(&export_to), "merge", e);
+                    }
+                }
+            }
+        }
+    }
+
+    // 扩缩容中途出错，target为缩容的导入方
+    fn record_failure(
+        &self,
+        server: &ServerInfo,
+        target: Option<&ServerInfo>,
+        action: &str,
+        e: anyhow::Error,
+    ) {
+        let mut event = scaling_log::event(ScalingEventKind::ScalingFailed, server);
+        event
+            .server_ids
+            .extend(target.map(|target| target.server_id));
+        event.message = format!("{action}: {e:#}");
+        self.scaling_log.record(event);
+    }
+
+    /// 定期登出空闲超时的玩家，扫描间隔为超时时间的一半
+    /// 确认与登出在玩家串行队列里一次完成，排在之前的请求已更新活跃时间，不会被登出
+    #[instrument(skip_all)]
+    pub async fn idle_reaper(self) {
+        use ert::prelude::RunVia;
         use tokio::time::{sleep, Duration};
 
+        let timeout = Duration::from_millis(self.config.idle_timeout);
         loop {
-            info!("checking, totally {} players", self.player_map.len());
-            let server_map = self
-                .get_all_servers()
-                .into_iter()
-                .map(|s| (s.server_id, s))
-                .collect::<HashMap<_, _>>();
-            let mut overhead_map = HashMap::with_capacity(server_map.len());
-            for server in server_map.values() {
-                let _ = server
+            sleep(timeout / 2).await;
+            let idle_players = self
+                .last_active_map
+                .iter()
+                .filter(|entry| entry.value().elapsed() >= timeout)
+                .map(|entry| *entry.key())
+                .collect::<Vec<_>>();
+            for player_id in idle_players {
+                let dsp = self.clone();
+                let _ = async move {
+                    let still_idle = dsp
+                        .last_active_map
+                        .get(&player_id)
+                        .map_or(false, |entry| entry.value().elapsed() >= timeout);
+                    if !still_idle {
+                        return Ok(());
+                    }
+                    info!(?player_id, "idle timeout, logout");
+                    dsp.end_session(player_id).await
+                }
+                .via_g(player_id)
+                .await
+                .log_err();
+            }
+        }
+    }
+
+    /// map-server开启tick时玩家会自动移动，只处理归本dispatcher的：
+    /// 1. 订阅各server，tick把玩家移出server的zone时立即导出到目标server
+    /// 2. 定期拉取移动中的玩家，同步player_map中的坐标；订阅的zone已过时、推送被丢弃而漏掉的跨出也在这里导出
+    #[instrument(skip_all)]
+    pub async fn simulation_moniter(self) {
+        use ert::prelude::RunVia;
+        use futures::StreamExt;
+        use tokio::time::{sleep, Duration};
+
+        let mut watches = HashMap::new();
+        loop {
+            self.watch_leaving(&mut watches);
+            for server in self.get_all_servers() {
+                let Ok(mut infos) = server
                     .map_cli
                     .clone()
-                    .get_overhead(())
+                    .get_moving_players(())
                     .await
-                    .map(|res| overhead_map.insert(server.server_id, res.into_inner().count))
-                    .log_err();
+                    .map(|res| res.into_inner().infos)
+                    .log_err() else {
+                    continue;
+                };
+                infos.retain(|player| self.cluster.is_local(player.player_id));
+                debug!(?server.server_id, "{} moving players", infos.len());
+                futures::stream::iter(infos)
+                    .for_each_concurrent(None, |player| {
+                        let dsp = self.clone();
+                        let server = server.clone();
+                        async move {
+                            let player_id = player.player_id;
+                            let _ = sync_simulated_player(dsp, server, player)
+                                .via_g(player_id)
+                                .await
+                                .log_err();
+                        }
+                    })
+                    .await;
             }
-            for (server_id, &overhead) in &overhead_map {
-                let server = server_map.get(server_id).unwrap();
-                info!(?server_id, ?overhead, ?server.zones);
-                if overhead >= self.config.max_players {
-                    let _ = self.expand_overload_server(server).await.log_err();
-                }
-                if overhead <= self.config.min_players {
-                    if let Ok(Some(export_to)) = self
-                        .get_merge_target_server(server, overhead, &overhead_map)
-                        .log_err()
-                    {
-                        let _ = self.close_idle_server(server, &export_to).await.log_err();
-                    }
+
+            sleep(Duration::from_millis(self.config.simulation_sync_interval)).await;
+        }
+    }
+
+    // 每个server订阅一次，zone变化或订阅断开后重新订阅，已关闭的server取消订阅
+    fn watch_leaving(&self, watches: &mut HashMap<ServerId, (Vec<ZoneId>, JoinHandle<()>)>) {
+        let servers = self.get_all_servers();
+        watches.retain(|server_id, (_, task)| {
+            let alive = servers.iter().any(|server| &server.server_id == server_id);
+            if !alive {
+                task.abort();
+            }
+            alive
+        });
+        for server in servers {
+            if let Some((zones, task)) = watches.get(&server.server_id) {
+                if zones == &server.zones && !task.is_finished() {
+                    continue;
                 }
+                task.abort();
             }
+            let (server_id, zones) = (server.server_id, server.zones.clone());
+            let task = tokio::spawn(self.clone().handle_leaving(server));
+            watches.insert(server_id, (zones, task));
+        }
+    }
 
-            sleep(Duration::from_millis(self.config.scaling_interval)).await;
+    // 每个玩家在单独的任务中导出，重新订阅时abort本任务不会中断进行中的导出
+    async fn handle_leaving(self, server: ServerInfo) {
+        use ert::prelude::RunVia;
+
+        let request = WatchLeavingRequest {
+            zone_ids: server.zones.iter().map(|&zone_id| zone_id.into()).collect(),
+        };
+        let Ok(mut stream) = server
+            .map_cli
+            .clone()
+            .watch_leaving(request)
+            .await
+            .map(|res| res.into_inner())
+            .log_err() else {
+            return;
+        };
+        while let Ok(Some(player)) = stream.message().await {
+            if !self.cluster.is_local(player.player_id) {
+                continue;
+            }
+            let dsp = self.clone();
+            let server = server.clone();
+            tokio::spawn(async move {
+                let player_id = player.player_id;
+                let _ = sync_simulated_player(dsp, server, player)
+                    .via_g(player_id)
+                    .await
+                    .log_err();
+            });
         }
     }
 }
+
+// ert: 以用户为单位与game api串行
+async fn sync_simulated_player(
+    dsp: Dispatcher,
+    server: ServerInfo,
+    player: PlayerInfo,
+) -> Result<()> {
+    let player_id = player.player_id;
+    let (current_server, ..) = dsp.get_server_of_player(&player_id)?;
+    if current_server != server {
+        // 拉取之后已被转移
+        return Ok(());
+    }
+    let target_server = dsp
+        .get_server_of_coord(current_server.world_id, player.x, player.y, player.z)?
+        .1
+        .server;
+    if target_server != current_server {
+        current_server
+            .map_cli
+            .clone()
+            .export_player(ExportRequest {
+                player_id,
+                addr: target_server.addr.clone(),
+                coord: None,
+                world: None,
+            })
+            .await?;
+    }
+    dsp.player_map
+        .insert(player_id, (target_server, player.x, player.y, player.z));
+    Ok(())
+}
@@ -0,0 +1,303 @@
+use crate::data::*;
+use crate::dispatcher::Dispatcher;
+use crate::util::*;
+
+use common::proto::dispatcher_service::dispatcher_service_server::{
+    DispatcherService, DispatcherServiceServer,
+};
+use common::proto::dispatcher_service::*;
+use common::proto::game_service::game_service_client::GameServiceClient;
+use common::proto::map_service::map_service_client::MapServiceClient;
+use common::proto::map_service::ExportRequest;
+use common::{tls, ErrHandle, RPCResult, ServerId, WorldId, ZoneId};
+
+use ert::prelude::RunVia;
+use tonic::service::interceptor::InterceptedService;
+use tonic::{async_trait, Request, Response, Status};
+use tracing::*;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+
+#[async_trait]
+impl DispatcherService for Dispatcher {
+    /// 自身为leader时只接受更高任期的，说明租约已被其他dispatcher接管
+    #[instrument(skip_all)]
+    async fn sync_topology(&self, request: Request<Topology>) -> RPCResult<()> {
+        let topology = request.into_inner();
+        if self.cluster.is_coordinator() {
+            if topology.term <= self.cluster.term() {
+                return Err(Status::failed_precondition(
+                    "topology is owned by coordinator",
+                ));
+            }
+            warn!(
+                term = topology.term,
+                version = topology.version,
+                "newer topology from another leader"
+            );
+            self.cluster.step_down();
+        }
+        self.apply_topology(topology)?;
+        Ok(Response::new(()))
+    }
+
+    async fn get_topology(&self, _request: Request<()>) -> RPCResult<Topology> {
+        Ok(Response::new(self.topology()))
+    }
+
+    /// 玩家已不在source server(已登出、已移动到其他server)时忽略
+    /// 与game api一样在玩家的串行队列中执行
+    #[instrument(skip(self))]
+    async fn transfer_player(&self, request: Request<TransferRequest>) -> RPCResult<()> {
+        let TransferRequest {
+            player_id,
+            source_server_id,
+            target,
+        } = request.into_inner();
+        let target = target.ok_or_else(|| Status::invalid_argument("target is required"))?;
+        let target = self.server_from_entry(target)?;
+        let dsp = self.clone();
+
+        // ert: serialized by player_id
+        async move {
+            let Ok((current_server, x, y, z)) = dsp.get_server_of_player(&player_id) else {
+                return Ok(Response::new(()));
+            };
+            if current_server.server_id != source_server_id {
+                return Ok(Response::new(()));
+            }
+            current_server
+                .map_cli
+                .clone()
+                .export_player(ExportRequest {
+                    player_id,
+                    addr: target.addr.clone(),
+                    coord: None,
+                    world: None,
+                })
+                .await?;
+            dsp.player_map.insert(player_id, (target, x, y, z));
+            Ok(Response::new(()))
+        }
+        .via_g(player_id)
+        .await
+    }
+}
+
+pub fn server_entry(server: &ServerInfo) -> ServerEntry {
+    ServerEntry {
+        server_id: server.server_id,
+        world_id: server.world_id,
+        addr: server.addr.clone(),
+        zones: server.zones.iter().map(|&id| id.into()).collect(),
+    }
+}
+
+impl Dispatcher {
+    /// 只接受带集群密钥的请求，与GameService共用对外端口
+    pub fn dispatcher_service(
+        &self,
+    ) -> InterceptedService<DispatcherServiceServer<Self>, crate::auth::ClusterAuth> {
+        DispatcherServiceServer::with_interceptor(self.clone(), self.cluster.auth.clone())
+    }
+
+    pub fn topology(&self) -> Topology {
+        let zones = self
+            .worlds
+            .values()
+            .flat_map(|world| world.zone_server_map.iter())
+            .map(|entry| ZoneEntry {
+                zone_id: (*entry.key()).into(),
+                server: Some(server_entry(&entry.value().server)),
+                exporting_server: entry.value().exporting_server.as_ref().map(server_entry),
+            })
+            .collect();
+        let version = self.topology_version.lock().unwrap();
+        Topology {
+            version: *version,
+            zones,
+            term: self.topology_term.load(Ordering::Acquire),
+        }
+    }
+
+    /// coordinator在拓扑变化后调用，版本+1并带上自身任期后推送给其他dispatcher
+    /// 推送失败只记录日志，对方定期拉取时补上
+    pub async fn publish_topology(&self) {
+        {
+            let mut version = self.topology_version.lock().unwrap();
+            *version += 1;
+            self.topology_term
+                .store(self.cluster.term(), Ordering::Release);
+        }
+        let topology = self.topology();
+        let tasks = self.cluster.peers().map(|peer| {
+            let topology = topology.clone();
+            async move {
+                let _ = peer
+                    .dispatcher_cli
+                    .clone()
+                    .sync_topology(topology)
+                    .await
+                    .log_err();
+            }
+        });
+        futures::future::join_all(tasks).await;
+        debug!(version = topology.version, "topology published");
+    }
+
+    /// 应用coordinator的拓扑，先比任期再比版本，不比当前新的忽略，任期更低的拒绝
+    /// 每个世界先插入新的zone再删除不存在的，过程中按坐标查找不会落空
+    pub fn apply_topology(&self, topology: Topology) -> Result<(), Status> {
+        let mut version = self.topology_version.lock().unwrap();
+        let term = self.topology_term.load(Ordering::Acquire);
+        if topology.term < term {
+            return Err(Status::failed_precondition(format!(
+                "stale topology of term:{}, current term:{term}",
+                topology.term
+            )));
+        }
+        if topology.term == term && topology.version <= *version {
+            return Ok(());
+        }
+        let mut world_zones: HashMap<WorldId, Vec<(ZoneId, ZoneServers)>> = HashMap::new();
+        for ZoneEntry {
+            zone_id,
+            server,
+            exporting_server,
+        } in topology.zones
+        {
+            let zone_id = ZoneId::from_raw(zone_id)
+                .ok_or_else(|| Status::invalid_argument(format!("zone_id:{zone_id:#x}")))?;
+            let server = server.ok_or_else(|| Status::invalid_argument("server is required"))?;
+            let server = self.server_from_entry(server)?;
+            if self.dimension(server.world_id)? != zone_id.dimension() {
+                return Err(Status::invalid_argument(format!(
+                    "zone_id:{zone_id} dimension mismatch with world:{}",
+                    server.world_id
+                )));
+            }
+            let exporting_server = exporting_server
+                .map(|entry| self.server_from_entry(entry))
+                .transpose()?;
+            world_zones.entry(server.world_id).or_default().push((
+                zone_id,
+                ZoneServers {
+                    server,
+                    exporting_server,
+                },
+            ));
+        }
+        for (world_id, world) in &self.worlds {
+            let zones = world_zones.remove(world_id).unwrap_or_default();
+            let zone_ids = zones.iter().map(|(id, _)| *id).collect::<HashSet<_>>();
+            for (zone_id, servers) in zones {
+                world.zone_server_map.insert(zone_id, servers);
+            }
+            for entry in world.zone_server_map.iter() {
+                if !zone_ids.contains(entry.key()) {
+                    entry.remove();
+                }
+            }
+        }
+        *version = topology.version;
+        self.topology_term.store(topology.term, Ordering::Release);
+        info!(term = topology.term, version = *version, "topology applied");
+        Ok(())
+    }
+
+    /// 已知的server沿用原有连接，新server首次请求时再连接
+    pub fn server_from_entry(&self, entry: ServerEntry) -> Result<ServerInfo, Status> {
+        let ServerEntry {
+            server_id,
+            world_id,
+            addr,
+            zones,
+        } = entry;
+        let zones = zones
+            .into_iter()
+            .map(|raw| {
+                ZoneId::from_raw(raw)
+                    .ok_or_else(|| Status::invalid_argument(format!("zone_id:{raw:#x}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let dimension = zones
+            .first()
+            .map(|id| id.dimension())
+            .ok_or_else(|| Status::invalid_argument(format!("server:{server_id} has no zone")))?;
+        let (map_cli, game_cli) = match self.find_server(server_id) {
+            Some(server) => (server.map_cli.clone(), server.game_cli.clone()),
+            None => {
+                let tls = load_tls(&self.config).map_err_unknown()?;
+                let channel = tls::connect_lazy(addr.clone(), tls.as_ref()).map_err_unknown()?;
+                (
+                    MapServiceClient::new(channel.clone()),
+                    GameServiceClient::new(channel),
+                )
+            }
+        };
+        Ok(ServerInfo {
+            inner: ServerInfoInner {
+                server_id,
+                world_id,
+                dimension,
+                zones,
+                map_cli,
+                game_cli,
+                addr,
+            }
+            .into(),
+        })
+    }
+
+    pub fn find_server(&self, server_id: ServerId) -> Option<ServerInfo> {
+        self.get_all_servers()
+            .into_iter()
+            .find(|server| server.server_id == server_id)
+    }
+
+    /// 刚成为leader时，从其他dispatcher取版本最新的拓扑
+    /// 上任leader推送时可能只送达了一部分dispatcher
+    pub async fn pull_latest_topology(&self) {
+        use tokio::time::{timeout, Duration};
+
+        let tasks = self.cluster.peers().map(|peer| async move {
+            let mut cli = peer.dispatcher_cli.clone();
+            timeout(Duration::from_secs(1), cli.get_topology(()))
+                .await
+                .ok()
+                .and_then(|res| res.ok())
+                .map(|res| res.into_inner())
+        });
+        let latest = futures::future::join_all(tasks)
+            .await
+            .into_iter()
+            .flatten()
+            .max_by_key(|topology| (topology.term, topology.version));
+        if let Some(topology) = latest {
+            let _ = self.apply_topology(topology).log_err();
+        }
+    }
+
+    /// 非leader定期从leader拉取拓扑，补上漏掉的推送
+    /// 启动后第一次拉取成功前，玩家请求返回unavailable
+    #[instrument(skip_all)]
+    pub async fn topology_sync(self) {
+        use tokio::time::{sleep, Duration};
+
+        loop {
+            if let Some(leader) = self.cluster.coordinator() {
+                if let Ok(res) = leader
+                    .dispatcher_cli
+                    .clone()
+                    .get_topology(())
+                    .await
+                    .log_err()
+                {
+                    let _ = self.apply_topology(res.into_inner()).log_err();
+                }
+            }
+            sleep(Duration::from_millis(self.config.scaling_interval)).await;
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::cluster::{DispatcherId, COORDINATOR_ID};
+use crate::util::Config;
+
+use anyhow::{bail, Context, Result};
+use fs2::FileExt;
+use tonic::async_trait;
+
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// # leader选举
+/// 只有leader运行扩缩容、修改拓扑，其他dispatcher只读拓扑。
+/// leader每轮扩缩容前续约，超过ttl没有续约视为下线，其他dispatcher下次竞选时接管。
+/// 每换一次leader任期+1，leader发布的拓扑带上任期，其他dispatcher拒绝任期更低的，
+/// 租约过期后仍在执行的旧leader不能覆盖新leader的拓扑。
+#[async_trait]
+pub trait LeaderElection: Send + Sync {
+    /// 没有leader或租约已过期时由id获得，已由id持有时续约；返回当前leader及其任期
+    async fn campaign(&self, id: DispatcherId, ttl: Duration) -> Result<Option<Leadership>>;
+    /// 由id持有时主动释放，其他dispatcher不必等租约过期
+    async fn resign(&self, id: DispatcherId) -> Result<()>;
+    /// 租约是否会过期，不会时leader在本地不按ttl判断到期
+    fn expires(&self) -> bool {
+        true
+    }
+}
+
+pub type Term = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Leadership {
+    pub leader: DispatcherId,
+    pub term: Term,
+}
+
+// 配置了租约文件时按文件选举，否则固定0号为leader
+pub fn from_config(config: &Config) -> Arc<dyn LeaderElection> {
+    if config.leader_lease_path.is_empty() {
+        Arc::new(StaticLeader::default())
+    } else {
+        Arc::new(FileElection::new(&config.leader_lease_path))
+    }
+}
+
+/// 不选举，固定一个leader，任期固定为1。没有配置选举时使用
+pub struct StaticLeader(pub DispatcherId);
+
+impl Default for StaticLeader {
+    fn default() -> Self {
+        Self(COORDINATOR_ID)
+    }
+}
+
+#[async_trait]
+impl LeaderElection for StaticLeader {
+    async fn campaign(&self, _id: DispatcherId, _ttl: Duration) -> Result<Option<Leadership>> {
+        Ok(Some(Leadership {
+            leader: self.0,
+            term: 1,
+        }))
+    }
+
+    async fn resign(&self, _id: DispatcherId) -> Result<()> {
+        Ok(())
+    }
+
+    fn expires(&self) -> bool {
+        false
+    }
+}
+
+/// 进程内的租约，同一进程内的多个dispatcher共用。测试用
+#[derive(Default)]
+pub struct MemoryElection {
+    lease: Mutex<(Option<(DispatcherId, Instant)>, Term)>, // (leader, 过期时间), 任期
+}
+
+#[async_trait]
+impl LeaderElection for MemoryElection {
+    async fn campaign(&self, id: DispatcherId, ttl: Duration) -> Result<Option<Leadership>> {
+        let mut lease = self.lease.lock().unwrap();
+        let now = Instant::now();
+        let (holder, term) = &mut *lease;
+        match *holder {
+            Some((leader, expire_at)) if leader != id && expire_at > now => {}
+            Some((leader, _)) if leader == id => *holder = Some((id, now + ttl)),
+            _ => {
+                *holder = Some((id, now + ttl));
+                *term += 1;
+            }
+        }
+        Ok(holder.map(|(leader, _)| Leadership {
+            leader,
+            term: *term,
+        }))
+    }
+
+    async fn resign(&self, id: DispatcherId) -> Result<()> {
+        let mut lease = self.lease.lock().unwrap();
+        if matches!(lease.0, Some((leader, _)) if leader == id) {
+            lease.0 = None;
+        }
+        Ok(())
+    }
+}
+
+/// # 文件租约
+/// 同一台机器(或共享目录)上的dispatcher共用一个文件，内容为`<leader> <任期> <续约序号>`，
+/// 让出后leader为`-`，保留任期。读改写期间对`<path>.lock`加flock，进程退出时由系统释放。
+/// 各进程的时钟不一定一致，文件中不记录时间：leader每次续约序号+1，
+/// 其他dispatcher按本地单调时钟观察到内容超过ttl没有变化才视为过期。
+/// leader从发起续约时起算ttl，早于其他dispatcher认定的过期时间。
+pub struct FileElection {
+    path: PathBuf,
+    lock_path: PathBuf,
+    observed: Mutex<Option<(FileLease, Instant)>>, // 最近一次看到的内容及看到的时间
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct FileLease {
+    leader: Option<DispatcherId>,
+    term: Term,
+    seq: u64,
+}
+
+const LOCK_RETRY: u32 = 100;
+
+impl FileElection {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        Self {
+            path,
+            lock_path: lock_path.into(),
+            observed: Mutex::new(None),
+        }
+    }
+
+    // 在flock保护下读改写租约文件，返回None时不修改
+    async fn with_lease<T>(
+        &self,
+        f: impl FnOnce(FileLease) -> (Option<FileLease>, T),
+    ) -> Result<T> {
+        use tokio::time::sleep;
+
+        let lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&self.lock_path)
+            .with_context(|| format!("Failed to open {}", self.lock_path.display()))?;
+        let mut retry = 0;
+        while lock.try_lock_exclusive().is_err() {
+            retry += 1;
+            if retry >= LOCK_RETRY {
+                bail!("Failed to lock {}", self.lock_path.display());
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        // 没有文件时任期从0开始；内容损坏时当作没有leader
+        let lease = fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|s| parse_lease(&s))
+            .unwrap_or_default();
+        let (update, res) = f(lease);
+        let written = match update {
+            // 先写临时文件再改名，中途退出不会留下半个文件
+            Some(FileLease { leader, term, seq }) => {
+                let leader = leader.map_or_else(|| "-".to_string(), |id| id.to_string());
+                let mut tmp_path = self.path.clone().into_os_string();
+                tmp_path.push(".tmp");
+                fs::write(&tmp_path, format!("{leader} {term} {seq}"))
+                    .and_then(|_| fs::rename(&tmp_path, &self.path))
+                    .with_context(|| format!("Failed to write {}", self.path.display()))
+            }
+            None => Ok(()),
+        };
+        let _ = lock.unlock();
+        written.map(|_| res)
+    }
+
+    // 内容与上次看到的不同时重新计时
+    fn observe(&self, lease: FileLease, ttl: Duration) -> bool {
+        let mut observed = self.observed.lock().unwrap();
+        match &*observed {
+            Some((seen, at)) if *seen == lease => at.elapsed() >= ttl,
+            _ => {
+                *observed = Some((lease, Instant::now()));
+                false
+            }
+        }
+    }
+}
+
+fn parse_lease(s: &str) -> Option<FileLease> {
+    let mut fields = s.split_whitespace();
+    let leader = match fields.next()? {
+        "-" => None,
+        id => Some(id.parse().ok()?),
+    };
+    let term = fields.next()?.parse().ok()?;
+    let seq = fields.next()?.parse().ok()?;
+    Some(FileLease { leader, term, seq })
+}
+
+#[async_trait]
+impl LeaderElection for FileElection {
+    async fn campaign(&self, id: DispatcherId, ttl: Duration) -> Result<Option<Leadership>> {
+        self.with_lease(|lease| {
+            let expired = self.observe(lease, ttl);
+            let next = match lease.leader {
+                Some(leader) if leader == id => FileLease {
+                    seq: lease.seq + 1,
+                    ..lease
+                },
+                Some(leader) if !expired => {
+                    return (
+                        None,
+                        Some(Leadership {
+                            leader,
+                            term: lease.term,
+                        }),
+                    );
+                }
+                _ => FileLease {
+                    leader: Some(id),
+                    term: lease.term + 1,
+                    seq: 0,
+                },
+            };
+            self.observe(next, ttl);
+            (
+                Some(next),
+                Some(Leadership {
+                    leader: id,
+                    term: next.term,
+                }),
+            )
+        })
+        .await
+    }
+
+    async fn resign(&self, id: DispatcherId) -> Result<()> {
+        self.with_lease(|lease| match lease.leader {
+            Some(leader) if leader == id => (
+                Some(FileLease {
+                    leader: None,
+                    seq: lease.seq + 1,
+                    ..lease
+                }),
+                (),
+            ),
+            _ => (None, ()),
+        })
+        .await
+    }
+}
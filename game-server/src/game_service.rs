@@ -1,95 +1,203 @@
+use crate::auth::{authorize, authorize_admin, Claims};
 use crate::data::*;
+use crate::dedup::DedupReply;
 use crate::dispatcher::Dispatcher;
+use crate::rate_limit::RpcKind;
 use crate::util::*;
 
 use common::proto::game_service::game_service_server::GameService;
+use common::proto::game_service::recorded_request::Request as Recorded;
 use common::proto::game_service::*;
-use common::proto::map_service::ExportRequest;
-use common::{ErrHandle, RPCResult, AABB};
+use common::proto::map_service::{ExportRequest, TargetWorld};
+use common::{ErrHandle, PlayerId, RPCResult, ZoneId, AABB, EVENT_CHANNEL_SIZE};
 
 use ert::prelude::RunVia;
-use rayon::prelude::*;
-use tonic::{async_trait, Request, Response, Status};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{async_trait, Request, Response, Status, Streaming};
 use tracing::*;
 
-use std::collections::HashMap;
+// 玩家归其他dispatcher时原样转发，返回对端的结果。对端通过认证与限流后在本地录制
+macro_rules! forward_if_remote {
+    ($dsp:ident, $request:ident, $method:ident, $recorded:path) => {
+        let player_id = $request.get_ref().player_id;
+        if let Some(peer) = $dsp.cluster.forward_target(&$request, player_id) {
+            let message = $dsp
+                .recorder
+                .is_enabled()
+                .then(|| $recorded($request.get_ref().clone()));
+            let res = peer
+                .forward($dsp.cluster.id, $request, |mut cli, request| async move {
+                    cli.$method(request).await
+                })
+                .await;
+            $dsp.recorder.record_forwarded(message, &res);
+            return res;
+        }
+    };
+}
 
 #[async_trait]
 impl GameService for Dispatcher {
+    type SubscribeStream = ReceiverStream<Result<PlayerEvent, Status>>;
+
     #[instrument(skip(self))]
     async fn login(&self, request: Request<PlayerInfo>) -> RPCResult<()> {
-        async fn inner_login(dsp: Dispatcher, player: PlayerInfo) -> RPCResult<()> {
-            check_xy_range(player.x, player.y)?;
-            if dsp.player_map.contains_key(&player.player_id) {
+        async fn inner_login(dsp: Dispatcher, mut player: PlayerInfo) -> RPCResult<()> {
+            check_xyz_range(player.x, player.y, player.z)?;
+            let dimension = dsp.dimension(player.world_id)?;
+            player.z = dimension.flatten_z(player.z);
+            player.vz = dimension.flatten_z(player.vz);
+            if dsp.world_map.is_blocked(player.x, player.y) {
+                return Err(Status::invalid_argument(format!(
+                    "x:{} y:{} is blocked",
+                    player.x, player.y
+                )));
+            }
+            if dsp.npc_set.contains(&player.player_id) {
                 return Err(Status::already_exists(format!(
-                    "player_id:{} was already login",
+                    "player_id:{} is used by npc",
                     player.player_id
                 )));
             }
-            let server = dsp.get_server_of_coord(player.x, player.y).1.server;
+            if dsp.player_map.contains_key(&player.player_id) {
+                match dsp.config.login_policy {
+                    LoginPolicy::Reject => {
+                        return Err(Status::already_exists(format!(
+                            "player_id:{} was already login",
+                            player.player_id
+                        )));
+                    }
+                    LoginPolicy::KickOld => {
+                        info!(?player.player_id, "kick old session");
+                        dsp.clear_session(player.player_id).await?;
+                    }
+                    LoginPolicy::Resume => {
+                        // 沿用旧会话，忽略本次传入的坐标等信息
+                        dsp.touch(player.player_id);
+                        return Ok(Response::new(()));
+                    }
+                }
+            }
+            let server = dsp
+                .get_server_of_coord(player.world_id, player.x, player.y, player.z)?
+                .1
+                .server;
 
             server.game_cli.clone().login(player.clone()).await?;
             dsp.player_map
-                .insert(player.player_id, (server, player.x, player.y));
+                .insert(player.player_id, (server, player.x, player.y, player.z));
+            dsp.touch(player.player_id);
             Ok(Response::new(()))
         }
 
         debug!("IN");
-        let player = request.into_inner();
+        forward_if_remote!(self, request, login, Recorded::Login);
+        authorize(&request, request.get_ref().player_id)?;
+        self.check_rate(RpcKind::Login, Some(request.get_ref().player_id))?;
+        self.recorder.record(&request, Recorded::Login);
+        let player = PlayerInfo {
+            kind: EntityKind::Player.into(),
+            ..request.into_inner()
+        };
         let player_id = player.player_id;
         let res = inner_login(self.clone(), player).via_g(player_id).await;
         debug!(?res, "OUT");
         res
     }
 
-    /// 根据正方形四个顶点，查找出对应的最多4个servers，给每个都发送aoe请求
+    /// 查找aoe范围涉及的servers，给每个都发送aoe请求
+    /// 带request_id时以施放者为单位串行，避免重试与首次请求同时执行
     #[instrument(skip(self))]
     async fn aoe(&self, request: Request<AoeRequest>) -> RPCResult<()> {
-        debug!("IN");
-        let AoeRequest {
-            player_id, radius, ..
-        } = request.into_inner();
-        let (_, x, y) = self.get_server_of_player(&player_id).map_err_unknown()?;
-        check_xy_range(x, y)?;
-
-        let xmin = x - radius;
-        let xmax = x + radius;
-        let ymax = y + radius;
-        let ymin = y - radius;
-        let tasks = [(xmin, ymin), (xmin, ymax), (xmax, ymin), (xmax, ymax)]
-            .into_iter()
-            .flat_map(|(x, y)| self.get_server_of_coord(x, y).1.into_vec())
-            .map(|server| (server.server_id, server))
-            .collect::<HashMap<_, _>>()
-            .into_values()
-            .map(|server| async move {
-                let _ = server
-                    .game_cli
-                    .clone()
-                    .aoe(AoeRequest {
-                        player_id,
-                        coord: Some(Coord { x, y }),
-                        radius,
-                    })
-                    .await
-                    .log_err();
-            });
-        futures::future::join_all(tasks).await;
+        async fn inner_aoe(dsp: Dispatcher, request: AoeRequest) -> RPCResult<()> {
+            let AoeRequest {
+                player_id,
+                radius,
+                request_id,
+                world_id,
+                ..
+            } = request;
+            dsp.touch(player_id);
+            let radius = check_radius(radius)?;
+            if let Some(DedupReply::Aoe) = dsp.get_dedup_reply(&player_id, request_id) {
+                debug!(?request_id, "duplicated");
+                return Ok(Response::new(()));
+            }
+            let (server, x, y, z) = dsp.get_server_of_player(&player_id).map_err_unknown()?;
+            check_world(&server, world_id)?;
+            check_xyz_range(x, y, z)?;
 
-        debug!("OUT");
-        Ok(Response::new(()))
+            let tasks = dsp
+                .get_servers_in_circle(world_id, x, y, z, radius)?
+                .into_iter()
+                .map(|server| async move {
+                    let _ = server
+                        .game_cli
+                        .clone()
+                        .aoe(AoeRequest {
+                            player_id,
+                            coord: Some(Coord { x, y, z }),
+                            radius,
+                            request_id,
+                            world_id,
+                        })
+                        .await
+                        .log_err();
+                });
+            futures::future::join_all(tasks).await;
+            dsp.record_dedup_reply(player_id, request_id, DedupReply::Aoe);
+            Ok(Response::new(()))
+        }
+
+        debug!("IN");
+        forward_if_remote!(self, request, aoe, Recorded::Aoe);
+        authorize(&request, request.get_ref().player_id)?;
+        self.check_rate(RpcKind::Aoe, Some(request.get_ref().player_id))?;
+        self.recorder.record(&request, Recorded::Aoe);
+        let request = request.into_inner();
+        let player_id = request.player_id;
+        let res = inner_aoe(self.clone(), request).via_g(player_id).await;
+        debug!(?res, "OUT");
+        res
     }
 
     // 移动目标在当前服务器之外的要导出用户到目标服务器
     #[instrument(skip(self))]
     async fn moving(&self, request: Request<MovingRequest>) -> RPCResult<Coord> {
         async fn inner_moving(dsp: Dispatcher, request: MovingRequest) -> RPCResult<Coord> {
-            let MovingRequest { player_id, dx, dy } = request.clone();
-            let (current_server, x, y) = dsp.get_server_of_player(&player_id).map_err_unknown()?;
+            let MovingRequest {
+                player_id,
+                dx,
+                dy,
+                dz,
+                request_id,
+                world_id,
+            } = request.clone();
+            dsp.touch(player_id);
+            if let Some(DedupReply::Moving(coord)) = dsp.get_dedup_reply(&player_id, request_id) {
+                debug!(?request_id, "duplicated");
+                return Ok(Response::new(coord));
+            }
+            let (current_server, x, y, z) =
+                dsp.get_server_of_player(&player_id).map_err_unknown()?;
+            check_world(&current_server, world_id)?;
 
-            let target_x = x + dx;
-            let target_y = y + dy;
-            check_xy_range(target_x, target_y)?;
+            // 超速或穿越障碍物时截断，转发给map-server的位移也用截断后的
+            let (target_x, target_y) = dsp.world_map.clamp_move(x, y, dx, dy);
+            let dz = current_server.dimension.flatten_z(dz);
+            let target_z = z + dz;
+            check_xyz_range(target_x, target_y, target_z)?;
+            let request = if (target_x, target_y) == (x + dx, y + dy) {
+                MovingRequest { dz, ..request }
+            } else {
+                MovingRequest {
+                    dx: target_x - x,
+                    dy: target_y - y,
+                    dz,
+                    ..request
+                }
+            };
 
             let (
                 zone_id,
@@ -97,10 +205,11 @@ impl GameService for Dispatcher {
                     server: target_server,
                     ..
                 },
-            ) = dsp.get_server_of_coord(target_x, target_y);
+            ) = dsp.get_server_of_coord(world_id, target_x, target_y, target_z)?;
             let mut coord = Coord {
                 x: target_x,
                 y: target_y,
+                z: target_z,
             };
             if !current_server.contains_zone(zone_id) {}
             if target_server != current_server {
@@ -112,6 +221,7 @@ impl GameService for Dispatcher {
                         player_id,
                         addr: target_server.addr.clone(),
                         coord: Some(coord.clone()),
+                        world: None,
                     })
                     .await?;
             } else {
@@ -124,11 +234,16 @@ impl GameService for Dispatcher {
                     .into_inner();
             }
             dsp.player_map
-                .insert(player_id, (target_server, coord.x, coord.y));
+                .insert(player_id, (target_server, coord.x, coord.y, coord.z));
+            dsp.record_dedup_reply(player_id, request_id, DedupReply::Moving(coord.clone()));
             Ok(Response::new(coord))
         }
 
         debug!("IN");
+        forward_if_remote!(self, request, moving, Recorded::Moving);
+        authorize(&request, request.get_ref().player_id)?;
+        self.check_rate(RpcKind::Moving, Some(request.get_ref().player_id))?;
+        self.recorder.record(&request, Recorded::Moving);
         let request = request.into_inner();
         let player_id = request.player_id;
         let res = inner_moving(self.clone(), request).via_g(player_id).await;
@@ -136,60 +251,162 @@ impl GameService for Dispatcher {
         res
     }
 
+    // 与moving相同，目标在当前服务器之外时带着新坐标导出用户
+    #[instrument(skip(self))]
+    async fn teleport(&self, request: Request<TeleportRequest>) -> RPCResult<Coord> {
+        async fn inner_teleport(dsp: Dispatcher, request: TeleportRequest) -> RPCResult<Coord> {
+            let TeleportRequest {
+                player_id,
+                x,
+                y,
+                z,
+                world_id,
+            } = request;
+            dsp.touch(player_id);
+            check_xyz_range(x, y, z)?;
+            if dsp.world_map.is_blocked(x, y) {
+                return Err(Status::invalid_argument(format!("x:{x} y:{y} is blocked")));
+            }
+            let (current_server, ..) = dsp.get_server_of_player(&player_id).map_err_unknown()?;
+            check_world(&current_server, world_id)?;
+            let z = current_server.dimension.flatten_z(z);
+            let target_server = dsp.get_server_of_coord(world_id, x, y, z)?.1.server;
+            let coord = if target_server != current_server {
+                let coord = Coord { x, y, z };
+                current_server
+                    .map_cli
+                    .clone()
+                    .export_player(ExportRequest {
+                        player_id,
+                        addr: target_server.addr.clone(),
+                        coord: Some(coord.clone()),
+                        world: None,
+                    })
+                    .await?;
+                coord
+            } else {
+                target_server
+                    .game_cli
+                    .clone()
+                    .teleport(TeleportRequest { z, ..request })
+                    .await?
+                    .into_inner()
+            };
+            dsp.player_map
+                .insert(player_id, (target_server, coord.x, coord.y, coord.z));
+            Ok(Response::new(coord))
+        }
+
+        debug!("IN");
+        forward_if_remote!(self, request, teleport, Recorded::Teleport);
+        authorize(&request, request.get_ref().player_id)?;
+        self.check_rate(RpcKind::Teleport, Some(request.get_ref().player_id))?;
+        self.recorder.record(&request, Recorded::Teleport);
+        let request = request.into_inner();
+        let player_id = request.player_id;
+        let res = inner_teleport(self.clone(), request).via_g(player_id).await;
+        debug!(?res, "OUT");
+        res
+    }
+
+    #[instrument(skip(self))]
+    async fn set_velocity(&self, request: Request<VelocityRequest>) -> RPCResult<()> {
+        debug!("IN");
+        forward_if_remote!(self, request, set_velocity, Recorded::SetVelocity);
+        authorize(&request, request.get_ref().player_id)?;
+        self.check_rate(RpcKind::SetVelocity, Some(request.get_ref().player_id))?;
+        self.recorder.record(&request, Recorded::SetVelocity);
+        let request = request.into_inner();
+        let player_id = request.player_id;
+        let self = self.clone();
+
+        // ert: serialized by player_id
+        async move {
+            self.touch(player_id);
+            let (server, ..) = self.get_server_of_player(&player_id).map_err_unknown()?;
+            check_world(&server, request.world_id)?;
+            let request = VelocityRequest {
+                vz: server.dimension.flatten_z(request.vz),
+                ..request
+            };
+            server.game_cli.clone().set_velocity(request).await
+        }
+        .via_g(player_id)
+        .await
+    }
+
     #[instrument(skip(self))]
     async fn query(&self, request: Request<QueryRequest>) -> RPCResult<QueryReply> {
         debug!("IN");
+        // query不指定玩家，开启认证时按token绑定的玩家限流
+        let player_id = request.extensions().get::<Claims>().map(|c| c.player_id);
+        self.check_rate(RpcKind::Query, player_id)?;
+        self.recorder.record(&request, Recorded::Query);
+        if let Some(player_id) = player_id {
+            self.touch_serialized(player_id).await;
+        }
         let QueryRequest {
             xmin,
             xmax,
             ymin,
             ymax,
+            world_id,
+            zmin,
+            zmax,
+            ..
         } = request.into_inner();
-        let query_aabb = AABB {
+        let dimension = self.dimension(world_id)?;
+        let query_aabb = check_aabb(AABB {
             xmin,
             xmax,
             ymin,
             ymax,
-        };
+            zmin: dimension.flatten_z(zmin),
+            zmax: dimension.flatten_z(zmax),
+        })?;
+        // 每个server可能有多块，合并后一次查询它们的并集，块的公共边上的玩家只返回一次
         let tasks = self
-            .get_all_servers()
-            .into_par_iter()
-            .filter_map(|server| {
-                let zone_id = if server.zones.len() == 1 {
-                    server.zones[0]
-                } else {
-                    // server有多个zone时，返回父节点zone
-                    server.zones[0] / 10
-                };
-                let zone_aabb = AABB::from_zone_id(zone_id);
-                // 取交集
-                zone_aabb
-                    .get_intersection(&query_aabb)
-                    .map(|aabb| async move {
-                        server
-                            .game_cli
-                            .clone()
-                            .query(QueryRequest {
-                                xmin: aabb.xmin,
-                                xmax: aabb.xmax,
-                                ymin: aabb.ymin,
-                                ymax: aabb.ymax,
-                            })
-                            .await
-                            .map(|res| {
-                                let infos = res.into_inner().infos;
-                                debug!("server_id:{} infos:{}", server.server_id, infos.len());
-                                infos
-                            })
+            .get_query_areas(world_id, &query_aabb)?
+            .into_iter()
+            .map(|(server, areas)| async move {
+                let areas = areas
+                    .into_iter()
+                    .map(|aabb| QueryRequest {
+                        xmin: aabb.xmin,
+                        xmax: aabb.xmax,
+                        ymin: aabb.ymin,
+                        ymax: aabb.ymax,
+                        world_id,
+                        zmin: aabb.zmin,
+                        zmax: aabb.zmax,
+                        areas: vec![],
+                    })
+                    .collect();
+                server
+                    .game_cli
+                    .clone()
+                    .query(QueryRequest {
+                        world_id,
+                        areas,
+                        ..Default::default()
+                    })
+                    .await
+                    .map(|res| {
+                        let infos = res.into_inner().infos;
+                        debug!("server_id:{} infos:{}", server.server_id, infos.len());
+                        infos
                     })
             })
             .collect::<Vec<_>>();
-        let infos: Vec<_> = futures::future::join_all(tasks)
+        let mut infos: Vec<_> = futures::future::join_all(tasks)
             .await
             .into_iter()
             .filter_map(|res| res.log_err().ok())
             .flatten()
             .collect();
+        // 导出过程中玩家会同时存在于两个server
+        infos.sort_unstable_by_key(|p| p.player_id);
+        infos.dedup_by_key(|p| p.player_id);
         debug!("OUT: {}", infos.len());
         Ok(Response::new(QueryReply { infos }))
     }
@@ -197,16 +414,508 @@ impl GameService for Dispatcher {
     #[instrument(skip(self))]
     async fn logout(&self, request: Request<PlayerIdRequest>) -> RPCResult<()> {
         debug!("IN");
+        forward_if_remote!(self, request, logout, Recorded::Logout);
+        authorize(&request, request.get_ref().player_id)?;
+        self.check_rate(RpcKind::Logout, Some(request.get_ref().player_id))?;
+        self.recorder.record(&request, Recorded::Logout);
+        let PlayerIdRequest {
+            player_id,
+            world_id,
+        } = request.into_inner();
+        let (server, ..) = self.get_server_of_player(&player_id).map_err_unknown()?;
+        check_world(&server, world_id)?;
+        if self.npc_set.contains(&player_id) {
+            return Err(Status::invalid_argument(format!(
+                "player_id:{player_id} is npc, use despawn_npc"
+            )));
+        }
+        self.logout_player(player_id).await?;
+        Ok(Response::new(()))
+    }
+
+    // 只更新活跃时间，不转发给map-server
+    #[instrument(skip(self))]
+    async fn heartbeat(&self, request: Request<PlayerIdRequest>) -> RPCResult<()> {
+        forward_if_remote!(self, request, heartbeat, Recorded::Heartbeat);
+        authorize(&request, request.get_ref().player_id)?;
+        self.check_rate(RpcKind::Heartbeat, Some(request.get_ref().player_id))?;
+        self.recorder.record(&request, Recorded::Heartbeat);
+        let PlayerIdRequest {
+            player_id,
+            world_id,
+        } = request.into_inner();
+        let (server, ..) = self
+            .get_server_of_player(&player_id)
+            .map_err(|_| Status::not_found(format!("player_id:{player_id} not login")))?;
+        check_world(&server, world_id)?;
+        self.touch_serialized(player_id).await;
+        Ok(Response::new(()))
+    }
+
+    // 与aoe相同，发给范围涉及的servers，汇总送达数
+    #[instrument(skip(self))]
+    async fn say(&self, request: Request<SayRequest>) -> RPCResult<DeliveryReply> {
+        debug!("IN");
+        forward_if_remote!(self, request, say, Recorded::Say);
+        authorize(&request, request.get_ref().player_id)?;
+        self.check_rate(RpcKind::Say, Some(request.get_ref().player_id))?;
+        self.recorder.record(&request, Recorded::Say);
+        self.touch_serialized(request.get_ref().player_id).await;
+        let SayRequest {
+            player_id,
+            radius,
+            text,
+            world_id,
+            ..
+        } = request.into_inner();
+        let radius = check_radius(radius)?;
+        let (server, x, y, z) = self.get_server_of_player(&player_id).map_err_unknown()?;
+        check_world(&server, world_id)?;
+        check_xyz_range(x, y, z)?;
+
+        let tasks = self
+            .get_servers_in_circle(world_id, x, y, z, radius)?
+            .into_iter()
+            .map(|server| {
+                let request = SayRequest {
+                    player_id,
+                    radius,
+                    text: text.clone(),
+                    coord: Some(Coord { x, y, z }),
+                    world_id,
+                };
+                async move {
+                    server
+                        .game_cli
+                        .clone()
+                        .say(request)
+                        .await
+                        .map(|res| res.into_inner().delivered)
+                        .log_err()
+                        .unwrap_or(0)
+                }
+            });
+        let delivered = futures::future::join_all(tasks).await.into_iter().sum();
+
+        debug!(?delivered, "OUT");
+        Ok(Response::new(DeliveryReply { delivered }))
+    }
+
+    /// zone_ids转换为区域，与areas一起按各server范围取交集后转发，汇总送达数
+    #[instrument(skip(self))]
+    async fn broadcast(&self, request: Request<BroadcastRequest>) -> RPCResult<DeliveryReply> {
+        debug!("IN");
+        authorize_admin(&request)?;
+        self.check_rate(RpcKind::Broadcast, None)?;
+        self.recorder.record(&request, Recorded::Broadcast);
+        let BroadcastRequest {
+            text,
+            zone_ids,
+            areas,
+            world_id,
+        } = request.into_inner();
+        let dimension = self.dimension(world_id)?;
+        let zone_ids = zone_ids
+            .into_iter()
+            .map(|raw| parse_zone_id(raw, self.config.max_zone_depth, dimension))
+            .collect::<Result<Vec<_>, _>>()?;
+        let areas = zone_ids
+            .into_iter()
+            .map(ZoneId::bounds)
+            .chain(areas.into_iter().map(
+                |QueryRequest {
+                     xmin,
+                     ymin,
+                     xmax,
+                     ymax,
+                     zmin,
+                     zmax,
+                     ..
+                 }| AABB {
+                    xmin,
+                    xmax,
+                    ymin,
+                    ymax,
+                    zmin: dimension.flatten_z(zmin),
+                    zmax: dimension.flatten_z(zmax),
+                },
+            ))
+            .collect::<Vec<_>>();
+        let whole_world = areas.is_empty();
+
+        let tasks = self
+            .get_world_servers(world_id)?
+            .into_iter()
+            .filter_map(|server| {
+                let zone_aabb = server.get_zone_aabb();
+                let areas = areas
+                    .iter()
+                    .filter_map(|area| zone_aabb.get_intersection(area))
+                    .map(|aabb| QueryRequest {
+                        xmin: aabb.xmin,
+                        ymin: aabb.ymin,
+                        xmax: aabb.xmax,
+                        ymax: aabb.ymax,
+                        world_id,
+                        zmin: aabb.zmin,
+                        zmax: aabb.zmax,
+                        areas: vec![],
+                    })
+                    .collect::<Vec<_>>();
+                if !whole_world && areas.is_empty() {
+                    return None;
+                }
+                let request = BroadcastRequest {
+                    text: text.clone(),
+                    zone_ids: vec![],
+                    areas,
+                    world_id,
+                };
+                Some(async move {
+                    server
+                        .game_cli
+                        .clone()
+                        .broadcast(request)
+                        .await
+                        .map(|res| res.into_inner().delivered)
+                        .log_err()
+                        .unwrap_or(0)
+                })
+            });
+        let delivered = futures::future::join_all(tasks).await.into_iter().sum();
+
+        debug!(?delivered, "OUT");
+        Ok(Response::new(DeliveryReply { delivered }))
+    }
+
+    /// 与跨服务器的teleport相同，带着新坐标与世界导出到目标世界的server
+    /// 事件流由forward_events在新server重新订阅
+    #[instrument(skip(self))]
+    async fn change_world(&self, request: Request<ChangeWorldRequest>) -> RPCResult<()> {
+        async fn inner_change_world(dsp: Dispatcher, request: ChangeWorldRequest) -> RPCResult<()> {
+            let ChangeWorldRequest {
+                player_id,
+                world_id,
+                target_world_id,
+                x,
+                y,
+                z,
+            } = request;
+            dsp.touch(player_id);
+            check_xyz_range(x, y, z)?;
+            if dsp.world_map.is_blocked(x, y) {
+                return Err(Status::invalid_argument(format!("x:{x} y:{y} is blocked")));
+            }
+            let (current_server, ..) = dsp.get_server_of_player(&player_id).map_err_unknown()?;
+            check_world(&current_server, world_id)?;
+            let z = dsp.dimension(target_world_id)?.flatten_z(z);
+            let target_server = dsp.get_server_of_coord(target_world_id, x, y, z)?.1.server;
+            if target_server == current_server {
+                // 同一世界同一server内，等同teleport
+                current_server
+                    .game_cli
+                    .clone()
+                    .teleport(TeleportRequest {
+                        player_id,
+                        x,
+                        y,
+                        z,
+                        world_id,
+                    })
+                    .await?;
+            } else {
+                current_server
+                    .map_cli
+                    .clone()
+                    .export_player(ExportRequest {
+                        player_id,
+                        addr: target_server.addr.clone(),
+                        coord: Some(Coord { x, y, z }),
+                        world: Some(TargetWorld {
+                            world_id: target_world_id,
+                        }),
+                    })
+                    .await?;
+            }
+            dsp.player_map.insert(player_id, (target_server, x, y, z));
+            Ok(Response::new(()))
+        }
+
+        debug!("IN");
+        forward_if_remote!(self, request, change_world, Recorded::ChangeWorld);
+        authorize(&request, request.get_ref().player_id)?;
+        self.check_rate(RpcKind::ChangeWorld, Some(request.get_ref().player_id))?;
+        self.recorder.record(&request, Recorded::ChangeWorld);
         let request = request.into_inner();
         let player_id = request.player_id;
-        let self = self.clone();
+        let res = inner_change_world(self.clone(), request)
+            .via_g(player_id)
+            .await;
+        debug!(?res, "OUT");
+        res
+    }
+
+    /// 按坐标放到对应server，之后与玩家一样随zone导出导入
+    #[instrument(skip(self))]
+    async fn spawn_npc(&self, request: Request<PlayerInfo>) -> RPCResult<()> {
+        async fn inner_spawn(dsp: Dispatcher, mut npc: PlayerInfo) -> RPCResult<()> {
+            check_xyz_range(npc.x, npc.y, npc.z)?;
+            let dimension = dsp.dimension(npc.world_id)?;
+            npc.z = dimension.flatten_z(npc.z);
+            npc.vz = dimension.flatten_z(npc.vz);
+            if dsp.world_map.is_blocked(npc.x, npc.y) {
+                return Err(Status::invalid_argument(format!(
+                    "x:{} y:{} is blocked",
+                    npc.x, npc.y
+                )));
+            }
+            if dsp.player_map.contains_key(&npc.player_id) {
+                return Err(Status::already_exists(format!(
+                    "npc_id:{} was already used",
+                    npc.player_id
+                )));
+            }
+            let server = dsp
+                .get_server_of_coord(npc.world_id, npc.x, npc.y, npc.z)?
+                .1
+                .server;
+
+            server.game_cli.clone().spawn_npc(npc.clone()).await?;
+            dsp.player_map
+                .insert(npc.player_id, (server, npc.x, npc.y, npc.z));
+            dsp.npc_set.insert(npc.player_id);
+            Ok(Response::new(()))
+        }
+
+        debug!("IN");
+        forward_if_remote!(self, request, spawn_npc, Recorded::SpawnNpc);
+        authorize_admin(&request)?;
+        self.check_rate(RpcKind::SpawnNpc, None)?;
+        self.recorder.record(&request, Recorded::SpawnNpc);
+        let npc = request.into_inner();
+        let npc_id = npc.player_id;
+        let res = inner_spawn(self.clone(), npc).via_g(npc_id).await;
+        debug!(?res, "OUT");
+        res
+    }
+
+    #[instrument(skip(self))]
+    async fn despawn_npc(&self, request: Request<PlayerIdRequest>) -> RPCResult<()> {
+        debug!("IN");
+        forward_if_remote!(self, request, despawn_npc, Recorded::DespawnNpc);
+        authorize_admin(&request)?;
+        self.check_rate(RpcKind::DespawnNpc, None)?;
+        self.recorder.record(&request, Recorded::DespawnNpc);
+        let PlayerIdRequest {
+            player_id: npc_id,
+            world_id,
+        } = request.into_inner();
+        let dsp = self.clone();
 
         // ert: serialized by player_id
-        async move {
-            let (server, ..) = self.get_server_of_player(&player_id).map_err_unknown()?;
-            server.game_cli.clone().logout(request).await
+        let res = async move {
+            if !dsp.npc_set.contains(&npc_id) {
+                return Err(Status::not_found(format!("npc_id:{npc_id} not found")));
+            }
+            let (server, ..) = dsp.get_server_of_player(&npc_id).map_err_unknown()?;
+            check_world(&server, world_id)?;
+            server
+                .game_cli
+                .clone()
+                .despawn_npc(PlayerIdRequest {
+                    player_id: npc_id,
+                    world_id,
+                })
+                .await?;
+            dsp.player_map.remove(&npc_id);
+            dsp.npc_set.remove(&npc_id);
+            Ok(Response::new(()))
+        }
+        .via_g(npc_id)
+        .await;
+        debug!(?res, "OUT");
+        res
+    }
+
+    #[instrument(skip(self))]
+    async fn subscribe(
+        &self,
+        request: Request<PlayerIdRequest>,
+    ) -> RPCResult<Self::SubscribeStream> {
+        debug!("IN");
+        if let Some(peer) = self
+            .cluster
+            .forward_target(&request, request.get_ref().player_id)
+        {
+            // 对端的事件流转接到本地channel
+            let message = self
+                .recorder
+                .is_enabled()
+                .then(|| Recorded::Subscribe(request.get_ref().clone()));
+            let res = peer
+                .forward(self.cluster.id, request, |mut cli, request| async move {
+                    cli.subscribe(request).await
+                })
+                .await;
+            self.recorder.record_forwarded(message, &res);
+            let stream = res?.into_inner();
+            let (tx, rx) = mpsc::channel(EVENT_CHANNEL_SIZE);
+            tokio::spawn(pipe_events(stream, tx));
+            return Ok(Response::new(ReceiverStream::new(rx)));
+        }
+        authorize(&request, request.get_ref().player_id)?;
+        self.check_rate(RpcKind::Subscribe, Some(request.get_ref().player_id))?;
+        self.recorder.record(&request, Recorded::Subscribe);
+        self.touch_serialized(request.get_ref().player_id).await;
+        let PlayerIdRequest {
+            player_id,
+            world_id,
+        } = request.into_inner();
+        let (server, ..) = self
+            .get_server_of_player(&player_id)
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        check_world(&server, world_id)?;
+        let (tx, rx) = mpsc::channel(EVENT_CHANNEL_SIZE);
+        tokio::spawn(forward_events(self.clone(), player_id, tx));
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// 事件只记录在执行扩缩容的leader，其他dispatcher认证、限流后转发给leader
+    #[instrument(skip(self))]
+    async fn get_scaling_events(
+        &self,
+        request: Request<ScalingEventsRequest>,
+    ) -> RPCResult<ScalingEventsReply> {
+        debug!("IN");
+        authorize_admin(&request)?;
+        self.check_rate(RpcKind::GetScalingEvents, None)?;
+        if let Some(leader) = self.cluster.leader_target(&request) {
+            let message = self
+                .recorder
+                .is_enabled()
+                .then(|| Recorded::GetScalingEvents(request.get_ref().clone()));
+            let res = leader
+                .forward(self.cluster.id, request, |mut cli, request| async move {
+                    cli.get_scaling_events(request).await
+                })
+                .await;
+            self.recorder.record_forwarded(message, &res);
+            return res;
+        }
+        self.recorder.record(&request, Recorded::GetScalingEvents);
+        let filter = request.into_inner();
+        let dimension = self.dimension(filter.world_id)?;
+        let zones = filter
+            .zone_ids
+            .iter()
+            .map(|&raw| parse_zone_id(raw, self.config.max_zone_depth, dimension))
+            .collect::<Result<Vec<_>, _>>()?;
+        let events = self.scaling_log.query(&filter, &zones);
+        debug!(count = events.len(), "OUT");
+        Ok(Response::new(ScalingEventsReply { events }))
+    }
+
+    /// 每个dispatcher各自限流计数，不转发；只读且与重放无关，不录制
+    #[instrument(skip(self))]
+    async fn get_rate_limit_stats(&self, request: Request<()>) -> RPCResult<RateLimitStatsReply> {
+        debug!("IN");
+        authorize_admin(&request)?;
+        self.check_rate(RpcKind::GetRateLimitStats, None)?;
+        let rejected = self
+            .rate_limiter
+            .rejected_counts()
+            .into_iter()
+            .map(|(kind, count)| RejectedCount {
+                rpc: format!("{kind:?}"),
+                count,
+            })
+            .collect();
+        Ok(Response::new(RateLimitStatsReply {
+            rejected,
+            player_buckets: self.rate_limiter.player_bucket_count() as u64,
+        }))
+    }
+}
+
+/// 订阅玩家所在map-server的事件流并转发给客户端
+/// 玩家被导出时旧事件流结束，到新服务器重新订阅；连续订阅失败(已登出)时结束
+async fn forward_events(
+    dsp: Dispatcher,
+    player_id: PlayerId,
+    tx: mpsc::Sender<Result<PlayerEvent, Status>>,
+) {
+    use futures::StreamExt;
+    use tokio::time::{sleep, Duration};
+
+    const MAX_RETRY: u32 = 10;
+    const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+    let mut retry = 0;
+    while !tx.is_closed() {
+        let res = match dsp.get_server_of_player(&player_id) {
+            Ok((server, ..)) => {
+                server
+                    .game_cli
+                    .clone()
+                    .subscribe(PlayerIdRequest {
+                        player_id,
+                        world_id: server.world_id,
+                    })
+                    .await
+            }
+            Err(e) => Err(Status::not_found(e.to_string())),
+        };
+        let mut stream = match res {
+            Ok(res) => res.into_inner(),
+            Err(status) => {
+                retry += 1;
+                if retry >= MAX_RETRY {
+                    let _ = tx.send(Err(status)).await;
+                    return;
+                }
+                sleep(RETRY_INTERVAL).await;
+                continue;
+            }
+        };
+        retry = 0;
+        loop {
+            tokio::select! {
+                _ = tx.closed() => return,
+                event = stream.next() => match event {
+                    Some(Ok(event)) => {
+                        if tx.send(Ok(event)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Err(status)) => {
+                        debug!(?player_id, ?status);
+                        break;
+                    }
+                    None => break,
+                },
+            }
+        }
+        debug!(?player_id, "event stream ended, resubscribe");
+    }
+}
+
+async fn pipe_events(
+    mut stream: Streaming<PlayerEvent>,
+    tx: mpsc::Sender<Result<PlayerEvent, Status>>,
+) {
+    use futures::StreamExt;
+
+    loop {
+        tokio::select! {
+            _ = tx.closed() => return,
+            event = stream.next() => match event {
+                Some(event) => {
+                    if tx.send(event).await.is_err() {
+                        return;
+                    }
+                }
+                None => return,
+            },
         }
-        .via_g(player_id)
-        .await
     }
 }
@@ -1,5 +1,15 @@
+pub mod auth;
+pub mod cluster;
 pub mod data;
+pub mod dedup;
 pub mod dispatcher;
+pub mod dispatcher_service;
+pub mod election;
 pub mod game_service;
+pub mod rate_limit;
+pub mod recorder;
+pub mod replay;
+pub mod scaling_log;
 pub mod server_scaling;
+pub mod simulator;
 pub mod util;
@@ -1,14 +1,19 @@
+mod auth;
+mod cluster;
 mod data;
+mod dedup;
 mod dispatcher;
+mod dispatcher_service;
+mod election;
 mod game_service;
+mod rate_limit;
+mod recorder;
+mod scaling_log;
 mod server_scaling;
 mod util;
 
 use common::proto::game_service::game_service_server::GameServiceServer;
-use common::{
-    DEFAULT_GAME_PORT, DEFAULT_MAX_PLAYERS, DEFAULT_MAX_ZONE_DEPTH, DEFAULT_MIN_PLAYERS,
-    GAME_PORT_ENV_NAME,
-};
+use common::{DEFAULT_GAME_PORT, GAME_PORT_ENV_NAME};
 use tonic::transport::Server;
 use tracing::*;
 
@@ -18,26 +23,82 @@ async fn main() {
 
     let port = std::env::var(GAME_PORT_ENV_NAME).unwrap_or_else(|_| DEFAULT_GAME_PORT.to_string());
     let addr = format!("127.0.0.1:{}", port).parse().unwrap();
-    let config = util::Config {
-        max_players: DEFAULT_MAX_PLAYERS,
-        min_players: DEFAULT_MIN_PLAYERS,
-        max_zone_depth: DEFAULT_MAX_ZONE_DEPTH,
-        scaling_interval: 10_000,
-    };
-    let config = econf::load(config, "GAME");
+    let config = econf::load(util::Config::default(), "GAME");
+    // 签发token：game-server token <player_id> [player|admin] [有效秒数]
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) == Some("token") {
+        issue_token(&config, &args[2..]);
+        return;
+    }
     info!("starting at {addr} {config:?}");
 
     // Set ert worker count.
     ert::prelude::Router::new(10_000).set_as_global();
 
     let dispatcher = dispatcher::Dispatcher::new(config).await.unwrap();
+    // 只有leader扩缩容，其他dispatcher从leader同步拓扑
     tokio::spawn(dispatcher.clone().scaling_moniter());
-    Server::builder()
-        .add_service(GameServiceServer::new(dispatcher.clone()))
+    tokio::spawn(dispatcher.clone().topology_sync());
+    if dispatcher.config.tick_interval > 0 {
+        tokio::spawn(dispatcher.clone().simulation_moniter());
+    }
+    if dispatcher.config.idle_timeout > 0 {
+        tokio::spawn(dispatcher.clone().idle_reaper());
+    }
+    let mut builder = Server::builder();
+    let router = if dispatcher.config.auth_key.is_empty() {
+        warn!("GAME_AUTH_KEY is empty, authentication disabled");
+        builder.add_service(GameServiceServer::new(dispatcher.clone()))
+    } else {
+        let auth = auth::TokenAuth::new(dispatcher.config.auth_key.as_str());
+        builder.add_service(GameServiceServer::with_interceptor(
+            dispatcher.clone(),
+            auth,
+        ))
+    };
+    router
+        .add_service(dispatcher.dispatcher_service())
         .serve(addr)
         .await
         .unwrap();
+    dispatcher.recorder.flush().await;
 
-    dispatcher.shutdown_all_map_server().await;
+    // 有其他dispatcher时只让出leader，map-server由接任的leader继续管理
+    if dispatcher.cluster.peers().next().is_some() {
+        dispatcher.cluster.resign().await;
+    } else {
+        dispatcher.shutdown_all_map_server().await;
+    }
     info!("exit");
 }
+
+fn issue_token(config: &util::Config, args: &[String]) {
+    use auth::{Claims, Scope, TokenAuth};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    assert!(!config.auth_key.is_empty(), "Please set env GAME_AUTH_KEY");
+    let player_id = args
+        .first()
+        .and_then(|s| s.parse().ok())
+        .expect("Usage: game-server token <player_id> [player|admin] [ttl_secs]");
+    let scope = match args.get(1).map(String::as_str) {
+        Some("admin") => Scope::Admin,
+        _ => Scope::Player,
+    };
+    let expire_at = match args.get(2).and_then(|s| s.parse::<u64>().ok()) {
+        Some(ttl) => {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                + ttl
+        }
+        None => 0,
+    };
+    let token = TokenAuth::new(config.auth_key.as_str()).issue(&Claims {
+        player_id,
+        scope,
+        expire_at,
+    });
+    println!("{token}");
+}
@@ -0,0 +1,185 @@
+use crate::util::Config;
+
+use common::PlayerId;
+
+use crossbeam_skiplist::SkipMap;
+use tonic::Status;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RpcKind {
+    Login,
+    Logout,
+    Aoe,
+    Moving,
+    Query,
+    Teleport,
+    SetVelocity,
+    Say,
+    Subscribe,
+    Broadcast,
+    Heartbeat,
+    SpawnNpc,
+    DespawnNpc,
+    ChangeWorld,
+    GetScalingEvents,
+    GetRateLimitStats,
+}
+
+impl RpcKind {
+    pub const ALL: [RpcKind; 16] = [
+        RpcKind::Login,
+        RpcKind::Logout,
+        RpcKind::Aoe,
+        RpcKind::Moving,
+        RpcKind::Query,
+        RpcKind::Teleport,
+        RpcKind::SetVelocity,
+        RpcKind::Say,
+        RpcKind::Subscribe,
+        RpcKind::Broadcast,
+        RpcKind::Heartbeat,
+        RpcKind::SpawnNpc,
+        RpcKind::DespawnNpc,
+        RpcKind::ChangeWorld,
+        RpcKind::GetScalingEvents,
+        RpcKind::GetRateLimitStats,
+    ];
+}
+
+const RPC_KIND_COUNT: usize = RpcKind::ALL.len();
+
+/// 令牌桶，容量为每秒速率，即允许1秒的突发
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last: Instant::now(),
+        }
+    }
+
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    // 归还try_acquire取走的令牌
+    fn refund(&mut self) {
+        self.tokens = (self.tokens + 1.0).min(self.rate);
+    }
+}
+
+/// # 限流
+/// * 每个玩家每种RPC一个桶，moving/aoe/query单独配置，其他RPC共用一个速率
+/// * 全部请求共用一个全局桶
+/// * 速率为0表示不限制
+/// 先检查玩家桶再检查全局桶，单个玩家的洪泛请求被玩家桶拒绝，不会耗尽全局配额；
+/// 被全局桶拒绝时归还玩家桶的令牌，不占用玩家配额
+pub struct RateLimiter {
+    player_buckets: SkipMap<(PlayerId, RpcKind), Mutex<TokenBucket>>,
+    global_bucket: Option<Mutex<TokenBucket>>,
+    moving_rate: u32,
+    aoe_rate: u32,
+    query_rate: u32,
+    player_rate: u32,
+    rejected: [AtomicU64; RPC_KIND_COUNT], // 按RPC统计被拒绝的请求数
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            player_buckets: SkipMap::new(),
+            global_bucket: (config.global_rate_limit > 0)
+                .then(|| Mutex::new(TokenBucket::new(config.global_rate_limit))),
+            moving_rate: config.moving_rate_limit,
+            aoe_rate: config.aoe_rate_limit,
+            query_rate: config.query_rate_limit,
+            player_rate: config.player_rate_limit,
+            rejected: Default::default(),
+        }
+    }
+
+    fn player_rate_of(&self, kind: RpcKind) -> u32 {
+        match kind {
+            RpcKind::Moving => self.moving_rate,
+            RpcKind::Aoe => self.aoe_rate,
+            RpcKind::Query => self.query_rate,
+            _ => self.player_rate,
+        }
+    }
+
+    // player_id为None时(例如未认证的query、未登录的玩家)只检查全局桶
+    pub fn check(&self, kind: RpcKind, player_id: Option<PlayerId>) -> Result<(), Status> {
+        let rate = self.player_rate_of(kind);
+        let player_bucket = match (player_id, rate > 0) {
+            (Some(player_id), true) => {
+                let entry = self
+                    .player_buckets
+                    .get_or_insert_with((player_id, kind), || Mutex::new(TokenBucket::new(rate)));
+                if !entry.value().lock().unwrap().try_acquire() {
+                    return Err(self.reject(kind, format!("player_id:{player_id} {kind:?}")));
+                }
+                Some(entry)
+            }
+            _ => None,
+        };
+        if let Some(bucket) = &self.global_bucket {
+            if !bucket.lock().unwrap().try_acquire() {
+                if let Some(entry) = player_bucket {
+                    entry.value().lock().unwrap().refund();
+                }
+                return Err(self.reject(kind, format!("global {kind:?}")));
+            }
+        }
+        Ok(())
+    }
+
+    fn reject(&self, kind: RpcKind, msg: String) -> Status {
+        self.rejected[kind as usize].fetch_add(1, Ordering::Relaxed);
+        Status::resource_exhausted(format!("rate limited: {msg}"))
+    }
+
+    pub fn rejected_count(&self, kind: RpcKind) -> u64 {
+        self.rejected[kind as usize].load(Ordering::Relaxed)
+    }
+
+    // 有被拒绝请求的RPC及次数，通过get_rate_limit_stats查询
+    pub fn rejected_counts(&self) -> Vec<(RpcKind, u64)> {
+        RpcKind::ALL
+            .into_iter()
+            .map(|kind| (kind, self.rejected_count(kind)))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+
+    pub fn player_bucket_count(&self) -> usize {
+        self.player_buckets.len()
+    }
+
+    // 登出时清理该玩家的桶
+    pub fn remove_player(&self, player_id: PlayerId) {
+        let range = (player_id, RpcKind::ALL[0])..=(player_id, RpcKind::ALL[RPC_KIND_COUNT - 1]);
+        for entry in self.player_buckets.range(range) {
+            entry.remove();
+        }
+    }
+}
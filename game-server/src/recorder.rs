@@ -0,0 +1,142 @@
+use crate::auth::ClusterAuth;
+use crate::util::{now_ms, Config};
+
+use common::proto::game_service::{recorded_request, RecordedRequest};
+use common::ErrHandle;
+
+use anyhow::{Context, Result};
+use prost::Message;
+use tokio::sync::{mpsc, oneshot};
+use tonic::{Code, Request, Status};
+use tracing::*;
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+// 写文件跟不上时最多缓冲的条数，超出的丢弃并记录日志
+const RECORD_CHANNEL_SIZE: usize = 65536;
+
+enum Command {
+    Write(Vec<u8>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// # 请求录制
+/// 开启后把收到的每个GameService请求连同时间追加到文件，replay模块读取后按原速或加速重放。
+/// 每条为长度前缀编码的RecordedRequest，由单独的线程缓冲写入，请求处理中只编码后放入channel。
+/// 认证失败、被限流的请求不录制，重放目标的token、限流配置不同，这些结果不可重现。
+/// 其他dispatcher转发来的请求已由收到它的dispatcher录制，不重复录制；token不录制。
+pub struct Recorder {
+    sink: Option<mpsc::Sender<Command>>,
+    auth: ClusterAuth,
+}
+
+impl Recorder {
+    pub fn new(config: &Config) -> Result<Self> {
+        let sink = if config.record_path.is_empty() {
+            None
+        } else {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&config.record_path)
+                .with_context(|| format!("Failed to open {}", config.record_path))?;
+            let (tx, rx) = mpsc::channel(RECORD_CHANNEL_SIZE);
+            std::thread::Builder::new()
+                .name("recorder".to_string())
+                .spawn(move || write_records(file, rx))?;
+            Some(tx)
+        };
+        Ok(Self {
+            sink,
+            auth: ClusterAuth::new(config.cluster_secret.as_str()),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    /// 本地处理的请求通过认证与限流后调用；未开启时不拷贝请求
+    pub fn record<T: Clone>(
+        &self,
+        request: &Request<T>,
+        wrap: impl FnOnce(T) -> recorded_request::Request,
+    ) {
+        if !self.is_enabled() || self.auth.is_forwarded(request.metadata()) {
+            return;
+        }
+        self.record_message(wrap(request.get_ref().clone()));
+    }
+
+    /// 转发给其他dispatcher的请求在得到结果后调用，对端认证失败或限流的不录制
+    pub fn record_forwarded<R>(
+        &self,
+        message: Option<recorded_request::Request>,
+        res: &Result<R, Status>,
+    ) {
+        if let Some(message) = message {
+            if !res.as_ref().err().map_or(false, is_rejected) {
+                self.record_message(message);
+            }
+        }
+    }
+
+    fn record_message(&self, message: recorded_request::Request) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        let record = RecordedRequest {
+            time_ms: now_ms(),
+            request: Some(message),
+        };
+        if sink
+            .try_send(Command::Write(record.encode_length_delimited_to_vec()))
+            .is_err()
+        {
+            error!("record channel is full or closed, request dropped");
+        }
+    }
+
+    /// 等已录制的请求都写入文件
+    pub async fn flush(&self) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        let (tx, rx) = oneshot::channel();
+        if sink.send(Command::Flush(tx)).await.is_ok() {
+            let _ = rx.await;
+        }
+    }
+}
+
+// 认证失败与限流，只有这几处返回这些状态码
+fn is_rejected(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Unauthenticated | Code::PermissionDenied | Code::ResourceExhausted
+    )
+}
+
+// 取完channel中已有的再flush，空闲时文件中是完整的
+fn write_records(file: File, mut rx: mpsc::Receiver<Command>) {
+    let mut writer = BufWriter::new(file);
+    while let Some(mut command) = rx.blocking_recv() {
+        loop {
+            match command {
+                Command::Write(buf) => {
+                    let _ = writer.write_all(&buf).log_err();
+                }
+                Command::Flush(ack) => {
+                    let _ = writer.flush().log_err();
+                    let _ = ack.send(());
+                }
+            }
+            match rx.try_recv() {
+                Ok(next) => command = next,
+                Err(_) => break,
+            }
+        }
+        let _ = writer.flush().log_err();
+    }
+}
@@ -0,0 +1,375 @@
+use crate::auth::{Claims, Scope, TokenAuth};
+
+use common::proto::game_service::game_service_client::GameServiceClient;
+use common::proto::game_service::recorded_request::Request as Recorded;
+use common::proto::game_service::{EntityKind, PlayerInfo, QueryRequest, RecordedRequest};
+use common::{
+    PlayerId, WorldId, DEFAULT_GAME_PORT, WORLD_X_MAX, WORLD_X_MIN, WORLD_Y_MAX, WORLD_Y_MIN,
+    WORLD_Z_MAX, WORLD_Z_MIN,
+};
+
+use anyhow::{bail, Context, Result};
+use econf::LoadEnv;
+use prost::Message;
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Duration, Instant};
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::Channel;
+use tonic::{Request, Status};
+use tracing::*;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+/// # 请求重放
+/// 按录制的时间间隔把请求发给目标dispatcher，speed倍速，0为不等待。
+/// 同一玩家的请求按录制顺序串行，不同玩家并发；query/broadcast等不指定玩家的请求直接发出。
+/// 结束后全图query各世界，得到玩家最终状态，可保存下来作为基线，与之后的重放结果比较。
+/// 用于扩缩容改动的回归测试：同一录制分别在改动前后重放，最终状态应相同。
+#[derive(LoadEnv)]
+pub struct ReplayConfig {
+    pub addr: String,          // 目标dispatcher地址
+    pub path: String,          // 录制文件，见recorder
+    pub speed: f64,            // 重放速度倍数，1为原速，0为不等待尽快发出
+    pub auth_key: String,      // 目标开启认证时与之相同，重放的请求都带admin token
+    pub state_path: String,    // 重放结束后的玩家状态写入该文件，为空则不写
+    pub baseline_path: String, // 与之前保存的玩家状态比较，为空则不比较
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            addr: format!("http://127.0.0.1:{DEFAULT_GAME_PORT}"),
+            path: String::new(),
+            speed: 1.0,
+            auth_key: String::new(),
+            state_path: String::new(),
+            baseline_path: String::new(),
+        }
+    }
+}
+
+/// 玩家(含NPC)的最终状态
+pub type State = BTreeMap<PlayerId, PlayerInfo>;
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub requests: u64,
+    pub failed: u64, // 失败的请求数，录制时失败的请求重放时通常也失败
+    pub players: usize,
+    pub diffs: Vec<String>, // 与基线不同的玩家，每个一项
+}
+
+/// 重放目标，每个请求带上同一个token
+#[derive(Clone)]
+pub struct Target {
+    cli: GameServiceClient<Channel>,
+    token: Option<MetadataValue<Ascii>>,
+}
+
+impl Target {
+    pub async fn connect(addr: &str, auth_key: &str) -> Result<Self> {
+        let cli = GameServiceClient::connect(addr.to_string())
+            .await
+            .with_context(|| format!("Failed to connect {addr}"))?;
+        let token = if auth_key.is_empty() {
+            None
+        } else {
+            let token = TokenAuth::new(auth_key).issue(&Claims {
+                player_id: 0,
+                scope: Scope::Admin,
+                expire_at: 0,
+            });
+            Some(format!("Bearer {token}").parse()?)
+        };
+        Ok(Self { cli, token })
+    }
+
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(token) = &self.token {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        request
+    }
+
+    // 只关心成功与否，subscribe的事件流立即丢弃
+    async fn send(&self, request: Recorded) -> Result<(), Status> {
+        let mut cli = self.cli.clone();
+        match request {
+            Recorded::Login(r) => cli.login(self.request(r)).await.map(drop),
+            Recorded::Logout(r) => cli.logout(self.request(r)).await.map(drop),
+            Recorded::Aoe(r) => cli.aoe(self.request(r)).await.map(drop),
+            Recorded::Moving(r) => cli.moving(self.request(r)).await.map(drop),
+            Recorded::Query(r) => cli.query(self.request(r)).await.map(drop),
+            Recorded::Teleport(r) => cli.teleport(self.request(r)).await.map(drop),
+            Recorded::SetVelocity(r) => cli.set_velocity(self.request(r)).await.map(drop),
+            Recorded::Say(r) => cli.say(self.request(r)).await.map(drop),
+            Recorded::Subscribe(r) => cli.subscribe(self.request(r)).await.map(drop),
+            Recorded::Broadcast(r) => cli.broadcast(self.request(r)).await.map(drop),
+            Recorded::Heartbeat(r) => cli.heartbeat(self.request(r)).await.map(drop),
+            Recorded::SpawnNpc(r) => cli.spawn_npc(self.request(r)).await.map(drop),
+            Recorded::DespawnNpc(r) => cli.despawn_npc(self.request(r)).await.map(drop),
+            Recorded::ChangeWorld(r) => cli.change_world(self.request(r)).await.map(drop),
+            Recorded::GetScalingEvents(r) => {
+                cli.get_scaling_events(self.request(r)).await.map(drop)
+            }
+        }
+    }
+
+    /// 全图query各世界；导出中同时在两个server的玩家已由dispatcher去重
+    pub async fn snapshot(&self, worlds: impl IntoIterator<Item = WorldId>) -> Result<State> {
+        let mut state = State::new();
+        for world_id in worlds {
+            let infos = self
+                .cli
+                .clone()
+                .query(self.request(QueryRequest {
+                    xmin: WORLD_X_MIN,
+                    xmax: WORLD_X_MAX,
+                    ymin: WORLD_Y_MIN,
+                    ymax: WORLD_Y_MAX,
+                    world_id,
+                    zmin: WORLD_Z_MIN,
+                    zmax: WORLD_Z_MAX,
+                    areas: vec![],
+                }))
+                .await
+                .with_context(|| format!("Failed to query world:{world_id}"))?
+                .into_inner()
+                .infos;
+            state.extend(infos.into_iter().map(|info| (info.player_id, info)));
+        }
+        Ok(state)
+    }
+}
+
+// 指定玩家的请求返回player_id，按玩家串行
+fn player_of(request: &Recorded) -> Option<PlayerId> {
+    match request {
+        Recorded::Login(r) | Recorded::SpawnNpc(r) => Some(r.player_id),
+        Recorded::Logout(r)
+        | Recorded::Subscribe(r)
+        | Recorded::Heartbeat(r)
+        | Recorded::DespawnNpc(r) => Some(r.player_id),
+        Recorded::Aoe(r) => Some(r.player_id),
+        Recorded::Moving(r) => Some(r.player_id),
+        Recorded::Teleport(r) => Some(r.player_id),
+        Recorded::SetVelocity(r) => Some(r.player_id),
+        Recorded::Say(r) => Some(r.player_id),
+        Recorded::ChangeWorld(r) => Some(r.player_id),
+        Recorded::Query(_) | Recorded::Broadcast(_) | Recorded::GetScalingEvents(_) => None,
+    }
+}
+
+/// 录制中出现过的世界，最终状态只查询这些世界
+pub fn world_ids(records: &[RecordedRequest]) -> BTreeSet<WorldId> {
+    let mut worlds = BTreeSet::new();
+    for request in records.iter().filter_map(|record| record.request.as_ref()) {
+        match request {
+            Recorded::Login(r) | Recorded::SpawnNpc(r) => worlds.insert(r.world_id),
+            Recorded::Logout(r)
+            | Recorded::Subscribe(r)
+            | Recorded::Heartbeat(r)
+            | Recorded::DespawnNpc(r) => worlds.insert(r.world_id),
+            Recorded::Aoe(r) => worlds.insert(r.world_id),
+            Recorded::Moving(r) => worlds.insert(r.world_id),
+            Recorded::Teleport(r) => worlds.insert(r.world_id),
+            Recorded::SetVelocity(r) => worlds.insert(r.world_id),
+            Recorded::Say(r) => worlds.insert(r.world_id),
+            Recorded::ChangeWorld(r) => worlds.insert(r.target_world_id),
+            Recorded::Query(r) => worlds.insert(r.world_id),
+            Recorded::Broadcast(r) => worlds.insert(r.world_id),
+            Recorded::GetScalingEvents(r) => worlds.insert(r.world_id),
+        };
+    }
+    worlds
+}
+
+/// 按录制的时间间隔除以speed发出，等待所有请求完成；返回(请求数, 失败数)
+pub async fn replay(target: &Target, records: Vec<RecordedRequest>, speed: f64) -> (u64, u64) {
+    let start = Instant::now();
+    let first_ms = records.first().map_or(0, |record| record.time_ms);
+    let mut players: HashMap<PlayerId, mpsc::UnboundedSender<Recorded>> = HashMap::new();
+    let mut tasks = vec![];
+    let mut requests = 0;
+    for record in records {
+        let Some(request) = record.request else {
+            continue;
+        };
+        if speed > 0.0 {
+            let offset = record.time_ms.saturating_sub(first_ms) as f64 / speed;
+            sleep_until(start + Duration::from_secs_f64(offset / 1000.0)).await;
+        }
+        requests += 1;
+        let Some(player_id) = player_of(&request) else {
+            let target = target.clone();
+            tasks.push(tokio::spawn(async move {
+                u64::from(target.send(request).await.is_err())
+            }));
+            continue;
+        };
+        let tx = players.entry(player_id).or_insert_with(|| {
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            let target = target.clone();
+            tasks.push(tokio::spawn(async move {
+                let mut failed = 0;
+                while let Some(request) = rx.recv().await {
+                    if let Err(status) = target.send(request).await {
+                        debug!(?player_id, ?status);
+                        failed += 1;
+                    }
+                }
+                failed
+            }));
+            tx
+        });
+        tx.send(request).unwrap();
+    }
+    // 关闭各玩家的channel，等待发完
+    drop(players);
+    let mut failed = 0;
+    for task in tasks {
+        failed += task.await.unwrap_or(1);
+    }
+    (requests, failed)
+}
+
+/// 读取录制文件，按写入顺序返回
+pub fn load_records(path: &str) -> Result<Vec<RecordedRequest>> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {path}"))?;
+    decode_records(&bytes).with_context(|| format!("Invalid record file {path}"))
+}
+
+pub fn decode_records(mut bytes: &[u8]) -> Result<Vec<RecordedRequest>> {
+    let total = bytes.len();
+    let mut records = vec![];
+    while !bytes.is_empty() {
+        let offset = total - bytes.len();
+        let record = RecordedRequest::decode_length_delimited(&mut bytes)
+            .with_context(|| format!("record {} at offset {offset}", records.len()))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+pub async fn run(config: &ReplayConfig) -> Result<Report> {
+    let records = load_records(&config.path)?;
+    let worlds = world_ids(&records);
+    let target = Target::connect(&config.addr, &config.auth_key).await?;
+    info!(records = records.len(), ?worlds, "replaying");
+
+    let (requests, failed) = replay(&target, records, config.speed).await;
+    let state = target.snapshot(worlds).await?;
+    if !config.state_path.is_empty() {
+        std::fs::write(&config.state_path, format_state(&state))
+            .with_context(|| format!("Failed to write {}", config.state_path))?;
+    }
+    let diffs = if config.baseline_path.is_empty() {
+        vec![]
+    } else {
+        let text = std::fs::read_to_string(&config.baseline_path)
+            .with_context(|| format!("Failed to read {}", config.baseline_path))?;
+        let baseline = parse_state(&text)
+            .with_context(|| format!("Invalid state file {}", config.baseline_path))?;
+        diff(&baseline, &state)
+    };
+    Ok(Report {
+        requests,
+        failed,
+        players: state.len(),
+        diffs,
+    })
+}
+
+fn format_player(p: &PlayerInfo) -> String {
+    format!(
+        "{} {} {} {} {} {} {}",
+        p.player_id,
+        p.world_id,
+        p.x,
+        p.y,
+        p.z,
+        p.money,
+        p.kind().as_str_name()
+    )
+}
+
+/// 每行一个玩家：`<player_id> <world_id> <x> <y> <z> <money> <kind>`，按player_id排序
+pub fn format_state(state: &State) -> String {
+    state.values().map(|p| format_player(p) + "\n").collect()
+}
+
+pub fn parse_state(text: &str) -> Result<State> {
+    let mut state = State::new();
+    for (no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let parsed = (|| -> Result<_> {
+            let [player_id, world_id, x, y, z, money, kind] = fields[..] else {
+                bail!("expected 7 fields");
+            };
+            let kind = EntityKind::from_str_name(kind).context("unknown kind")?;
+            Ok(PlayerInfo {
+                player_id: player_id.parse()?,
+                world_id: world_id.parse()?,
+                x: x.parse()?,
+                y: y.parse()?,
+                z: z.parse()?,
+                money: money.parse()?,
+                kind: kind.into(),
+                ..Default::default()
+            })
+        })()
+        .with_context(|| format!("line {}: {line:?}", no + 1))?;
+        state.insert(parsed.player_id, parsed);
+    }
+    Ok(state)
+}
+
+/// 比较世界、坐标、钱数与类型，速度不比较
+pub fn diff(baseline: &State, actual: &State) -> Vec<String> {
+    let mut diffs = vec![];
+    for (player_id, expected) in baseline {
+        match actual.get(player_id) {
+            None => diffs.push(format!("- {}", format_player(expected))),
+            Some(p) if format_player(p) != format_player(expected) => {
+                diffs.push(format!(
+                    "- {}\n+ {}",
+                    format_player(expected),
+                    format_player(p)
+                ));
+            }
+            Some(_) => {}
+        }
+    }
+    for (player_id, p) in actual {
+        if !baseline.contains_key(player_id) {
+            diffs.push(format!("+ {}", format_player(p)));
+        }
+    }
+    diffs
+}
+
+/// 第一行为汇总，之后为与基线不同的玩家，`-`为基线、`+`为本次
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "# requests:{} failed:{} players:{} diffs:{}",
+            self.requests,
+            self.failed,
+            self.players,
+            self.diffs.len()
+        )?;
+        for line in &self.diffs {
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use crate::data::ServerInfo;
+use crate::util::{now_ms, Config};
+
+use common::proto::game_service::{ScalingEvent, ScalingEventKind, ScalingEventsRequest};
+use common::{ErrHandle, ZoneId};
+
+use anyhow::{Context, Result};
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+/// # 扩缩容事件日志
+/// leader扩缩容时记录结构化的事件，内存中保留最近capacity条，配置了文件时另外逐行追加。
+/// 文件不轮转，只追加；内存中的事件在重启或换leader后不保留。
+pub struct ScalingLog {
+    capacity: usize,
+    ring: Mutex<Ring>,
+    sink: Option<Mutex<File>>,
+}
+
+#[derive(Default)]
+struct Ring {
+    next_seq: u64,
+    events: VecDeque<ScalingEvent>,
+    totals: ScalingTotals,
+}
+
+/// 启动以来的累计，按记录的事件统计，不受capacity限制
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScalingTotals {
+    pub expands: u64,
+    pub merges: u64,
+    pub failures: u64,
+    pub transferred: u64, // 扩缩容转移的人数
+}
+
+// 以server为主体的事件，zone默认为该server负责的
+pub fn event(kind: ScalingEventKind, server: &ServerInfo) -> ScalingEvent {
+    ScalingEvent {
+        kind: kind.into(),
+        world_id: server.world_id,
+        server_ids: vec![server.server_id],
+        zone_ids: server.zones.iter().map(|&id| id.into()).collect(),
+        ..Default::default()
+    }
+}
+
+impl ScalingLog {
+    pub fn new(config: &Config) -> Result<Self> {
+        let sink = if config.scaling_log_path.is_empty() {
+            None
+        } else {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&config.scaling_log_path)
+                .with_context(|| format!("Failed to open {}", config.scaling_log_path))?;
+            Some(Mutex::new(file))
+        };
+        Ok(Self {
+            capacity: config.scaling_log_capacity,
+            ring: Mutex::default(),
+            sink,
+        })
+    }
+
+    /// 填上seq与时间后保存，超出容量时丢弃最早的；写文件失败只记录日志
+    pub fn record(&self, mut event: ScalingEvent) {
+        let mut ring = self.ring.lock().unwrap();
+        event.seq = ring.next_seq;
+        event.time_ms = now_ms();
+        ring.next_seq += 1;
+        match event.kind() {
+            ScalingEventKind::ExpandFinished => ring.totals.expands += 1,
+            ScalingEventKind::MergeFinished => ring.totals.merges += 1,
+            ScalingEventKind::ScalingFailed => ring.totals.failures += 1,
+            ScalingEventKind::PlayersTransferred => {
+                ring.totals.transferred += event.player_count as u64
+            }
+            _ => {}
+        }
+        // 持有ring锁写文件，文件中的顺序与seq一致
+        if let Some(sink) = &self.sink {
+            let _ = writeln!(sink.lock().unwrap(), "{}", format_event(&event)).log_err();
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        if ring.events.len() == self.capacity {
+            ring.events.pop_front();
+        }
+        ring.events.push_back(event);
+    }
+
+    pub fn totals(&self) -> ScalingTotals {
+        self.ring.lock().unwrap().totals
+    }
+
+    /// 按时间先后返回满足条件的事件，有limit时取最近的
+    pub fn query(&self, filter: &ScalingEventsRequest, zones: &[ZoneId]) -> Vec<ScalingEvent> {
+        let ring = self.ring.lock().unwrap();
+        let mut events = ring
+            .events
+            .iter()
+            .filter(|event| matches(event, filter, zones))
+            .cloned()
+            .collect::<Vec<_>>();
+        let limit = filter.limit as usize;
+        if limit > 0 && events.len() > limit {
+            events.drain(..events.len() - limit);
+        }
+        events
+    }
+}
+
+fn matches(event: &ScalingEvent, filter: &ScalingEventsRequest, zones: &[ZoneId]) -> bool {
+    event.world_id == filter.world_id
+        && (filter.server_ids.is_empty()
+            || event
+                .server_ids
+                .iter()
+                .any(|id| filter.server_ids.contains(id)))
+        && (zones.is_empty()
+            || event
+                .zone_ids
+                .iter()
+                .filter_map(|&raw| ZoneId::from_raw(raw))
+                .any(|zone_id| zones.iter().any(|&f| is_related(zone_id, f))))
+        && event.time_ms >= filter.start_ms
+        && (filter.end_ms == 0 || event.time_ms < filter.end_ms)
+}
+
+// 同一棵树上互为祖先、子孙(含相等)
+fn is_related(a: ZoneId, b: ZoneId) -> bool {
+    if a.dimension() != b.dimension() {
+        return false;
+    }
+    let depth = a.depth().min(b.depth());
+    a.ancestor(depth) == b.ancestor(depth)
+}
+
+// 文件中每行一条，空格分隔，便于grep
+fn format_event(event: &ScalingEvent) -> String {
+    let zones = event
+        .zone_ids
+        .iter()
+        .map(|&raw| ZoneId::from_raw(raw).map_or_else(|| format!("{raw:#x}"), |id| id.to_string()))
+        .collect::<Vec<_>>();
+    format!(
+        "{} #{} {:?} world:{} servers:{:?} zones:[{}] players:{} duration_ms:{} {}",
+        event.time_ms,
+        event.seq,
+        event.kind(),
+        event.world_id,
+        event.server_ids,
+        zones.join(","),
+        event.player_count,
+        event.duration_ms,
+        event.message
+    )
+    .trim_end()
+    .to_string()
+}
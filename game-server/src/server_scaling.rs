@@ -1,18 +1,22 @@
 use crate::data::*;
 use crate::dispatcher::Dispatcher;
+use crate::dispatcher_service::server_entry;
+use crate::scaling_log::event;
 use crate::util::*;
 
-use common::proto::game_service::QueryRequest;
+use common::proto::dispatcher_service::TransferRequest;
+use common::proto::game_service::{QueryRequest, ScalingEvent, ScalingEventKind};
 use common::proto::map_service::{ExportRequest, GetPlayersRequest, ZoneDepth, ZonePlayersReply};
 use common::*;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use ert::prelude::RunVia;
 use futures::StreamExt;
 use tonic::async_trait;
 use tracing::*;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
 
 #[async_trait]
 pub trait ServerScaling {
@@ -33,13 +37,16 @@ pub trait ServerScaling {
         target_server: &ServerInfo,
         players: &[PlayerId],
     ) -> Result<()>;
+    /// 接管leader时，完成上任leader中断的导出
+    async fn finish_exports(&self) -> Result<()>;
 }
 
 /// API级别不能保证并发原子，应避免多个线程同时调用，以下API仅在monitor单线程中使用
+/// 只在leader运行，转移玩家前和完成后把拓扑推送给其他dispatcher
 #[async_trait]
 impl ServerScaling for Dispatcher {
     /// 管理多个同父叶子节点时，挑出最大的扩容
-    /// 只管理一个叶子节点时，深度+1，分出4个叶子结点，把最大的分配到新服务器
+    /// 只管理一个叶子节点时，深度+1，分出4个(3D为8个)叶子结点，把最大的分配到新服务器
     /// 只有一个节点且达最大深度则无法扩展，直接返回false
     /// 1. 新旧服务器同时注册到要导出的zone(server + exporting_server)
     /// 2. 用户导出(此时对于该zone的范围请求(aoe/query)会送到两台服务器)
@@ -47,7 +54,9 @@ impl ServerScaling for Dispatcher {
     #[instrument(skip_all, fields(server_id = %server.server_id, zones = ?server.zones))]
     async fn expand_overload_server(&self, server: &ServerInfo) -> Result<bool> {
         info!("IN");
-        let mut depth = zone_depth(server.zones[0]);
+        let started_at = Instant::now();
+        let zone_server_map = self.zone_server_map(server.world_id)?;
+        let mut depth = server.zones[0].depth();
         let only_one_zone = server.zones.len() == 1;
         if only_one_zone && depth == self.config.max_zone_depth {
             info!(
@@ -57,7 +66,7 @@ impl ServerScaling for Dispatcher {
             return Ok(false);
         }
         if only_one_zone {
-            // 只管理一个叶子节点时，深度+1，分出4个叶子结点
+            // 只管理一个叶子节点时，深度+1，分出4个(3D为8个)叶子结点
             depth += 1;
         };
         // 从server拆分出人数最多的zone。
@@ -71,22 +80,38 @@ impl ServerScaling for Dispatcher {
             .get_heaviest_zone_players(ZoneDepth { depth })
             .await?
             .into_inner();
+        let new_zone_id = ZoneId::from_raw(new_zone_id)
+            .with_context(|| format!("Invalid zone_id:{new_zone_id:#x}"))?;
+        self.scaling_log.record(ScalingEvent {
+            player_count: player_ids.len() as u32,
+            ..event(ScalingEventKind::ExpandStarted, server)
+        });
         // 启动一台新server
-        let new_server = start_map_server(vec![new_zone_id]).await?;
+        let new_server = start_map_server(
+            server.world_id,
+            server.dimension,
+            vec![new_zone_id],
+            &self.config,
+        )
+        .await?;
         // 将导出server和导入server都注册到zone
-        self.zone_server_map.insert(
+        zone_server_map.insert(
             new_zone_id,
             ZoneServers {
                 server: new_server.clone(),
                 exporting_server: Some(server.clone()),
             },
         );
+        self.scaling_log.record(ScalingEvent {
+            server_ids: vec![server.server_id, new_server.server_id],
+            zone_ids: vec![new_zone_id.into()],
+            ..event(ScalingEventKind::ZoneRegistered, server)
+        });
         // 获得原server拆分后剩下的zone
         let zones: Vec<_> = if only_one_zone {
-            // 只有一个zone时，先注销原节点，拆分成4个叶子节点，去掉导出的
-            self.zone_server_map.remove(&server.zones[0]);
-            get_child_zone_ids(server.zones[0])
-                .into_iter()
+            // 只有一个zone时，拆分成叶子节点，去掉导出的
+            server.zones[0]
+                .children()
                 .filter(|id| id != &new_zone_id)
                 .collect()
         } else {
@@ -108,19 +133,29 @@ impl ServerScaling for Dispatcher {
         };
         // 重新注册原server拆分剩下的zone
         zones.into_iter().for_each(|zone_id| {
-            self.zone_server_map.insert(zone_id, update_server.clone());
+            zone_server_map.insert(zone_id, update_server.clone());
         });
+        // 叶子都注册后再注销原节点，期间按范围查找不会落空
+        if only_one_zone {
+            zone_server_map.remove(&server.zones[0]);
+        }
+        self.publish_topology().await;
 
         let AABB {
             xmin,
             xmax,
             ymin,
             ymax,
-        } = AABB::from_zone_id(new_zone_id);
+            zmin,
+            zmax,
+        } = new_zone_id.bounds();
+        let mut transferred = 0;
         while !player_ids.is_empty() {
             // 用户导出。loop transfer_players直至该zone无人为止
+            self.ensure_leader().await?;
             self.transfer_players(server, &new_server, &player_ids)
                 .await?;
+            transferred += player_ids.len() as u32;
             player_ids = server
                 .game_cli
                 .clone()
@@ -129,6 +164,9 @@ impl ServerScaling for Dispatcher {
                     xmax,
                     ymin,
                     ymax,
+                    zmin,
+                    zmax,
+                    ..Default::default()
                 })
                 .await?
                 .into_inner()
@@ -139,13 +177,21 @@ impl ServerScaling for Dispatcher {
         }
 
         // 完成后取消exporting_server设置
-        self.zone_server_map.insert(
+        zone_server_map.insert(
             new_zone_id,
             ZoneServers {
                 server: new_server.clone(),
                 exporting_server: None,
             },
         );
+        self.publish_topology().await;
+        self.scaling_log.record(ScalingEvent {
+            server_ids: vec![server.server_id, new_server.server_id],
+            zone_ids: vec![new_zone_id.into()],
+            player_count: transferred,
+            duration_ms: started_at.elapsed().as_millis() as u64,
+            ..event(ScalingEventKind::ExpandFinished, server)
+        });
 
         info!("OUT");
         Ok(true)
@@ -159,17 +205,20 @@ impl ServerScaling for Dispatcher {
         overhead: u32,
         overhead_map: &HashMap<ServerId, u32>,
     ) -> Result<Option<ServerInfo>> {
-        if server.zones[0] == ROOT_ZONE_ID {
+        if server.zones[0].is_root() {
             info!("Skip merge root zone");
             return Ok(None);
         }
 
         // 获取同父其它叶子节点的最闲服务器
-        let Some((bro_overhead, bro_server)) = get_child_zone_ids(server.zones[0] / 10)
-            .into_iter()
+        let zone_server_map = self.zone_server_map(server.world_id)?;
+        let Some((bro_overhead, bro_server)) = server.zones[0]
+            .parent()
+            .unwrap()
+            .children()
             .filter(|id| !server.zones.contains(id))
             .filter_map(|id| {
-                self.zone_server_map.get(&id).map(|entry| {
+                zone_server_map.get(&id).map(|entry| {
                     let server = entry.value().server.clone();
                     (server.server_id, server)
                 })
@@ -201,7 +250,14 @@ impl ServerScaling for Dispatcher {
     #[instrument(skip_all, fields(server_id = %server.server_id, export_to = %export_to.server_id))]
     async fn close_idle_server(&self, server: &ServerInfo, export_to: &ServerInfo) -> Result<()> {
         info!("IN");
-        const FULL_LEAVES: usize = 4; // 满叶子结点为4个
+        let started_at = Instant::now();
+        let server_ids = vec![server.server_id, export_to.server_id];
+        self.scaling_log.record(ScalingEvent {
+            server_ids: server_ids.clone(),
+            ..event(ScalingEventKind::MergeStarted, server)
+        });
+        let full_leaves = server.dimension.children() as usize; // 满叶子结点为4个，3D为8个
+        let zone_server_map = self.zone_server_map(server.world_id)?;
 
         let mut export_inner = (*export_to.inner).clone();
         export_inner.zones.append(&mut server.zones.clone());
@@ -210,7 +266,7 @@ impl ServerScaling for Dispatcher {
         };
         // 将关闭server和接收server都注册到zone
         server.zones.iter().for_each(|zone_id| {
-            self.zone_server_map.insert(
+            zone_server_map.insert(
                 *zone_id,
                 ZoneServers {
                     server: exported_server.clone(),
@@ -220,7 +276,7 @@ impl ServerScaling for Dispatcher {
         });
         // 更新接收server的原本zone的服务器信息
         export_to.zones.iter().for_each(|zone_id| {
-            self.zone_server_map.insert(
+            zone_server_map.insert(
                 *zone_id,
                 ZoneServers {
                     server: exported_server.clone(),
@@ -228,8 +284,15 @@ impl ServerScaling for Dispatcher {
                 },
             );
         });
+        self.publish_topology().await;
+        self.scaling_log.record(ScalingEvent {
+            server_ids: server_ids.clone(),
+            ..event(ScalingEventKind::ZoneRegistered, server)
+        });
         // 用户导出
+        let mut transferred = 0;
         loop {
+            self.ensure_leader().await?;
             let players = server
                 .map_cli
                 .clone()
@@ -242,15 +305,20 @@ impl ServerScaling for Dispatcher {
             if players.is_empty() {
                 break;
             }
-            self.transfer_players(server, export_to, &players).await?;
+            // 以注册后的导入方转移，事件中的zone含导出的
+            self.transfer_players(server, &exported_server, &players)
+                .await?;
+            transferred += players.len() as u32;
         }
 
-        if exported_server.zones.len() == FULL_LEAVES {
-            // 4个叶子在同一个server，合并成父节点。先插父节点，再删叶子
+        let merged_zones;
+        if exported_server.zones.len() == full_leaves {
+            // 叶子都在同一个server，合并成父节点。先插父节点，再删叶子
             let zones = exported_server.zones.clone();
-            export_inner.zones = vec![zones[0] / 10];
-            self.zone_server_map.insert(
-                zones[0] / 10,
+            let parent = zones[0].parent().unwrap();
+            export_inner.zones = vec![parent];
+            zone_server_map.insert(
+                parent,
                 ZoneServers {
                     server: ServerInfo {
                         inner: export_inner.into(),
@@ -259,12 +327,13 @@ impl ServerScaling for Dispatcher {
                 },
             );
             zones.iter().for_each(|zone_id| {
-                self.zone_server_map.remove(zone_id);
+                zone_server_map.remove(zone_id);
             });
+            merged_zones = vec![parent];
         } else {
             // 完成后取消exporting_server设置
             server.zones.iter().for_each(|zone_id| {
-                self.zone_server_map.insert(
+                zone_server_map.insert(
                     *zone_id,
                     ZoneServers {
                         server: exported_server.clone(),
@@ -272,23 +341,121 @@ impl ServerScaling for Dispatcher {
                     },
                 );
             });
+            merged_zones = server.zones.clone();
         };
+        self.publish_topology().await;
 
         shutdown_map_server(server).await?;
+        self.scaling_log.record(ScalingEvent {
+            server_ids,
+            zone_ids: merged_zones.into_iter().map(Into::into).collect(),
+            player_count: transferred,
+            duration_ms: started_at.elapsed().as_millis() as u64,
+            ..event(ScalingEventKind::MergeFinished, server)
+        });
 
         info!("OUT");
         Ok(())
     }
 
+    /// 还有exporting_server的zone，把导出方在该zone的玩家转移完再取消注册
+    /// 导出方已不负责任何zone的(缩容中断)，转移完后关闭
+    #[instrument(skip_all)]
+    async fn finish_exports(&self) -> Result<()> {
+        let mut exported = HashMap::new();
+        for world in self.worlds.values() {
+            for entry in world.zone_server_map.iter() {
+                let zone_id = *entry.key();
+                let ZoneServers {
+                    server,
+                    exporting_server: Some(exporting_server),
+                } = entry.value().clone() else {
+                    continue;
+                };
+                info!(%zone_id, from = exporting_server.server_id, to = server.server_id);
+                let AABB {
+                    xmin,
+                    xmax,
+                    ymin,
+                    ymax,
+                    zmin,
+                    zmax,
+                } = zone_id.bounds();
+                loop {
+                    self.ensure_leader().await?;
+                    let player_ids: Vec<_> = exporting_server
+                        .game_cli
+                        .clone()
+                        .query(QueryRequest {
+                            xmin,
+                            xmax,
+                            ymin,
+                            ymax,
+                            zmin,
+                            zmax,
+                            world_id: exporting_server.world_id,
+                            areas: vec![],
+                        })
+                        .await?
+                        .into_inner()
+                        .infos
+                        .into_iter()
+                        .map(|info| info.player_id)
+                        .collect();
+                    if player_ids.is_empty() {
+                        break;
+                    }
+                    self.transfer_players(&exporting_server, &server, &player_ids)
+                        .await?;
+                }
+                world.zone_server_map.insert(
+                    zone_id,
+                    ZoneServers {
+                        server,
+                        exporting_server: None,
+                    },
+                );
+                exported.insert(exporting_server.server_id, exporting_server);
+            }
+        }
+        let remaining = self
+            .get_all_servers()
+            .into_iter()
+            .map(|server| server.server_id)
+            .collect::<HashSet<_>>();
+        for server in exported.values() {
+            if !remaining.contains(&server.server_id) {
+                shutdown_map_server(server).await?;
+            }
+        }
+        Ok(())
+    }
+
     // 把player_id取来，逐个让map-server导出。这里要用ert将用户串行，避免与game api数据竞争
+    // 归其他dispatcher的玩家由其所属dispatcher导出，不能在本地串行队列中请求，以免同进程内相互等待
+    // 记录的事件中zone为导入方负责的
     async fn transfer_players(
         &self,
         source_server: &ServerInfo,
         target_server: &ServerInfo,
         players: &[PlayerId],
     ) -> Result<()> {
+        let started_at = Instant::now();
         futures::stream::iter(players)
             .for_each_concurrent(None, |&player_id| async move {
+                if let Some(peer) = self.cluster.remote_owner(player_id) {
+                    let _ = peer
+                        .dispatcher_cli
+                        .clone()
+                        .transfer_player(TransferRequest {
+                            player_id,
+                            source_server_id: source_server.server_id,
+                            target: Some(server_entry(target_server)),
+                        })
+                        .await
+                        .log_err();
+                    return;
+                }
                 let mut cli = source_server.map_cli.clone();
                 let target = target_server.clone();
                 let self = self.clone();
@@ -297,11 +464,12 @@ impl ServerScaling for Dispatcher {
                         player_id,
                         addr: target.addr.clone(),
                         coord: None,
+                        world: None,
                     })
                     .await
                     .map_err(anyhow::Error::msg)?;
-                    let (_, x, y) = self.get_server_of_player(&player_id)?;
-                    self.player_map.insert(player_id, (target, x, y));
+                    let (_, x, y, z) = self.get_server_of_player(&player_id)?;
+                    self.player_map.insert(player_id, (target, x, y, z));
                     Result::<(), anyhow::Error>::Ok(())
                 }
                 .via_g(player_id)
@@ -315,6 +483,12 @@ impl ServerScaling for Dispatcher {
             source_server.server_id,
             target_server.server_id
         );
+        self.scaling_log.record(ScalingEvent {
+            server_ids: vec![source_server.server_id, target_server.server_id],
+            player_count: players.len() as u32,
+            duration_ms: started_at.elapsed().as_millis() as u64,
+            ..event(ScalingEventKind::PlayersTransferred, target_server)
+        });
         Ok(())
     }
 }
@@ -0,0 +1,266 @@
+pub mod distribution;
+
+use crate::dispatcher::Dispatcher;
+use crate::util::Config;
+use distribution::{Action, Distribution, Pattern, Synthetic, Trace};
+
+use common::proto::game_service::game_service_server::GameService;
+use common::proto::game_service::{PlayerIdRequest, PlayerInfo, TeleportRequest};
+use common::{PlayerId, ServerId};
+
+use anyhow::Result;
+use econf::LoadEnv;
+use futures::StreamExt;
+use tonic::IntoRequest;
+use tracing::*;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// # 扩缩容模拟器
+/// 按模拟时间驱动dispatcher的拓扑与扩缩容逻辑，不等待真实的scaling_interval，用于比较扩缩容参数。
+/// map-server以内部对象启动(map_server_inside)，是真实的MapServer而不是假实现，经loopback gRPC访问，
+/// 转移玩家与正式运行相同；dispatcher只通过gRPC客户端访问map-server，不另外抽象出接口。玩家分布为合成的或录制的。
+/// 每经过scaling_interval(模拟时间)执行一轮扩缩容，并记录server数、转移人数、负载不均衡度。
+#[derive(Debug, LoadEnv)]
+pub struct SimConfig {
+    pub duration: u64,      // 模拟时长(ms)，录制分布为0时到最后一行为止
+    pub step: u64,          // 玩家分布的更新间隔(ms)
+    pub players: u32,       // 合成分布的人数峰值，前半段线性增加，后半段线性减少
+    pub pattern: String,    // 合成分布：uniform均匀随机游走，hotspot聚集在漂移的热点附近
+    pub hotspots: u32,      // 热点个数
+    pub area: f32,          // 玩家分布在[-area, area]的正方形内
+    pub speed: f32,         // 玩家、热点每秒移动距离
+    pub seed: u64,          // 随机种子，相同参数结果可重复
+    pub trace_path: String, // 录制的分布，格式见Trace；非空时不用合成分布
+    pub concurrency: usize, // 每步同时请求的玩家数
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            duration: 600_000,
+            step: 5000,
+            players: 2000,
+            pattern: "hotspot".to_string(),
+            hotspots: 3,
+            area: 100_000.0,
+            speed: 100.0,
+            seed: 0,
+            trace_path: String::new(),
+            concurrency: 64,
+        }
+    }
+}
+
+/// 每轮扩缩容后的状态
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub t_ms: u64,
+    pub players: usize,
+    pub servers: usize,
+    pub transferred: u64,  // 本轮扩缩容转移的人数
+    pub imbalance: f64,    // 人数最多的server与平均人数之比，1为完全均衡
+    pub overloaded: usize, // 人数超过max_players的server数
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub samples: Vec<Sample>,
+    pub expands: u64,
+    pub merges: u64,
+    pub failures: u64,        // 扩缩容失败次数
+    pub transferred: u64,     // 扩缩容转移的总人数，不含玩家自己跨server移动
+    pub failed_requests: u64, // 玩家请求失败次数，例如登录到正在关闭的server
+    scaling_interval: u64,
+}
+
+pub async fn run(config: Config, sim: &SimConfig) -> Result<Report> {
+    let (mut distribution, duration): (Box<dyn Distribution>, _) = if sim.trace_path.is_empty() {
+        let pattern = sim.pattern.parse::<Pattern>()?;
+        (Box::new(Synthetic::new(pattern, sim)?), sim.duration)
+    } else {
+        let trace = Trace::load(&sim.trace_path)?;
+        let duration = if sim.duration == 0 {
+            trace.end_ms()
+        } else {
+            sim.duration
+        };
+        (Box::new(trace), duration)
+    };
+    let dispatcher = Dispatcher::new(config).await?;
+    let interval = dispatcher.config.scaling_interval.max(1);
+    let step = sim.step.max(1);
+    let mut report = Report {
+        scaling_interval: interval,
+        ..Default::default()
+    };
+
+    let mut next_round = interval;
+    let mut t = 0;
+    loop {
+        let actions = distribution.step(t);
+        report.failed_requests += apply(&dispatcher, actions, sim.concurrency).await;
+        // 一步跨过多轮时连续执行
+        while next_round <= t {
+            dispatcher.scaling_round().await;
+            report.sample(next_round, &dispatcher);
+            next_round += interval;
+        }
+        if t >= duration {
+            break;
+        }
+        t = (t + step).min(duration);
+    }
+    dispatcher.shutdown_all_map_server().await;
+    Ok(report)
+}
+
+// 同一玩家的请求按顺序执行，不同玩家并发；返回失败的请求数
+async fn apply(dispatcher: &Dispatcher, actions: Vec<Action>, concurrency: usize) -> u64 {
+    let mut player_actions: BTreeMap<PlayerId, Vec<Action>> = BTreeMap::new();
+    for action in actions {
+        let player_id = match action {
+            Action::Login(player_id, ..)
+            | Action::Move(player_id, ..)
+            | Action::Logout(player_id) => player_id,
+        };
+        player_actions.entry(player_id).or_default().push(action);
+    }
+    futures::stream::iter(player_actions.into_values())
+        .map(|actions| async move {
+            let mut failed = 0;
+            for action in actions {
+                let res = match action {
+                    Action::Login(player_id, x, y) => dispatcher
+                        .login(
+                            PlayerInfo {
+                                player_id,
+                                x,
+                                y,
+                                ..Default::default()
+                            }
+                            .into_request(),
+                        )
+                        .await
+                        .map(drop),
+                    Action::Move(player_id, x, y) => dispatcher
+                        .teleport(
+                            TeleportRequest {
+                                player_id,
+                                x,
+                                y,
+                                ..Default::default()
+                            }
+                            .into_request(),
+                        )
+                        .await
+                        .map(drop),
+                    Action::Logout(player_id) => dispatcher
+                        .logout(
+                            PlayerIdRequest {
+                                player_id,
+                                ..Default::default()
+                            }
+                            .into_request(),
+                        )
+                        .await
+                        .map(drop),
+                };
+                if let Err(status) = res {
+                    debug!(?action, ?status);
+                    failed += 1;
+                }
+            }
+            failed
+        })
+        .buffer_unordered(concurrency.max(1))
+        .fold(0, |sum, failed| async move { sum + failed })
+        .await
+}
+
+impl Report {
+    fn sample(&mut self, t_ms: u64, dispatcher: &Dispatcher) {
+        // 用累计值，不受scaling_log_capacity与世界的限制
+        let totals = dispatcher.scaling_log.totals();
+        let transferred = totals.transferred - self.transferred;
+        self.expands = totals.expands;
+        self.merges = totals.merges;
+        self.failures = totals.failures;
+        self.transferred = totals.transferred;
+
+        let mut counts: HashMap<ServerId, usize> = dispatcher
+            .get_all_servers()
+            .into_iter()
+            .map(|server| (server.server_id, 0))
+            .collect();
+        for entry in dispatcher.player_map.iter() {
+            *counts.entry(entry.value().0.server_id).or_default() += 1;
+        }
+        let players = dispatcher.player_map.len();
+        let servers = counts.len();
+        let max = counts.values().copied().max().unwrap_or_default();
+        let imbalance = if players == 0 {
+            1.0
+        } else {
+            max as f64 * servers as f64 / players as f64
+        };
+        let overloaded = counts
+            .values()
+            .filter(|&&count| count > dispatcher.config.max_players as usize)
+            .count();
+        let sample = Sample {
+            t_ms,
+            players,
+            servers,
+            transferred,
+            imbalance,
+            overloaded,
+        };
+        info!(?sample);
+        self.samples.push(sample);
+    }
+}
+
+/// 每轮一行csv，最后一行以`#`开头为汇总
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "t_s,players,servers,transferred,imbalance,overloaded")?;
+        for sample in &self.samples {
+            writeln!(
+                f,
+                "{:.1},{},{},{},{:.2},{}",
+                sample.t_ms as f64 / 1000.0,
+                sample.players,
+                sample.servers,
+                sample.transferred,
+                sample.imbalance,
+                sample.overloaded
+            )?;
+        }
+        let rounds = self.samples.len().max(1) as f64;
+        let server_rounds = self.samples.iter().map(|s| s.servers).sum::<usize>() as f64;
+        let loaded = self
+            .samples
+            .iter()
+            .filter(|s| s.players > 0)
+            .map(|s| s.imbalance)
+            .collect::<Vec<_>>();
+        writeln!(
+            f,
+            "# rounds:{} peak_servers:{} avg_servers:{:.2} server_time_s:{:.1} expands:{} merges:{} failures:{} transferred:{} avg_imbalance:{:.2} max_imbalance:{:.2} overloaded_rounds:{} failed_requests:{}",
+            self.samples.len(),
+            self.samples.iter().map(|s| s.servers).max().unwrap_or_default(),
+            server_rounds / rounds,
+            server_rounds * self.scaling_interval as f64 / 1000.0,
+            self.expands,
+            self.merges,
+            self.failures,
+            self.transferred,
+            loaded.iter().sum::<f64>() / loaded.len().max(1) as f64,
+            loaded.iter().copied().fold(1.0, f64::max),
+            self.samples.iter().filter(|s| s.overloaded > 0).count(),
+            self.failed_requests
+        )
+    }
+}
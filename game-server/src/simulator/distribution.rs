@@ -0,0 +1,279 @@
+use super::SimConfig;
+
+use common::{PlayerId, WORLD_X_MAX, WORLD_X_MIN, WORLD_Y_MAX, WORLD_Y_MIN};
+
+use anyhow::{bail, ensure, Context, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+// 离世界边界留出的距离，坐标不会越界
+const MARGIN: f32 = 1.0;
+
+/// 一个模拟步内对dispatcher发出的请求
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Login(PlayerId, f32, f32),
+    Move(PlayerId, f32, f32), // 绝对坐标，按teleport发出
+    Logout(PlayerId),
+}
+
+/// 玩家分布随模拟时间的变化
+pub trait Distribution: Send {
+    /// 推进到t_ms(模拟时间)，返回这一步的请求
+    fn step(&mut self, t_ms: u64) -> Vec<Action>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Uniform, // 全图均匀，随机游走
+    Hotspot, // 聚集在几个热点附近，热点整体漂移
+}
+
+impl std::str::FromStr for Pattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "uniform" => Ok(Self::Uniform),
+            "hotspot" => Ok(Self::Hotspot),
+            _ => bail!("unknown pattern:{s:?}, expected uniform or hotspot"),
+        }
+    }
+}
+
+/// 合成的分布：人数在前半段线性增至peak，后半段线性减回0
+pub struct Synthetic {
+    pattern: Pattern,
+    peak: u32,
+    duration: u64,
+    area: f32,  // 玩家分布在[-area, area]的正方形内
+    speed: f32, // 玩家/热点每秒移动距离
+    rng: StdRng,
+    hotspots: Vec<(f32, f32, f32, f32)>,          // x,y,vx,vy
+    players: BTreeMap<PlayerId, (f32, f32, f32)>, // x,y,热点内的偏移角度
+    next_player_id: PlayerId,
+    last_t: u64,
+}
+
+impl Synthetic {
+    /// area须为正数，speed不能为负
+    pub fn new(pattern: Pattern, config: &SimConfig) -> Result<Self> {
+        ensure!(
+            config.area > 0.0,
+            "area:{} must be greater than 0",
+            config.area
+        );
+        ensure!(
+            config.speed >= 0.0 && config.speed.is_finite(),
+            "speed:{} must be a finite non-negative number",
+            config.speed
+        );
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let area = config
+            .area
+            .min(WORLD_X_MAX - MARGIN)
+            .min(WORLD_Y_MAX - MARGIN);
+        let speed = config.speed;
+        let hotspots = (0..config.hotspots.max(1))
+            .map(|_| {
+                let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+                (
+                    rng.gen_range(-area..area),
+                    rng.gen_range(-area..area),
+                    speed * angle.cos(),
+                    speed * angle.sin(),
+                )
+            })
+            .collect();
+        Ok(Self {
+            pattern,
+            peak: config.players,
+            duration: config.duration,
+            area,
+            speed,
+            rng,
+            hotspots,
+            players: BTreeMap::new(),
+            next_player_id: 0,
+            last_t: 0,
+        })
+    }
+
+    fn target_players(&self, t_ms: u64) -> usize {
+        if self.duration == 0 {
+            return self.peak as usize;
+        }
+        let half = self.duration as f64 / 2.0;
+        let ratio = 1.0 - ((t_ms as f64 - half) / half).abs();
+        (self.peak as f64 * ratio.clamp(0.0, 1.0)).round() as usize
+    }
+
+    fn clamp(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            x.clamp(-self.area, self.area),
+            y.clamp(-self.area, self.area),
+        )
+    }
+
+    // 按player_id分到热点，分布在热点周围7个半径的圆上，最大为area的1/10
+    fn hotspot_position(&self, player_id: PlayerId, angle: f32) -> (f32, f32) {
+        let (hx, hy, ..) = self.hotspots[player_id as usize % self.hotspots.len()];
+        let radius = self.area / 10.0 * (player_id % 7 + 1) as f32 / 7.0;
+        self.clamp(hx + radius * angle.cos(), hy + radius * angle.sin())
+    }
+
+    fn spawn(&mut self) -> (f32, f32, f32) {
+        let angle = self.rng.gen_range(0.0..std::f32::consts::TAU);
+        match self.pattern {
+            Pattern::Uniform => (
+                self.rng.gen_range(-self.area..self.area),
+                self.rng.gen_range(-self.area..self.area),
+                angle,
+            ),
+            Pattern::Hotspot => {
+                let (x, y) = self.hotspot_position(self.next_player_id, angle);
+                (x, y, angle)
+            }
+        }
+    }
+}
+
+impl Distribution for Synthetic {
+    fn step(&mut self, t_ms: u64) -> Vec<Action> {
+        let secs = t_ms.saturating_sub(self.last_t) as f32 / 1000.0;
+        self.last_t = t_ms;
+        let mut actions = vec![];
+
+        // 先登出最早登录的，不再移动
+        let target = self.target_players(t_ms);
+        while self.players.len() > target {
+            let (player_id, _) = self.players.pop_first().unwrap();
+            actions.push(Action::Logout(player_id));
+        }
+
+        // 热点碰到边界反弹
+        let area = self.area;
+        for (x, y, vx, vy) in &mut self.hotspots {
+            *x += *vx * secs;
+            *y += *vy * secs;
+            if x.abs() > area {
+                *vx = -*vx;
+                *x = x.clamp(-area, area);
+            }
+            if y.abs() > area {
+                *vy = -*vy;
+                *y = y.clamp(-area, area);
+            }
+        }
+        let ids = self.players.keys().copied().collect::<Vec<_>>();
+        for player_id in ids {
+            let (x, y, angle) = self.players[&player_id];
+            let (x, y) = match self.pattern {
+                Pattern::Uniform => {
+                    let dir = self.rng.gen_range(0.0..std::f32::consts::TAU);
+                    let dist = self.speed * secs;
+                    self.clamp(x + dist * dir.cos(), y + dist * dir.sin())
+                }
+                Pattern::Hotspot => self.hotspot_position(player_id, angle),
+            };
+            self.players.insert(player_id, (x, y, angle));
+            actions.push(Action::Move(player_id, x, y));
+        }
+
+        while self.players.len() < target {
+            let (x, y, angle) = self.spawn();
+            let player_id = self.next_player_id;
+            self.next_player_id += 1;
+            self.players.insert(player_id, (x, y, angle));
+            actions.push(Action::Login(player_id, x, y));
+        }
+        actions
+    }
+}
+
+type TraceLine = (u64, PlayerId, Option<(f32, f32)>); // t_ms, player_id, 坐标(登出为None)
+
+/// 录制的分布，每行`<t_ms> <player_id> <x> <y>`或`<t_ms> <player_id> logout`，按时间排序
+/// 首次出现的玩家为登录，之后为移动；`#`开头的行为注释
+pub struct Trace {
+    lines: VecDeque<TraceLine>,
+    online: HashSet<PlayerId>,
+}
+
+impl Trace {
+    pub fn load(path: &str) -> Result<Self> {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?;
+        Self::parse(&text).with_context(|| format!("Invalid trace {path}"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = VecDeque::new();
+        let mut last_t = 0;
+        for (no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let parsed = (|| -> Result<_> {
+                Ok(match fields[..] {
+                    [t, player_id, "logout"] => (t.parse()?, player_id.parse()?, None),
+                    [t, player_id, x, y] => (
+                        t.parse()?,
+                        player_id.parse()?,
+                        Some((x.parse()?, y.parse()?)),
+                    ),
+                    _ => bail!("expected 3 or 4 fields"),
+                })
+            })()
+            .with_context(|| format!("line {}: {line:?}", no + 1))?;
+            if parsed.0 < last_t {
+                bail!("line {}: time goes backwards", no + 1);
+            }
+            last_t = parsed.0;
+            lines.push_back(parsed);
+        }
+        Ok(Self {
+            lines,
+            online: HashSet::new(),
+        })
+    }
+
+    // 最后一行的时间，未指定模拟时长时用
+    pub fn end_ms(&self) -> u64 {
+        self.lines.back().map_or(0, |(t, ..)| *t)
+    }
+}
+
+impl Distribution for Trace {
+    fn step(&mut self, t_ms: u64) -> Vec<Action> {
+        let mut actions = vec![];
+        while let Some(&(t, player_id, position)) = self.lines.front() {
+            if t > t_ms {
+                break;
+            }
+            self.lines.pop_front();
+            let (x, y) = match position {
+                Some((x, y)) => (
+                    x.clamp(WORLD_X_MIN + MARGIN, WORLD_X_MAX - MARGIN),
+                    y.clamp(WORLD_Y_MIN + MARGIN, WORLD_Y_MAX - MARGIN),
+                ),
+                None => {
+                    if self.online.remove(&player_id) {
+                        actions.push(Action::Logout(player_id));
+                    }
+                    continue;
+                }
+            };
+            if self.online.insert(player_id) {
+                actions.push(Action::Login(player_id, x, y));
+            } else {
+                actions.push(Action::Move(player_id, x, y));
+            }
+        }
+        actions
+    }
+}
@@ -2,83 +2,354 @@ use crate::data::*;
 
 use common::proto::game_service::game_service_client::GameServiceClient;
 use common::proto::map_service::map_service_client::MapServiceClient;
-use common::{ServerId, ZoneId, DEFAULT_MAP_PORT, MAP_PORT_ENV_NAME};
+use common::{
+    ServerId, WorldId, ZoneId, DEFAULT_DEDUP_WINDOW, DEFAULT_MAX_PLAYERS, DEFAULT_MAX_ZONE_DEPTH,
+    DEFAULT_MIN_PLAYERS,
+};
 
-use anyhow::Result;
-use common::{WORLD_X_MAX, WORLD_X_MIN, WORLD_Y_MAX, WORLD_Y_MIN};
+use anyhow::{Context, Result};
+use common::tls::{self, TlsConfig};
+use common::world_map::WorldMap;
+use common::{Dimension, AABB};
+use common::{WORLD_X_MAX, WORLD_X_MIN, WORLD_Y_MAX, WORLD_Y_MIN, WORLD_Z_MAX, WORLD_Z_MIN};
 use econf::LoadEnv;
+use tonic::transport::Channel;
 use tonic::Status;
 use tracing::*;
 
+use std::collections::HashSet;
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, LoadEnv)]
+const MAX_RADIUS: f32 = 2.0 * (WORLD_X_MAX - WORLD_X_MIN); // 大于世界对角线，覆盖全图
+
+#[derive(LoadEnv, Clone)]
 pub struct Config {
-    pub max_players: u32,      // 扩容阈值
-    pub min_players: u32,      // 缩容阈值
-    pub max_zone_depth: u32,   // 四叉树最大高度
-    pub scaling_interval: u64, // 扩缩容扫描间隔(ms)
+    pub max_players: u32,              // 扩容阈值
+    pub min_players: u32,              // 缩容阈值
+    pub max_zone_depth: u32,           // 四叉树最大高度
+    pub scaling_interval: u64,         // 扩缩容扫描间隔(ms)
+    pub dedup_window: usize,           // 每个玩家缓存的request_id个数
+    pub world_map_path: String,        // 静态地图文件，为空则无障碍物、不限速
+    pub tick_interval: u64,            // map-server按速度移动玩家的tick间隔(ms)，0为不开启
+    pub simulation_sync_interval: u64, // 开启tick时，同步移动中玩家坐标、重新订阅跨出zone推送的间隔(ms)
+    pub auth_key: String,              // 签发/验证token的HMAC密钥，为空则不认证
+    pub tls_ca_path: String,           // 与map-server之间双向TLS的CA证书，三个路径都为空则不开启
+    pub tls_cert_path: String,         // 本进程证书，同时传给启动的map-server
+    pub tls_key_path: String,
+    pub tls_domain: String,          // 证书中的域名，为空则为localhost
+    pub moving_rate_limit: u32,      // 每个玩家每秒moving次数上限，0为不限
+    pub aoe_rate_limit: u32,         // 每个玩家每秒aoe次数上限
+    pub query_rate_limit: u32,       // 每个玩家每秒query次数上限，开启认证时生效
+    pub player_rate_limit: u32,      // 每个玩家每秒其他RPC各自的次数上限
+    pub global_rate_limit: u32,      // dispatcher每秒处理的请求总数上限
+    pub idle_timeout: u64,           // 玩家超过该时间(ms)没有任何请求则自动登出，0为不开启
+    pub login_policy: LoginPolicy,   // 已登录的player_id再次login时的处理
+    pub npc_as_load: bool,           // NPC是否计入扩缩容的负载人数
+    pub world_count: u32, // 世界(副本、分线)数量，world_id为0..world_count，各自独立扩缩容
+    pub octree_worlds: String, // 带z轴的3D世界，world_id逗号分隔，其余为2D
+    pub dispatcher_id: u32, // 本dispatcher在dispatcher_addrs中的序号。coordinator为持有leader租约的dispatcher，不选举(StaticLeader)时固定为COORDINATOR_ID即0号
+    pub dispatcher_addrs: String, // 所有dispatcher的地址，逗号分隔，为空则只有一个dispatcher
+    pub cluster_secret: String, // dispatcher之间调用的共享密钥，多个dispatcher时必须配置
+    pub leader_lease_path: String, // 选举用的租约文件，为空则不选举，0号dispatcher固定为leader
+    pub leader_lease_ttl: u64, // leader超过该时间(ms)没有续约则由其他dispatcher接管，须大于scaling_interval
+    pub scaling_log_capacity: usize, // 内存中保留的扩缩容事件条数
+    pub scaling_log_path: String, // 扩缩容事件另外追加写入的文件，为空则不写
+    pub record_path: String,   // 录制收到的GameService请求的文件，为空则不录制
+    pub map_host: String, // 启动的map-server绑定的地址，其他机器上的dispatcher需要连接时改为对外的地址
+    pub map_port: String, // 启动的map-server绑定的端口：0由系统分配，或如5000-5100取范围内第一个空闲的
+}
+
+/// 重复登录策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, LoadEnv)]
+pub enum LoginPolicy {
+    #[default]
+    Reject, // 返回already_exists
+    KickOld, // 登出旧会话，按本次传入的坐标重新登录，可能换到其他server
+    Resume,  // 保留旧会话及其位置，直接返回成功
 }
 
-pub fn check_xy_range(x: f32, y: f32) -> Result<(), Status> {
-    if x >= WORLD_X_MAX || y >= WORLD_Y_MAX || x <= WORLD_X_MIN || y < WORLD_Y_MIN {
-        Err(Status::out_of_range(format!("x:{x} y:{y}")))
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_players: DEFAULT_MAX_PLAYERS,
+            min_players: DEFAULT_MIN_PLAYERS,
+            max_zone_depth: DEFAULT_MAX_ZONE_DEPTH,
+            scaling_interval: 10_000,
+            dedup_window: DEFAULT_DEDUP_WINDOW,
+            world_map_path: String::new(),
+            tick_interval: 0,
+            simulation_sync_interval: 1000,
+            auth_key: String::new(),
+            tls_ca_path: String::new(),
+            tls_cert_path: String::new(),
+            tls_key_path: String::new(),
+            tls_domain: String::new(),
+            moving_rate_limit: 0,
+            aoe_rate_limit: 0,
+            query_rate_limit: 0,
+            player_rate_limit: 0,
+            global_rate_limit: 0,
+            idle_timeout: 0,
+            login_policy: LoginPolicy::default(),
+            npc_as_load: false,
+            world_count: 1,
+            octree_worlds: String::new(),
+            dispatcher_id: 0,
+            dispatcher_addrs: String::new(),
+            cluster_secret: String::new(),
+            leader_lease_path: String::new(),
+            leader_lease_ttl: 30_000,
+            scaling_log_capacity: 1000,
+            scaling_log_path: String::new(),
+            record_path: String::new(),
+            map_host: common::launch::DEFAULT_HOST.to_string(),
+            map_port: common::launch::DEFAULT_PORT_RANGE.to_string(),
+        }
+    }
+}
+
+// 启动时会打印配置，不输出密钥
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Config")
+            .field("max_players", &self.max_players)
+            .field("min_players", &self.min_players)
+            .field("max_zone_depth", &self.max_zone_depth)
+            .field("scaling_interval", &self.scaling_interval)
+            .field("dedup_window", &self.dedup_window)
+            .field("world_map_path", &self.world_map_path)
+            .field("tick_interval", &self.tick_interval)
+            .field("simulation_sync_interval", &self.simulation_sync_interval)
+            .field(
+                "auth_key",
+                &if self.auth_key.is_empty() { "" } else { "***" },
+            )
+            .field("tls_ca_path", &self.tls_ca_path)
+            .field("tls_cert_path", &self.tls_cert_path)
+            .field("tls_key_path", &self.tls_key_path)
+            .field("tls_domain", &self.tls_domain)
+            .field("moving_rate_limit", &self.moving_rate_limit)
+            .field("aoe_rate_limit", &self.aoe_rate_limit)
+            .field("query_rate_limit", &self.query_rate_limit)
+            .field("player_rate_limit", &self.player_rate_limit)
+            .field("global_rate_limit", &self.global_rate_limit)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("login_policy", &self.login_policy)
+            .field("npc_as_load", &self.npc_as_load)
+            .field("world_count", &self.world_count)
+            .field("octree_worlds", &self.octree_worlds)
+            .field("dispatcher_id", &self.dispatcher_id)
+            .field("dispatcher_addrs", &self.dispatcher_addrs)
+            .field(
+                "cluster_secret",
+                &if self.cluster_secret.is_empty() {
+                    ""
+                } else {
+                    "***"
+                },
+            )
+            .field("leader_lease_path", &self.leader_lease_path)
+            .field("leader_lease_ttl", &self.leader_lease_ttl)
+            .field("scaling_log_capacity", &self.scaling_log_capacity)
+            .field("scaling_log_path", &self.scaling_log_path)
+            .field("record_path", &self.record_path)
+            .field("map_host", &self.map_host)
+            .field("map_port", &self.map_port)
+            .finish()
+    }
+}
+
+pub fn check_xyz_range(x: f32, y: f32, z: f32) -> Result<(), Status> {
+    if x >= WORLD_X_MAX
+        || y >= WORLD_Y_MAX
+        || z >= WORLD_Z_MAX
+        || x <= WORLD_X_MIN
+        || y < WORLD_Y_MIN
+        || z < WORLD_Z_MIN
+    {
+        Err(Status::out_of_range(format!("x:{x} y:{y} z:{z}")))
     } else {
         Ok(())
     }
 }
 
-#[inline]
-pub fn get_child_zone_ids(id: ZoneId) -> [ZoneId; 4] {
-    [id * 10 + 1, id * 10 + 2, id * 10 + 3, id * 10 + 4]
+// aoe/say的半径须为非负有限值，大于世界对角线的截断，map-server计算范围时不会溢出
+pub fn check_radius(radius: f32) -> Result<f32, Status> {
+    if radius.is_finite() && radius >= 0.0 {
+        Ok(radius.min(MAX_RADIUS))
+    } else {
+        Err(Status::invalid_argument(format!("radius:{radius}")))
+    }
+}
+
+// query范围须为有限值且min不大于max，截到世界范围内
+pub fn check_aabb(aabb: AABB) -> Result<AABB, Status> {
+    let AABB {
+        xmin,
+        xmax,
+        ymin,
+        ymax,
+        zmin,
+        zmax,
+    } = aabb;
+    let valid = [xmin, xmax, ymin, ymax, zmin, zmax]
+        .iter()
+        .all(|v| v.is_finite())
+        && xmin <= xmax
+        && ymin <= ymax
+        && zmin <= zmax;
+    if !valid {
+        return Err(Status::invalid_argument(format!("{aabb:?}")));
+    }
+    Ok(AABB {
+        xmin: xmin.max(WORLD_X_MIN),
+        xmax: xmax.min(WORLD_X_MAX),
+        ymin: ymin.max(WORLD_Y_MIN),
+        ymax: ymax.min(WORLD_Y_MAX),
+        zmin: zmin.max(WORLD_Z_MIN),
+        zmax: zmax.min(WORLD_Z_MAX),
+    })
+}
+
+// proto中按位编码的zone_id，须属于该世界的树且不超过最大深度
+pub fn parse_zone_id(raw: u64, max_depth: u32, dimension: Dimension) -> Result<ZoneId, Status> {
+    ZoneId::from_raw(raw)
+        .filter(|id| id.dimension() == dimension && id.depth() <= max_depth)
+        .ok_or_else(|| Status::invalid_argument(format!("zone_id:{raw:#x}")))
+}
+
+// 指定玩家的请求，world_id须与玩家所在世界一致
+pub fn check_world(server: &ServerInfo, world_id: WorldId) -> Result<(), Status> {
+    if server.world_id == world_id {
+        Ok(())
+    } else {
+        Err(Status::failed_precondition(format!(
+            "player is in world:{}, not {world_id}",
+            server.world_id
+        )))
+    }
+}
+
+// 逗号分隔的world_id列表，例如"1,3"
+pub fn parse_world_ids(s: &str) -> Result<HashSet<WorldId>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().with_context(|| format!("invalid world_id:{s:?}")))
+        .collect()
+}
+
+pub fn load_world_map(path: &str) -> Result<WorldMap> {
+    if path.is_empty() {
+        Ok(WorldMap::default())
+    } else {
+        WorldMap::load(path)
+    }
+}
+
+pub fn load_tls(config: &Config) -> Result<Option<TlsConfig>> {
+    TlsConfig::load_optional(
+        &config.tls_ca_path,
+        &config.tls_cert_path,
+        &config.tls_key_path,
+        &config.tls_domain,
+    )
+}
+
+// 两个client共用一个连接
+async fn connect_map_server(
+    addr: String,
+    tls: Option<&TlsConfig>,
+) -> Result<(MapServiceClient<Channel>, GameServiceClient<Channel>)> {
+    let channel = tls::connect(addr, tls).await?;
+    Ok((
+        MapServiceClient::new(channel.clone()),
+        GameServiceClient::new(channel),
+    ))
 }
 
+static SERVER_ID: AtomicU32 = AtomicU32::new(0);
+
 pub fn gen_server_id() -> ServerId {
-    static SERVER_ID: AtomicU32 = AtomicU32::new(0);
     SERVER_ID.fetch_add(1, Ordering::Relaxed)
 }
 
-// 从MAP_PORT_ENV_NAME开始，每次获取逐个+1
-pub fn gen_port_no() -> u32 {
-    use once_cell::sync::Lazy;
-    use std::env;
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
 
-    static PORT: Lazy<AtomicU32> = Lazy::new(|| {
-        let port = env::var(MAP_PORT_ENV_NAME)
-            .map(|s| s.parse().unwrap())
-            .unwrap_or(DEFAULT_MAP_PORT);
-        AtomicU32::new(port)
-    });
-    PORT.fetch_add(1, Ordering::Relaxed)
+// 接管leader时，跳过上任leader已分配的server_id
+pub fn reserve_server_id(next: ServerId) {
+    SERVER_ID.fetch_max(next, Ordering::Relaxed);
 }
 
-// 启动独立的bin
+// map-server绑定端口后报告地址的超时
 #[cfg(not(feature = "map_server_inside"))]
-#[instrument]
-pub async fn start_map_server(zones: Vec<ZoneId>) -> Result<ServerInfo> {
-    use anyhow::Context;
+const MAP_SERVER_READY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+// 启动独立的bin，server_id由本进程分配，端口由map-server按map_port绑定后报告，见[`common::launch`]
+#[cfg(not(feature = "map_server_inside"))]
+#[instrument(skip(config))]
+pub async fn start_map_server(
+    world_id: WorldId,
+    dimension: Dimension,
+    zones: Vec<ZoneId>,
+    config: &Config,
+) -> Result<ServerInfo> {
+    use common::{
+        DIMENSION_ENV_NAME, MAP_HOST_ENV_NAME, MAP_PORT_ENV_NAME, SERVER_ID_ENV_NAME,
+        TICK_INTERVAL_ENV_NAME, TLS_CA_ENV_NAME, TLS_CERT_ENV_NAME, TLS_DOMAIN_ENV_NAME,
+        TLS_KEY_ENV_NAME, WORLD_MAP_ENV_NAME,
+    };
     use std::env;
-    use std::process::Command;
-    use tokio::time::{sleep, Duration};
+    use std::process::Stdio;
+    use tokio::process::Command;
+    use tokio::time::timeout;
 
-    let port = gen_port_no();
-    let addr = format!("http://127.0.0.1:{port}");
+    let tls = load_tls(config)?;
+    let server_id = gen_server_id();
 
     let map_bin_path = env::var("MAP_SERVER_BIN_PATH").expect("Please set env MAP_SERVER_BIN_PATH");
-    Command::new(&map_bin_path)
-        .env(MAP_PORT_ENV_NAME, port.to_string())
+    let mut child = Command::new(&map_bin_path)
+        .env(MAP_HOST_ENV_NAME, &config.map_host)
+        .env(MAP_PORT_ENV_NAME, &config.map_port)
+        .env(SERVER_ID_ENV_NAME, server_id.to_string())
+        .env(WORLD_MAP_ENV_NAME, &config.world_map_path)
+        .env(TICK_INTERVAL_ENV_NAME, config.tick_interval.to_string())
+        .env(DIMENSION_ENV_NAME, dimension.as_str())
+        .env(TLS_CA_ENV_NAME, &config.tls_ca_path)
+        .env(TLS_CERT_ENV_NAME, &config.tls_cert_path)
+        .env(TLS_KEY_ENV_NAME, &config.tls_key_path)
+        .env(TLS_DOMAIN_ENV_NAME, &config.tls_domain)
+        .stdout(Stdio::piped())
         .spawn()
         .with_context(|| format!("Failed to start {map_bin_path}"))?;
-    sleep(Duration::from_millis(500)).await;
+    let stdout = child.stdout.take().context("map-server stdout not piped")?;
+    let bound = match timeout(MAP_SERVER_READY_TIMEOUT, wait_ready(stdout, server_id)).await {
+        Ok(res) => res,
+        Err(_) => Err(anyhow::anyhow!(
+            "map-server:{server_id} not ready in {MAP_SERVER_READY_TIMEOUT:?}"
+        )),
+    };
+    // 没有就绪的进程不再使用
+    if bound.is_err() {
+        let _ = child.start_kill();
+    }
+    let bound = bound?;
+    let addr = format!("{}://{bound}", tls::scheme(tls.as_ref()));
 
-    let map_cli = MapServiceClient::connect(addr.clone()).await?;
-    let game_cli = GameServiceClient::connect(addr.clone()).await?;
-    let server_id = gen_server_id();
+    let (map_cli, game_cli) = connect_map_server(addr.clone(), tls.as_ref()).await?;
 
-    info!(?server_id, ?addr);
+    info!(?server_id, ?world_id, ?dimension, ?addr);
     Ok(ServerInfo {
         inner: ServerInfoInner {
             server_id,
+            world_id,
+            dimension,
             zones,
             map_cli,
             game_cli,
@@ -88,37 +359,107 @@ pub async fn start_map_server(zones: Vec<ZoneId>) -> Result<ServerInfo> {
     })
 }
 
+// 读到握手行返回map-server绑定的地址，之前与之后的输出原样写到本进程的stdout
+#[cfg(not(feature = "map_server_inside"))]
+async fn wait_ready(stdout: tokio::process::ChildStdout, server_id: ServerId) -> Result<String> {
+    use anyhow::ensure;
+    use common::launch::parse_ready_line;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let mut reader = BufReader::new(stdout);
+    let mut line = String::new();
+    let bound = loop {
+        line.clear();
+        ensure!(
+            reader.read_line(&mut line).await? > 0,
+            "map-server:{server_id} exited before ready"
+        );
+        match parse_ready_line(line.trim_end()) {
+            Some((id, bound)) => {
+                ensure!(
+                    id == server_id,
+                    "map-server reported server_id:{id}, expected {server_id}"
+                );
+                break bound.to_string();
+            }
+            None => tokio::io::stdout().write_all(line.as_bytes()).await?,
+        }
+    };
+    // reader中已缓冲的部分一并转发
+    tokio::spawn(async move {
+        let _ = tokio::io::copy_buf(&mut reader, &mut tokio::io::stdout()).await;
+    });
+    Ok(bound)
+}
+
+// 以对象形式启动的map-server的任务，关闭时abort。测试用
+#[cfg(feature = "map_server_inside")]
+static INSIDE_SERVERS: once_cell::sync::Lazy<
+    std::sync::Mutex<std::collections::HashMap<ServerId, Vec<tokio::task::JoinHandle<()>>>>,
+> = once_cell::sync::Lazy::new(Default::default);
+
 // 以对象形式加载。测试用
+// 与独立的bin一样按map_host、map_port绑定，默认为0由系统分配，同一台机器上并行的测试进程不会冲突
 #[cfg(feature = "map_server_inside")]
-#[instrument]
-pub async fn start_map_server(zones: Vec<ZoneId>) -> Result<ServerInfo> {
+#[instrument(skip(config))]
+pub async fn start_map_server(
+    world_id: WorldId,
+    dimension: Dimension,
+    zones: Vec<ZoneId>,
+    config: &Config,
+) -> Result<ServerInfo> {
+    use common::launch;
     use common::proto::game_service::game_service_server::GameServiceServer;
     use common::proto::map_service::map_service_server::MapServiceServer;
-    use tokio::time::{sleep, Duration};
+    use common::ErrHandle;
+    use tokio_stream::wrappers::TcpListenerStream;
     use tonic::transport::Server;
 
-    let port = gen_port_no();
-    let addr = format!("127.0.0.1:{port}");
-    let socket = addr.parse().unwrap();
+    let listener = launch::bind(
+        &config.map_host,
+        launch::parse_port_range(&config.map_port)?,
+    )
+    .await?;
+    let addr = listener.local_addr()?.to_string();
 
     let server_id = gen_server_id();
-    let map_server = map_server::server::MapServer::new(server_id, addr.clone());
-    tokio::spawn(
-        Server::builder()
-            .add_service(MapServiceServer::new(map_server.clone()))
-            .add_service(GameServiceServer::new(map_server))
-            .serve(socket),
+    let world_map = load_world_map(&config.world_map_path)?;
+    let tls = load_tls(config)?;
+    let map_server = map_server::server::MapServer::new(
+        server_id,
+        addr.clone(),
+        world_map,
+        tls.clone(),
+        dimension,
     );
-    sleep(Duration::from_millis(100)).await;
+    let mut tasks = vec![];
+    if config.tick_interval > 0 {
+        tasks.push(tokio::spawn(
+            map_server.clone().simulation_tick(config.tick_interval),
+        ));
+    }
+    let mut builder = Server::builder();
+    if let Some(tls) = &tls {
+        builder = builder.tls_config(tls.server_config())?;
+    }
+    let serve = builder
+        .add_service(MapServiceServer::new(map_server.clone()))
+        .add_service(GameServiceServer::new(map_server))
+        .serve_with_incoming(TcpListenerStream::new(listener));
+    tasks.push(tokio::spawn(async move {
+        let _ = serve.await.log_err();
+    }));
+    INSIDE_SERVERS.lock().unwrap().insert(server_id, tasks);
 
-    let addr = format!("http://{}", addr);
-    let map_cli = MapServiceClient::connect(addr.clone()).await?;
-    let game_cli = GameServiceClient::connect(addr.clone()).await?;
+    let addr = format!("{}://{}", tls::scheme(tls.as_ref()), addr);
+    let (map_cli, game_cli) = connect_map_server(addr.clone(), tls.as_ref()).await?;
 
-    info!(?server_id, ?addr);
+    info!(?server_id, ?world_id, ?dimension, ?addr);
     Ok(ServerInfo {
         inner: ServerInfoInner {
             server_id,
+            world_id,
+            dimension,
             zones,
             map_cli,
             game_cli,
@@ -128,9 +469,29 @@ pub async fn start_map_server(zones: Vec<ZoneId>) -> Result<ServerInfo> {
     })
 }
 
+#[cfg(not(feature = "map_server_inside"))]
 pub async fn shutdown_map_server(server: &ServerInfo) -> Result<()> {
     info!(?server.server_id,?server.addr, "Shutdowning");
     server.map_cli.clone().shutdown(()).await?;
     info!(?server.server_id, "Shutdown done");
     Ok(())
 }
+
+// 与独立的bin一样延迟100ms停止，让在途的请求完成
+#[cfg(feature = "map_server_inside")]
+pub async fn shutdown_map_server(server: &ServerInfo) -> Result<()> {
+    use tokio::time::{sleep, Duration};
+
+    info!(?server.server_id,?server.addr, "Shutdowning");
+    let tasks = INSIDE_SERVERS
+        .lock()
+        .unwrap()
+        .remove(&server.server_id)
+        .with_context(|| format!("server_id:{} not running", server.server_id))?;
+    tokio::spawn(async move {
+        sleep(Duration::from_millis(100)).await;
+        tasks.iter().for_each(|task| task.abort());
+    });
+    info!(?server.server_id, "Shutdown done");
+    Ok(())
+}
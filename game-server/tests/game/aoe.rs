@@ -5,12 +5,15 @@ use common::proto::game_service::{
     game_service_server::GameService, AoeRequest, PlayerInfo, QueryRequest,
 };
 
+use common::AOE_MONEY;
 use tokio::time::sleep;
-use tonic::IntoRequest;
+
+use tonic::{Code, IntoRequest};
 
 use std::time::Duration;
 
 #[tokio::test]
+#[allow(clippy::needless_range_loop)]
 async fn test_game_aoe() {
     crate::init_log();
 
@@ -19,6 +22,7 @@ async fn test_game_aoe() {
         min_players: 3,
         max_zone_depth: 10,
         scaling_interval: 200,
+        ..Default::default()
     })
     .await
     .unwrap();
@@ -32,6 +36,7 @@ async fn test_game_aoe() {
                     x: 1.0,
                     y: 2.0,
                     money: 99,
+                    ..Default::default()
                 }
                 .into_request(),
             )
@@ -46,6 +51,7 @@ async fn test_game_aoe() {
                 x: -1.0,
                 y: 2.0,
                 money: 99,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -60,7 +66,7 @@ async fn test_game_aoe() {
             AoeRequest {
                 player_id: 0,
                 radius: 5.0,
-                coord: None,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -74,6 +80,7 @@ async fn test_game_aoe() {
                 xmax: 1.0,
                 ymin: 0.0,
                 ymax: 2.0,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -91,3 +98,126 @@ async fn test_game_aoe() {
 
     dispatcher.shutdown_all_map_server().await;
 }
+
+// 半径跨越多个zone时，四个顶点之外、位于圆内部的深层zone也要收到aoe
+#[tokio::test]
+async fn test_aoe_large_radius() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10,
+        min_players: 0,
+        max_zone_depth: 6,
+        scaling_interval: 100,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+
+    // 玩家聚在一点，逐层扩容直到最大深度
+    for i in 0..10 {
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x: 10.0 + i as f32,
+                    y: 10.0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(2000)).await;
+    assert_eq!(dispatcher.get_all_servers().len(), 6);
+    let (server, ..) = dispatcher.get_server_of_player(&0).unwrap();
+    assert_eq!(server.zones[0].depth(), 6);
+
+    // 施放者在第3象限，正方形的四个顶点都不在玩家所在的zone
+    for (player_id, x, y) in [(100, -300_000.0, -300_000.0), (101, 150_000.0, 150_000.0)] {
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id,
+                    x,
+                    y,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    dispatcher
+        .aoe(
+            AoeRequest {
+                player_id: 100,
+                radius: 500_000.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+
+    let players = dispatcher
+        .query(
+            QueryRequest {
+                xmin: -1_000_000.0,
+                xmax: 1_000_000.0,
+                ymin: -1_000_000.0,
+                ymax: 1_000_000.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    assert_eq!(players.len(), 12);
+    for player in &players {
+        // 101在正方形内、圆外
+        let money = if player.player_id >= 100 {
+            0
+        } else {
+            AOE_MONEY
+        };
+        assert_eq!(player.money, money, "{player:?}");
+    }
+
+    // 超过世界对角线的半径截断，非法半径与范围被拒绝
+    let aoe = |radius| {
+        dispatcher.aoe(
+            AoeRequest {
+                player_id: 100,
+                radius,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+    };
+    aoe(1e30).await.unwrap();
+    for radius in [-1.0, f32::NAN, f32::INFINITY] {
+        let status = aoe(radius).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument, "radius:{radius}");
+    }
+    let status = dispatcher
+        .query(
+            QueryRequest {
+                xmin: 10.0,
+                xmax: -10.0,
+                ymin: f32::NEG_INFINITY,
+                ymax: 10.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    dispatcher.shutdown_all_map_server().await;
+}
@@ -0,0 +1,166 @@
+use game_server::auth::{Claims, Scope, TokenAuth};
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::game_service::{
+    game_service_server::GameService, BroadcastRequest, MovingRequest, PlayerIdRequest, PlayerInfo,
+};
+
+use tonic::service::Interceptor;
+use tonic::{Code, Request, Status};
+
+// 与GameServiceServer::with_interceptor相同，先过拦截器再调用dispatcher
+fn with_token<T>(
+    auth: &mut TokenAuth,
+    token: Option<&str>,
+    message: T,
+) -> Result<Request<T>, Status> {
+    let mut request = Request::new(());
+    if let Some(token) = token {
+        request
+            .metadata_mut()
+            .insert("authorization", format!("Bearer {token}").parse().unwrap());
+    }
+    let (metadata, extensions, _) = auth.call(request)?.into_parts();
+    Ok(Request::from_parts(metadata, extensions, message))
+}
+
+#[test]
+fn test_token() {
+    let auth = TokenAuth::new("secret");
+    let claims = Claims {
+        player_id: 7,
+        scope: Scope::Player,
+        expire_at: 0,
+    };
+    let token = auth.issue(&claims);
+    assert_eq!(auth.verify(&token).unwrap(), claims);
+
+    // 篡改player_id
+    let forged = token.replacen('7', "8", 1);
+    assert_eq!(
+        auth.verify(&forged).unwrap_err().code(),
+        Code::Unauthenticated
+    );
+    // 密钥不同
+    let other = TokenAuth::new("other");
+    assert_eq!(
+        other.verify(&token).unwrap_err().code(),
+        Code::Unauthenticated
+    );
+    // 已过期
+    let expired = auth.issue(&Claims {
+        expire_at: 1,
+        ..claims
+    });
+    assert_eq!(
+        auth.verify(&expired).unwrap_err().code(),
+        Code::Unauthenticated
+    );
+}
+
+#[tokio::test]
+async fn test_auth() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10,
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        auth_key: "secret".to_string(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let mut auth = TokenAuth::new(dispatcher.config.auth_key.as_str());
+    let token = auth.issue(&Claims {
+        player_id: 1,
+        scope: Scope::Player,
+        expire_at: 0,
+    });
+    let admin_token = auth.issue(&Claims {
+        player_id: 0,
+        scope: Scope::Admin,
+        expire_at: 0,
+    });
+
+    // 没有token
+    let status = with_token(&mut auth, None, ()).unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    let player = |player_id| PlayerInfo {
+        player_id,
+        x: 100.0,
+        y: 200.0,
+        ..Default::default()
+    };
+    dispatcher
+        .login(with_token(&mut auth, Some(&token), player(1)).unwrap())
+        .await
+        .unwrap();
+    // 不能操作其他玩家
+    let status = dispatcher
+        .login(with_token(&mut auth, Some(&token), player(2)).unwrap())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+    let moving = MovingRequest {
+        player_id: 1,
+        dx: 1.0,
+        dy: 1.0,
+        ..Default::default()
+    };
+    dispatcher
+        .moving(with_token(&mut auth, Some(&token), moving.clone()).unwrap())
+        .await
+        .unwrap();
+    let status = dispatcher
+        .moving(
+            with_token(
+                &mut auth,
+                Some(&token),
+                MovingRequest {
+                    player_id: 2,
+                    ..moving
+                },
+            )
+            .unwrap(),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    // broadcast需要admin
+    let broadcast = BroadcastRequest {
+        text: "notice".to_string(),
+        ..Default::default()
+    };
+    let status = dispatcher
+        .broadcast(with_token(&mut auth, Some(&token), broadcast.clone()).unwrap())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+    dispatcher
+        .broadcast(with_token(&mut auth, Some(&admin_token), broadcast).unwrap())
+        .await
+        .unwrap();
+
+    // admin可以操作任意玩家
+    dispatcher
+        .logout(
+            with_token(
+                &mut auth,
+                Some(&admin_token),
+                PlayerIdRequest {
+                    player_id: 1,
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    dispatcher.shutdown_all_map_server().await;
+}
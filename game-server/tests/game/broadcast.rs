@@ -0,0 +1,133 @@
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::game_service::{
+    game_service_server::GameService, BroadcastRequest, EventKind, PlayerIdRequest, PlayerInfo,
+    QueryRequest,
+};
+use common::{Dimension, ZoneId};
+
+use futures::StreamExt;
+use tokio::time::{sleep, timeout, Duration};
+use tonic::{Code, IntoRequest};
+
+fn zone(decimal: u64) -> u64 {
+    ZoneId::from_decimal(decimal, Dimension::D2).unwrap().into()
+}
+
+// 全服、指定zone、指定区域公告，只有范围内已订阅的玩家收到
+#[tokio::test]
+async fn test_broadcast() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10, // 第10个触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+
+    for i in 0..10 {
+        let x = if i == 9 { -100.0 } else { 100.0 + i as f32 };
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x,
+                    y: 200.0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(1000)).await;
+    assert_eq!(dispatcher.get_all_servers().len(), 2);
+
+    let mut streams = Vec::new();
+    for player_id in [0, 1, 9] {
+        streams.push(
+            dispatcher
+                .subscribe(
+                    PlayerIdRequest {
+                        player_id,
+                        ..Default::default()
+                    }
+                    .into_request(),
+                )
+                .await
+                .unwrap()
+                .into_inner(),
+        );
+    }
+    sleep(Duration::from_millis(200)).await;
+
+    let broadcast = |zone_ids: Vec<u64>, areas: Vec<QueryRequest>| {
+        let dispatcher = dispatcher.clone();
+        async move {
+            dispatcher
+                .broadcast(
+                    BroadcastRequest {
+                        text: "notice".to_string(),
+                        zone_ids,
+                        areas,
+                        ..Default::default()
+                    }
+                    .into_request(),
+                )
+                .await
+                .map(|res| res.into_inner().delivered)
+        }
+    };
+
+    // 全服
+    assert_eq!(broadcast(vec![], vec![]).await.unwrap(), 3);
+    for stream in streams.iter_mut() {
+        let event = timeout(Duration::from_secs(1), stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.kind(), EventKind::Broadcast);
+        assert_eq!(event.from_player_id, 0);
+        assert_eq!(event.text, "notice");
+    }
+
+    // 第2象限只有9
+    assert_eq!(broadcast(vec![zone(12)], vec![]).await.unwrap(), 1);
+    assert!(timeout(Duration::from_secs(1), streams[2].next())
+        .await
+        .is_ok());
+
+    // 区域内只有0
+    let area = QueryRequest {
+        xmin: 99.5,
+        ymin: 199.5,
+        xmax: 100.5,
+        ymax: 200.5,
+        ..Default::default()
+    };
+    assert_eq!(broadcast(vec![], vec![area]).await.unwrap(), 1);
+    assert!(timeout(Duration::from_secs(1), streams[0].next())
+        .await
+        .is_ok());
+    // 1没有收到后两次公告
+    assert!(timeout(Duration::from_millis(200), streams[1].next())
+        .await
+        .is_err());
+
+    // 非法zone_id：编码错误、超过最大深度、八叉树的zone
+    let too_deep = (0..10).fold(ZoneId::root(Dimension::D2), |id, _| id.child(1));
+    let octree = ZoneId::root(Dimension::D3).child(1);
+    for zone_id in [0, too_deep.into(), octree.into()] {
+        let status = broadcast(vec![zone_id], vec![]).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    dispatcher.shutdown_all_map_server().await;
+}
@@ -0,0 +1,167 @@
+use game_server::cluster::{DispatcherId, HashRing};
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::game_service::game_service_server::{GameService, GameServiceServer};
+use common::proto::game_service::{AoeRequest, MovingRequest, PlayerIdRequest};
+use common::AOE_MONEY;
+
+use test_kit::cluster::{bind_local, TEST_CLUSTER_SECRET};
+use test_kit::fixture::{player, query_all};
+
+use tokio::net::TcpListener;
+use tokio::time::{sleep, Duration};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::IntoRequest;
+
+async fn start_dispatcher(
+    dispatcher_id: DispatcherId,
+    addrs: &[String],
+    listener: TcpListener,
+) -> Dispatcher {
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10, // 第10个触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        dispatcher_id,
+        dispatcher_addrs: addrs.join(","),
+        cluster_secret: TEST_CLUSTER_SECRET.to_string(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(GameServiceServer::new(dispatcher.clone()))
+            .add_service(dispatcher.dispatcher_service())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+    tokio::spawn(dispatcher.clone().topology_sync());
+    dispatcher
+}
+
+// 增加dispatcher时，原有玩家要么不动，要么归新dispatcher
+#[test]
+fn test_hash_ring() {
+    let ring2 = HashRing::new(0..2);
+    let ring3 = HashRing::new(0..3);
+    let mut counts = [0; 3];
+    for player_id in 0..3000 {
+        let owner = ring3.owner(player_id).unwrap();
+        counts[owner as usize] += 1;
+        assert!(owner == 2 || owner == ring2.owner(player_id).unwrap());
+    }
+    assert!(counts.iter().all(|&n| n > 600), "{counts:?}");
+    assert_eq!(HashRing::default().owner(0), None);
+}
+
+// 两个dispatcher，玩家按id分属，非本地的请求转发；扩容由coordinator执行并同步拓扑
+#[tokio::test]
+async fn test_cluster() {
+    crate::init_log();
+
+    let (listeners, addrs) = bind_local(2).await.unwrap();
+    let [listener0, listener1]: [_; 2] = listeners.try_into().unwrap();
+    // coordinator最后创建，推送拓扑时follower已开始服务
+    let follower = start_dispatcher(1, &addrs, listener1).await;
+    let coordinator = start_dispatcher(0, &addrs, listener0).await;
+    sleep(Duration::from_millis(300)).await;
+    assert_eq!(follower.get_all_servers().len(), 1);
+    let dispatchers = [&coordinator, &follower];
+    let owner = |player_id| dispatchers[coordinator.cluster.owner(player_id) as usize];
+    assert!((0..10).any(|id| coordinator.cluster.owner(id) == 0));
+    assert!((0..10).any(|id| coordinator.cluster.owner(id) == 1));
+
+    // 都从follower登录，玩家数据只在所属dispatcher
+    for i in 0..10 {
+        let x = if i == 9 { -100.0 } else { 100.0 + i as f32 };
+        follower
+            .login(player(i, x, 200.0).into_request())
+            .await
+            .unwrap();
+    }
+    for i in 0..10 {
+        for dispatcher in dispatchers {
+            assert_eq!(
+                dispatcher.player_map.contains_key(&i),
+                dispatcher.cluster.is_local(i)
+            );
+        }
+    }
+
+    // coordinator扩容，各dispatcher的玩家都转移到新server，拓扑同步到follower
+    sleep(Duration::from_millis(1000)).await;
+    assert_eq!(coordinator.get_all_servers().len(), 2);
+    assert_eq!(follower.get_all_servers().len(), 2);
+    assert_eq!(
+        *coordinator.topology_version.lock().unwrap(),
+        *follower.topology_version.lock().unwrap()
+    );
+    let (server9, ..) = owner(9).get_server_of_player(&9).unwrap();
+    for i in 0..9 {
+        let (server, ..) = owner(i).get_server_of_player(&i).unwrap();
+        assert!(server != server9);
+    }
+    assert_eq!(query_all(&follower).await.len(), 10);
+
+    for dispatcher in dispatchers {
+        dispatcher
+            .aoe(
+                AoeRequest {
+                    player_id: 0,
+                    radius: 5.0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    for p in query_all(&coordinator).await {
+        let money = if p.player_id != 0 && p.x > 0.0 && p.x <= 105.0 {
+            AOE_MONEY * 2
+        } else {
+            0
+        };
+        assert_eq!(p.money, money, "{p:?}");
+    }
+
+    // 跨server移动，由所属dispatcher导出
+    let coord = coordinator
+        .moving(
+            MovingRequest {
+                player_id: 9,
+                dx: 200.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!((coord.x, coord.y), (100.0, 200.0));
+    let (server9, ..) = owner(9).get_server_of_player(&9).unwrap();
+    let (server0, ..) = owner(0).get_server_of_player(&0).unwrap();
+    assert!(server9 == server0);
+
+    for i in 0..10 {
+        coordinator
+            .logout(
+                PlayerIdRequest {
+                    player_id: i,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    assert!(query_all(&follower).await.is_empty());
+    assert!(follower.player_map.is_empty() && coordinator.player_map.is_empty());
+
+    follower.shutdown_all_map_server().await;
+    coordinator.shutdown_all_map_server().await;
+}
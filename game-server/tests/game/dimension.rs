@@ -0,0 +1,110 @@
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::game_service::{game_service_server::GameService, AoeRequest, MovingRequest};
+use common::{WorldId, AOE_MONEY, DEFAULT_WORLD_ID};
+
+use test_kit::fixture::{player_at, query_all, query_box, query_world};
+
+use tokio::time::{sleep, Duration};
+use tonic::IntoRequest;
+
+const SPACE_WORLD_ID: WorldId = 1;
+
+// 3D世界按八叉树扩容，aoe为球形，query为长方体；2D世界忽略z
+#[tokio::test]
+async fn test_dimension() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10, // 第10个触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        world_count: 2,
+        octree_worlds: SPACE_WORLD_ID.to_string(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+
+    // 2D世界的z按0处理
+    dispatcher
+        .login(player_at(DEFAULT_WORLD_ID, 100, 100.0, 200.0, 50.0).into_request())
+        .await
+        .unwrap();
+    let infos = query_all(&dispatcher).await;
+    assert_eq!(infos.len(), 1);
+    assert_eq!(infos[0].z, 0.0);
+
+    // 第10个玩家与其他玩家xy相同，但在z轴下半部分
+    for i in 0..10 {
+        let (x, z) = if i == 9 {
+            (100.0, -100.0)
+        } else {
+            (100.0 + i as f32, 100.0)
+        };
+        dispatcher
+            .login(player_at(SPACE_WORLD_ID, i, x, 200.0, z).into_request())
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(1000)).await;
+    let servers = dispatcher.get_world_servers(SPACE_WORLD_ID).unwrap();
+    assert_eq!(servers.len(), 2);
+    // 根节点分出8个卦限，新server取人数最多的一个
+    let mut zone_counts: Vec<_> = servers.iter().map(|s| s.zones.len()).collect();
+    zone_counts.sort();
+    assert_eq!(zone_counts, [1, 7]);
+    let (server0, ..) = dispatcher.get_server_of_player(&0).unwrap();
+    let (server9, ..) = dispatcher.get_server_of_player(&9).unwrap();
+    assert!(server0 != server9);
+
+    assert_eq!(query_world(&dispatcher, SPACE_WORLD_ID).await.len(), 10);
+    assert_eq!(
+        query_box(&dispatcher, SPACE_WORLD_ID, 0.0, 1000.0)
+            .await
+            .len(),
+        9
+    );
+
+    // 球形范围，正下方的玩家9不受影响
+    dispatcher
+        .aoe(
+            AoeRequest {
+                player_id: 0,
+                radius: 5.0,
+                world_id: SPACE_WORLD_ID,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    for p in query_world(&dispatcher, SPACE_WORLD_ID).await {
+        let money = if p.player_id != 0 && p.z > 0.0 && p.x <= 105.0 {
+            AOE_MONEY
+        } else {
+            0
+        };
+        assert_eq!(p.money, money, "{p:?}");
+    }
+
+    let coord = dispatcher
+        .moving(
+            MovingRequest {
+                player_id: 0,
+                dz: 10.0,
+                world_id: SPACE_WORLD_ID,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!((coord.x, coord.y, coord.z), (100.0, 200.0, 110.0));
+
+    dispatcher.shutdown_all_map_server().await;
+}
@@ -0,0 +1,171 @@
+use game_server::cluster::DispatcherId;
+use game_server::dispatcher::Dispatcher;
+use game_server::election::{FileElection, LeaderElection, Leadership, MemoryElection, Term};
+use game_server::util::Config;
+
+use common::proto::dispatcher_service::Topology;
+use common::proto::game_service::game_service_server::{GameService, GameServiceServer};
+use common::proto::game_service::PlayerIdRequest;
+
+use test_kit::cluster::{bind_local, TEST_CLUSTER_SECRET};
+use test_kit::fixture::{player, query_all};
+
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::{Code, IntoRequest};
+
+use std::sync::Arc;
+
+const TTL: Duration = Duration::from_millis(600);
+
+// 返回(leader, 任期)
+async fn campaign(election: &dyn LeaderElection, id: DispatcherId) -> (DispatcherId, Term) {
+    let Leadership { leader, term } = election.campaign(id, TTL).await.unwrap().unwrap();
+    (leader, term)
+}
+
+// leader与follower可以是同一个对象，也可以是共用租约文件的两个进程各自的对象
+async fn check_election(leader: &dyn LeaderElection, follower: &dyn LeaderElection) {
+    assert_eq!(campaign(leader, 0).await, (0, 1));
+    assert_eq!(campaign(follower, 1).await, (0, 1));
+    // 续约不换任期
+    sleep(TTL / 2).await;
+    assert_eq!(campaign(leader, 0).await, (0, 1));
+    sleep(TTL / 2).await;
+    assert_eq!(campaign(follower, 1).await, (0, 1));
+    // 过期后接管，任期+1
+    sleep(TTL).await;
+    assert_eq!(campaign(follower, 1).await, (1, 2));
+    assert_eq!(campaign(leader, 0).await, (1, 2));
+    // 非leader让出无效，leader让出后立即可接管
+    leader.resign(0).await.unwrap();
+    assert_eq!(campaign(leader, 0).await, (1, 2));
+    follower.resign(1).await.unwrap();
+    assert_eq!(campaign(leader, 0).await, (0, 3));
+}
+
+#[tokio::test]
+async fn test_election() {
+    let election = MemoryElection::default();
+    check_election(&election, &election).await;
+
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!("game-server-lease-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let election = FileElection::new(&path);
+    check_election(&election, &election).await;
+    std::fs::remove_file(&path).unwrap();
+
+    // 各进程按自己的单调时钟判断过期，文件中不记录时间
+    let path = dir.join(format!("game-server-lease2-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    check_election(&FileElection::new(&path), &FileElection::new(&path)).await;
+    // 让出后任期保留在文件中
+    let election = FileElection::new(&path);
+    assert_eq!(campaign(&election, 1).await, (0, 3));
+    std::fs::remove_file(&path).unwrap();
+    let mut lock_path = path.into_os_string();
+    lock_path.push(".lock");
+    std::fs::remove_file(lock_path).unwrap();
+}
+
+async fn start_dispatcher(
+    dispatcher_id: DispatcherId,
+    addrs: &[String],
+    listener: TcpListener,
+    election: Arc<MemoryElection>,
+) -> (Dispatcher, JoinHandle<()>) {
+    let dispatcher = Dispatcher::with_election(
+        Config {
+            max_players: 10, // 第10个触发expand
+            min_players: 3,
+            max_zone_depth: 10,
+            scaling_interval: 200,
+            dispatcher_id,
+            dispatcher_addrs: addrs.join(","),
+            cluster_secret: TEST_CLUSTER_SECRET.to_string(),
+            leader_lease_ttl: TTL.as_millis() as u64,
+            ..Default::default()
+        },
+        election,
+    )
+    .await
+    .unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(GameServiceServer::new(dispatcher.clone()))
+            .add_service(dispatcher.dispatcher_service())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    tokio::spawn(dispatcher.clone().topology_sync());
+    let monitor = tokio::spawn(dispatcher.clone().scaling_moniter());
+    (dispatcher, monitor)
+}
+
+// leader停止续约后由另一个dispatcher接管，沿用同步到的拓扑继续扩缩容
+#[tokio::test]
+async fn test_failover() {
+    crate::init_log();
+
+    let (listeners, addrs) = bind_local(2).await.unwrap();
+    let [listener0, listener1]: [_; 2] = listeners.try_into().unwrap();
+    // 0号先取得租约，但最后创建，接管后推送拓扑时follower已开始服务
+    let election = Arc::new(MemoryElection::default());
+    election.campaign(0, TTL).await.unwrap();
+    let (follower, _) = start_dispatcher(1, &addrs, listener1, election.clone()).await;
+    let (leader, leader_monitor) = start_dispatcher(0, &addrs, listener0, election.clone()).await;
+    assert!(leader.cluster.is_coordinator());
+    assert!(!follower.cluster.is_coordinator());
+
+    for i in 0..10 {
+        let x = if i == 9 { -100.0 } else { 100.0 + i as f32 };
+        follower
+            .login(player(i, x, 200.0).into_request())
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(1000)).await;
+    assert_eq!(follower.get_all_servers().len(), 2);
+    let version = *follower.topology_version.lock().unwrap();
+
+    // leader不再续约，租约过期后follower接管
+    leader_monitor.abort();
+    sleep(TTL * 2).await;
+    assert!(follower.cluster.is_coordinator());
+    assert!(*follower.topology_version.lock().unwrap() > version);
+    // 旧leader的租约在本地已到期，旧任期的拓扑被拒绝
+    assert!(!leader.cluster.is_coordinator());
+    assert!(follower.cluster.term() > 1);
+    let stale = Topology {
+        term: 1,
+        version: u64::MAX,
+        ..follower.topology()
+    };
+    let status = follower.apply_topology(stale).unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+    assert_eq!(follower.get_all_servers().len(), 2);
+    assert_eq!(query_all(&follower).await.len(), 10);
+
+    // 人数减少后由新leader缩容
+    for i in 0..6 {
+        follower
+            .logout(
+                PlayerIdRequest {
+                    player_id: i,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(1000)).await;
+    assert_eq!(follower.get_all_servers().len(), 1);
+    assert_eq!(query_all(&follower).await.len(), 4);
+    assert_eq!(query_all(&leader).await.len(), 4);
+
+    follower.shutdown_all_map_server().await;
+}
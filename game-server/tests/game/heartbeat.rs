@@ -0,0 +1,117 @@
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::game_service::{
+    game_service_server::GameService, PlayerIdRequest, PlayerInfo, QueryRequest,
+};
+
+use tokio::time::{sleep, Duration};
+use tonic::{Code, IntoRequest};
+
+// 保持心跳的玩家不受影响，空闲超时的玩家从dispatcher与map-server中登出
+#[tokio::test]
+async fn test_idle_logout() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10,
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        idle_timeout: 500,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().idle_reaper());
+
+    for player_id in 0..3 {
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id,
+                    x: 100.0 + player_id as f32,
+                    y: 200.0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    // 2主动登出
+    dispatcher
+        .logout(
+            PlayerIdRequest {
+                player_id: 2,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    assert!(!dispatcher.player_map.contains_key(&2));
+
+    for _ in 0..8 {
+        sleep(Duration::from_millis(200)).await;
+        dispatcher
+            .heartbeat(
+                PlayerIdRequest {
+                    player_id: 0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+
+    assert!(dispatcher.player_map.contains_key(&0));
+    assert!(!dispatcher.player_map.contains_key(&1));
+    assert!(!dispatcher.last_active_map.contains_key(&1));
+    let status = dispatcher
+        .heartbeat(
+            PlayerIdRequest {
+                player_id: 1,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    let infos = dispatcher
+        .query(
+            QueryRequest {
+                xmin: 0.0,
+                xmax: 1000.0,
+                ymin: 0.0,
+                ymax: 1000.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    assert_eq!(infos.len(), 1);
+    assert_eq!(infos[0].player_id, 0);
+
+    // 登出后可以重新登录
+    dispatcher
+        .login(
+            PlayerInfo {
+                player_id: 1,
+                x: 100.0,
+                y: 200.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+
+    dispatcher.shutdown_all_map_server().await;
+}
@@ -0,0 +1,109 @@
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::game_service::{
+    game_service_server::GameService, AoeRequest, MovingRequest, PlayerInfo, QueryRequest,
+};
+
+use tonic::IntoRequest;
+
+// 相同request_id的moving/aoe重试只执行一次
+#[tokio::test]
+async fn test_retry_with_request_id() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10,
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        dedup_window: 2,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    for i in 0..2 {
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x: 10.0,
+                    y: 10.0,
+                    money: 0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+
+    let moving = MovingRequest {
+        player_id: 0,
+        dx: 1.0,
+        dy: 1.0,
+        request_id: 1,
+        ..Default::default()
+    };
+    let first = dispatcher
+        .moving(moving.clone().into_request())
+        .await
+        .unwrap()
+        .into_inner();
+    let retry = dispatcher
+        .moving(moving.clone().into_request())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(first, retry);
+    assert_eq!((retry.x, retry.y), (11.0, 11.0));
+
+    let aoe = AoeRequest {
+        player_id: 0,
+        radius: 5.0,
+        request_id: 2,
+        ..Default::default()
+    };
+    dispatcher.aoe(aoe.clone().into_request()).await.unwrap();
+    dispatcher.aoe(aoe.clone().into_request()).await.unwrap();
+
+    let mut players = dispatcher
+        .query(
+            QueryRequest {
+                xmin: 0.0,
+                xmax: 20.0,
+                ymin: 0.0,
+                ymax: 20.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    players.sort_by_key(|p| p.player_id);
+    assert_eq!((players[0].x, players[0].y), (11.0, 11.0));
+    assert_eq!(players[1].money, 1);
+
+    // 窗口容量2，request_id:1已被淘汰，再次发送会重新执行
+    dispatcher
+        .aoe(
+            AoeRequest {
+                request_id: 3,
+                ..aoe
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    let coord = dispatcher
+        .moving(moving.into_request())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!((coord.x, coord.y), (12.0, 12.0));
+
+    dispatcher.shutdown_all_map_server().await;
+}
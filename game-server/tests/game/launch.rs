@@ -0,0 +1,32 @@
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+#[tokio::test]
+async fn test_map_host() {
+    crate::init_log();
+
+    // 按map_host绑定，报告的地址即绑定的地址
+    let dispatcher = Dispatcher::new(Config {
+        scaling_interval: 0,
+        map_host: "127.0.0.1".to_string(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let servers = dispatcher.get_all_servers();
+    assert!(!servers.is_empty());
+    for server in &servers {
+        assert!(server.addr.contains("127.0.0.1:"), "{}", server.addr);
+    }
+    dispatcher.shutdown_all_map_server().await;
+
+    // 无法绑定时接管失败，让出leader，没有启动的server
+    let dispatcher = Dispatcher::new(Config {
+        scaling_interval: 0,
+        map_host: "192.0.2.1".to_string(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    assert!(dispatcher.get_all_servers().is_empty());
+}
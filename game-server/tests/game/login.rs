@@ -1,9 +1,12 @@
 use game_server::dispatcher::Dispatcher;
-use game_server::util::Config;
+use game_server::util::{Config, LoginPolicy};
 
 use common::proto::game_service::{game_service_server::GameService, PlayerInfo};
 
-use tonic::IntoRequest;
+use test_kit::fixture::player;
+
+use tokio::time::{sleep, Duration};
+use tonic::{Code, IntoRequest};
 
 #[tokio::test]
 async fn test_game_login() {
@@ -14,6 +17,7 @@ async fn test_game_login() {
         min_players: 3,
         max_zone_depth: 10,
         scaling_interval: 0,
+        ..Default::default()
     })
     .await
     .unwrap();
@@ -25,6 +29,7 @@ async fn test_game_login() {
                 x: -100.0,
                 y: 200.0,
                 money: 99,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -45,3 +50,79 @@ async fn test_game_login() {
 
     dispatcher.shutdown_all_map_server().await;
 }
+
+// 重复登录：reject报错；resume保留原位置；kick_old按新坐标重新登录，可换server
+#[tokio::test]
+async fn test_duplicate_login() {
+    crate::init_log();
+
+    let config = |login_policy| Config {
+        max_players: 10, // 第10个触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        login_policy,
+        ..Default::default()
+    };
+
+    let dispatcher = Dispatcher::new(config(LoginPolicy::Reject)).await.unwrap();
+    dispatcher
+        .login(player(1, 100.0, 200.0).into_request())
+        .await
+        .unwrap();
+    let status = dispatcher
+        .login(player(1, 300.0, 300.0).into_request())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::AlreadyExists);
+    dispatcher.shutdown_all_map_server().await;
+
+    let dispatcher = Dispatcher::new(config(LoginPolicy::Resume)).await.unwrap();
+    dispatcher
+        .login(player(1, 100.0, 200.0).into_request())
+        .await
+        .unwrap();
+    dispatcher
+        .login(player(1, 300.0, 300.0).into_request())
+        .await
+        .unwrap();
+    let (_, x, y, _) = dispatcher.get_server_of_player(&1).unwrap();
+    assert_eq!((x, y), (100.0, 200.0));
+    dispatcher.shutdown_all_map_server().await;
+
+    let dispatcher = Dispatcher::new(config(LoginPolicy::KickOld)).await.unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+    for i in 0..9 {
+        dispatcher
+            .login(player(i, 100.0 + i as f32, 200.0).into_request())
+            .await
+            .unwrap();
+    }
+    dispatcher
+        .login(player(9, -100.0, 200.0).into_request())
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(1000)).await;
+
+    let (old_server, ..) = dispatcher.get_server_of_player(&0).unwrap();
+    let (other_server, ..) = dispatcher.get_server_of_player(&9).unwrap();
+    assert!(old_server != other_server);
+    dispatcher
+        .login(player(0, -101.0, 200.0).into_request())
+        .await
+        .unwrap();
+    let (new_server, x, y, _) = dispatcher.get_server_of_player(&0).unwrap();
+    assert!(new_server == other_server);
+    assert_eq!((x, y), (-101.0, 200.0));
+    let count = old_server
+        .map_cli
+        .clone()
+        .get_overhead(())
+        .await
+        .unwrap()
+        .into_inner()
+        .count;
+    assert_eq!(count, 8);
+
+    dispatcher.shutdown_all_map_server().await;
+}
@@ -1,4 +1,20 @@
 pub mod aoe;
+pub mod auth;
+pub mod broadcast;
+pub mod cluster;
+pub mod dimension;
+pub mod election;
+pub mod heartbeat;
+pub mod idempotent;
+pub mod launch;
 pub mod login;
 pub mod moving;
+pub mod npc;
 pub mod query;
+pub mod rate_limit;
+pub mod replay;
+pub mod say;
+pub mod simulation;
+pub mod teleport;
+pub mod tls;
+pub mod world;
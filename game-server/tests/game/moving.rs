@@ -16,6 +16,7 @@ async fn test_moving_cross_server() {
         min_players: 3,
         max_zone_depth: 10,
         scaling_interval: 200,
+        ..Default::default()
     })
     .await
     .unwrap();
@@ -29,6 +30,7 @@ async fn test_moving_cross_server() {
                     x: 100.0,
                     y: 200.0,
                     money: 99,
+                    ..Default::default()
                 }
                 .into_request(),
             )
@@ -43,6 +45,7 @@ async fn test_moving_cross_server() {
                 x: -100.0,
                 y: 200.0,
                 money: 99,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -82,6 +85,7 @@ async fn test_moving_cross_server() {
                 player_id: 1,
                 dx: -150.0,
                 dy: 1.0,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -114,3 +118,50 @@ async fn test_moving_cross_server() {
 
     dispatcher.shutdown_all_map_server().await;
 }
+
+// 目标在障碍物后的moving被截断，返回截断后的坐标
+#[tokio::test]
+async fn test_moving_blocked() {
+    crate::init_log();
+
+    let path = std::env::temp_dir().join("test_moving_blocked.map");
+    std::fs::write(&path, "rect -60 -1000 -50 1000\n").unwrap();
+    let dispatcher = Dispatcher::new(Config {
+        world_map_path: path.to_str().unwrap().to_string(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    dispatcher
+        .login(
+            PlayerInfo {
+                player_id: 1,
+                x: 0.0,
+                y: 0.0,
+                money: 0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    let coord = dispatcher
+        .moving(
+            MovingRequest {
+                player_id: 1,
+                dx: -100.0,
+                dy: 0.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(coord.x > -50.0 && coord.x < -49.9);
+    let (_, x, ..) = dispatcher.get_server_of_player(&1).unwrap();
+    assert_eq!(x, coord.x);
+
+    dispatcher.shutdown_all_map_server().await;
+}
@@ -0,0 +1,189 @@
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::game_service::{
+    game_service_server::GameService, AoeRequest, EntityKind, PlayerIdRequest, PlayerInfo,
+};
+use common::AOE_MONEY;
+
+use test_kit::fixture::{player, query_all};
+
+use tokio::time::{sleep, Duration};
+use tonic::{Code, IntoRequest};
+
+const NPC_ID_BASE: u64 = 1000;
+
+async fn query_npcs(dispatcher: &Dispatcher) -> Vec<PlayerInfo> {
+    query_all(dispatcher)
+        .await
+        .into_iter()
+        .filter(|p| p.kind() == EntityKind::Npc)
+        .collect()
+}
+
+async fn total_npc_count(dispatcher: &Dispatcher) -> u32 {
+    let mut total = 0;
+    for server in dispatcher.get_all_servers() {
+        total += server
+            .map_cli
+            .clone()
+            .get_overhead(())
+            .await
+            .unwrap()
+            .into_inner()
+            .npc_count;
+    }
+    total
+}
+
+// NPC可被query/aoe，不计入负载，扩容时随zone迁移
+#[tokio::test]
+async fn test_npc() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10, // 第10个玩家触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+
+    for i in 0..20 {
+        dispatcher
+            .spawn_npc(player(NPC_ID_BASE + i, 100.0 + i as f32, 200.0).into_request())
+            .await
+            .unwrap();
+    }
+    let status = dispatcher
+        .spawn_npc(player(NPC_ID_BASE, 100.0, 200.0).into_request())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::AlreadyExists);
+    sleep(Duration::from_millis(500)).await;
+    // NPC不计入负载，不扩容
+    assert_eq!(dispatcher.get_all_servers().len(), 1);
+
+    // NPC的id不能用于玩家登录/登出
+    let status = dispatcher
+        .login(player(NPC_ID_BASE, 100.0, 200.0).into_request())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::AlreadyExists);
+    let status = dispatcher
+        .logout(
+            PlayerIdRequest {
+                player_id: NPC_ID_BASE,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    for i in 0..10 {
+        let x = if i == 9 { -100.0 } else { 100.0 + i as f32 };
+        dispatcher
+            .login(player(i, x, 200.0).into_request())
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(1000)).await;
+    assert_eq!(dispatcher.get_all_servers().len(), 2);
+
+    // NPC随zone迁移到新server，dispatcher记录同步更新
+    let (npc_server, ..) = dispatcher.get_server_of_player(&NPC_ID_BASE).unwrap();
+    let (player_server, ..) = dispatcher.get_server_of_player(&0).unwrap();
+    assert!(npc_server == player_server);
+    assert_eq!(total_npc_count(&dispatcher).await, 20);
+
+    dispatcher
+        .aoe(
+            AoeRequest {
+                player_id: 0,
+                radius: 5.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    let npcs = query_npcs(&dispatcher).await;
+    assert_eq!(npcs.len(), 20);
+    for npc in &npcs {
+        let money = if npc.x <= 105.0 { AOE_MONEY } else { 0 };
+        assert_eq!(npc.money, money, "{npc:?}");
+    }
+
+    dispatcher
+        .despawn_npc(
+            PlayerIdRequest {
+                player_id: NPC_ID_BASE,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(query_npcs(&dispatcher).await.len(), 19);
+    let status = dispatcher
+        .despawn_npc(
+            PlayerIdRequest {
+                player_id: NPC_ID_BASE,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    // 玩家不能被despawn
+    let status = dispatcher
+        .despawn_npc(
+            PlayerIdRequest {
+                player_id: 0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    dispatcher.shutdown_all_map_server().await;
+}
+
+// 配置npc_as_load后NPC计入负载，可触发扩容
+#[tokio::test]
+async fn test_npc_as_load() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10,
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        npc_as_load: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+
+    for i in 0..10 {
+        let x = if i == 9 { -100.0 } else { 100.0 + i as f32 };
+        dispatcher
+            .spawn_npc(player(NPC_ID_BASE + i, x, 200.0).into_request())
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(1000)).await;
+    assert_eq!(dispatcher.get_all_servers().len(), 2);
+    assert_eq!(query_npcs(&dispatcher).await.len(), 10);
+
+    dispatcher.shutdown_all_map_server().await;
+}
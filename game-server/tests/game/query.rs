@@ -5,7 +5,7 @@ use common::{
     proto::game_service::{
         game_service_server::GameService, MovingRequest, PlayerInfo, QueryRequest,
     },
-    WORLD_X_MAX, WORLD_X_MIN, WORLD_Y_MAX, WORLD_Y_MIN,
+    AABB, DEFAULT_WORLD_ID, WORLD_X_MAX, WORLD_X_MIN, WORLD_Y_MAX, WORLD_Y_MIN,
 };
 
 use tokio::time::{sleep, Duration};
@@ -21,6 +21,7 @@ async fn test_query() {
         min_players: 3,
         max_zone_depth: 10,
         scaling_interval: 200,
+        ..Default::default()
     })
     .await
     .unwrap();
@@ -34,6 +35,7 @@ async fn test_query() {
                     x: 100.0,
                     y: 200.0,
                     money: 99,
+                    ..Default::default()
                 }
                 .into_request(),
             )
@@ -48,6 +50,7 @@ async fn test_query() {
                 xmax: 100.0,
                 ymin: 0.0,
                 ymax: 200.0,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -66,6 +69,7 @@ async fn test_query() {
                 x: -100.0,
                 y: 200.0,
                 money: 99,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -81,6 +85,7 @@ async fn test_query() {
                 xmax: 100.0,
                 ymin: 0.0,
                 ymax: 200.0,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -97,6 +102,7 @@ async fn test_query() {
                 xmax: 100.0,
                 ymin: 0.0,
                 ymax: 200.0,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -114,6 +120,7 @@ async fn test_query() {
                 player_id: 1,
                 dx: -150.0,
                 dy: 1.0,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -127,6 +134,7 @@ async fn test_query() {
                 xmax: 100.0,
                 ymin: 0.0,
                 ymax: 200.0,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -143,6 +151,7 @@ async fn test_query() {
                 xmax: 0.0,
                 ymin: 0.0,
                 ymax: 200.0,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -159,6 +168,7 @@ async fn test_query() {
                 xmax: 0.0,
                 ymin: 0.0,
                 ymax: 201.0,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -175,6 +185,7 @@ async fn test_query() {
                 xmax: 100.0,
                 ymin: 0.0,
                 ymax: 201.0,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -196,6 +207,7 @@ async fn test_full_map_query() {
         min_players: 25,
         max_zone_depth: 10,
         scaling_interval: 1000,
+        ..Default::default()
     })
     .await
     .unwrap();
@@ -209,6 +221,7 @@ async fn test_full_map_query() {
                     x: i as f32 * 10.0,
                     y: i as f32 * 10.0,
                     money: 99,
+                    ..Default::default()
                 }
                 .into_request(),
             )
@@ -224,6 +237,7 @@ async fn test_full_map_query() {
                 xmax: WORLD_X_MAX,
                 ymin: WORLD_Y_MIN,
                 ymax: WORLD_Y_MAX,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -235,3 +249,169 @@ async fn test_full_map_query() {
 
     dispatcher.shutdown_all_map_server().await;
 }
+
+// 按叶子zone精确取交集，多zone的server只收到与自己zone相交的部分
+#[tokio::test]
+async fn test_query_areas() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10, // 第10个触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+
+    // 第1象限的玩家分到新server，原server保留2~4象限
+    for i in 0..10 {
+        let x = if i == 9 { -5.0 } else { 5.0 };
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x,
+                    y: 5.0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(1000)).await;
+    assert_eq!(dispatcher.get_all_servers().len(), 2);
+    let (server0, ..) = dispatcher.get_server_of_player(&0).unwrap();
+    let (server9, ..) = dispatcher.get_server_of_player(&9).unwrap();
+    // player_map中的ServerInfo是登录时的，zones以zone_server_map中的为准
+    let zones = dispatcher
+        .get_all_servers()
+        .into_iter()
+        .find(|server| server == &server9)
+        .unwrap()
+        .zones
+        .clone();
+    assert_eq!(zones.len(), 3);
+
+    let area = |xmin, xmax, ymin, ymax| AABB {
+        xmin,
+        xmax,
+        ymin,
+        ymax,
+        zmin: 0.0,
+        zmax: 0.0,
+    };
+    let mut areas = dispatcher
+        .get_query_areas(DEFAULT_WORLD_ID, &area(-10.0, 10.0, -10.0, 10.0))
+        .unwrap();
+    areas.sort_by_key(|(server, _)| server.server_id);
+    assert_eq!(areas.len(), 2);
+    for (server, areas) in &areas {
+        if server.server_id == server0.server_id {
+            assert_eq!(areas, &[area(0.0, 10.0, 0.0, 10.0)]);
+        } else {
+            // 2~4象限中相邻的两块合并，不含第1象限
+            assert_eq!(server.server_id, server9.server_id);
+            assert_eq!(areas.len(), 2);
+            let covered = |x, y| areas.iter().any(|aabb| aabb.contains(x, y));
+            assert!(covered(-5.0, 5.0) && covered(-5.0, -5.0) && covered(5.0, -5.0));
+            assert!(!covered(5.0, 5.0));
+        }
+    }
+
+    // 只与第1象限相交
+    let areas = dispatcher
+        .get_query_areas(DEFAULT_WORLD_ID, &area(1.0, 10.0, 1.0, 10.0))
+        .unwrap();
+    assert_eq!(areas.len(), 1);
+    assert!(areas[0].0 == server0);
+
+    let infos = dispatcher
+        .query(
+            QueryRequest {
+                xmin: -10.0,
+                xmax: 10.0,
+                ymin: -10.0,
+                ymax: 10.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    assert_eq!(infos.len(), 10);
+
+    // server9的两块在公共边上相接，边上的玩家在两块中都有，一次查询并集只返回一次
+    for (player_id, x, y) in [(10, 0.0, -5.0), (11, -5.0, 0.0)] {
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id,
+                    x,
+                    y,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    let (server, areas) = dispatcher
+        .get_query_areas(DEFAULT_WORLD_ID, &area(-10.0, 10.0, -10.0, 10.0))
+        .unwrap()
+        .into_iter()
+        .find(|(server, _)| server == &server9)
+        .unwrap();
+    assert!([(0.0, -5.0), (-5.0, 0.0)].into_iter().any(|(x, y)| areas
+        .iter()
+        .filter(|aabb| aabb.contains(x, y))
+        .count()
+        == 2));
+    let infos = server
+        .game_cli
+        .clone()
+        .query(QueryRequest {
+            areas: areas
+                .iter()
+                .map(|aabb| QueryRequest {
+                    xmin: aabb.xmin,
+                    xmax: aabb.xmax,
+                    ymin: aabb.ymin,
+                    ymax: aabb.ymax,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    let mut player_ids = infos.iter().map(|p| p.player_id).collect::<Vec<_>>();
+    player_ids.sort_unstable();
+    assert_eq!(player_ids, [9, 10, 11]);
+
+    let infos = dispatcher
+        .query(
+            QueryRequest {
+                xmin: -10.0,
+                xmax: 10.0,
+                ymin: -10.0,
+                ymax: 10.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    assert_eq!(infos.len(), 12);
+
+    dispatcher.shutdown_all_map_server().await;
+}
@@ -0,0 +1,174 @@
+use game_server::dispatcher::Dispatcher;
+use game_server::rate_limit::RpcKind;
+use game_server::util::Config;
+
+use common::proto::game_service::{
+    game_service_server::GameService, MovingRequest, PlayerInfo, RejectedCount, VelocityRequest,
+};
+
+use tokio::time::{sleep, Duration};
+use tonic::{Code, IntoRequest};
+
+#[tokio::test]
+async fn test_rate_limit() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10,
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        moving_rate_limit: 5,
+        global_rate_limit: 20,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    for player_id in 0..2 {
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id,
+                    x: 100.0,
+                    y: 200.0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+
+    // 玩家桶：每秒5次moving
+    let moving = |player_id| {
+        dispatcher.moving(
+            MovingRequest {
+                player_id,
+                dx: 1.0,
+                dy: 0.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+    };
+    for _ in 0..5 {
+        moving(0).await.unwrap();
+    }
+    let status = moving(0).await.unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    assert_eq!(dispatcher.rate_limiter.rejected_count(RpcKind::Moving), 1);
+    // 不影响其他玩家
+    moving(1).await.unwrap();
+    // 1秒恢复满
+    sleep(Duration::from_millis(1000)).await;
+    moving(0).await.unwrap();
+
+    // 全局桶：其他RPC不限玩家，突发请求超过全局配额后被拒绝
+    let mut rejected = 0;
+    for _ in 0..50 {
+        let res = dispatcher
+            .set_velocity(
+                VelocityRequest {
+                    player_id: 1,
+                    vx: 0.0,
+                    vy: 0.0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await;
+        if let Err(status) = res {
+            assert_eq!(status.code(), Code::ResourceExhausted);
+            rejected += 1;
+        }
+    }
+    assert!(rejected >= 20, "rejected:{rejected}");
+    assert_eq!(
+        dispatcher.rate_limiter.rejected_count(RpcKind::SetVelocity),
+        rejected
+    );
+    assert_eq!(
+        dispatcher.rate_limiter.rejected_counts(),
+        vec![(RpcKind::Moving, 1), (RpcKind::SetVelocity, rejected)]
+    );
+
+    // 未登录的player_id不建桶，登出时清理。全局桶已耗尽，直接登出
+    let buckets = dispatcher.rate_limiter.player_bucket_count();
+    assert_eq!(buckets, 2);
+    for player_id in 100..200 {
+        let _ = moving(player_id).await.unwrap_err();
+    }
+    assert_eq!(dispatcher.rate_limiter.player_bucket_count(), buckets);
+    dispatcher.logout_player(0).await.unwrap();
+    assert_eq!(dispatcher.rate_limiter.player_bucket_count(), 1);
+
+    dispatcher.shutdown_all_map_server().await;
+}
+
+#[tokio::test]
+async fn test_global_reject_refund() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10,
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        moving_rate_limit: 2,
+        global_rate_limit: 1,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    // login用掉全局桶唯一的令牌
+    dispatcher
+        .login(
+            PlayerInfo {
+                player_id: 0,
+                x: 100.0,
+                y: 200.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+
+    // 被全局桶拒绝时归还玩家桶的令牌，不会转为被玩家桶拒绝
+    for _ in 0..10 {
+        let status = dispatcher
+            .moving(
+                MovingRequest {
+                    player_id: 0,
+                    dx: 1.0,
+                    dy: 0.0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(status.message().contains("global"), "{}", status.message());
+    }
+
+    // 全局桶恢复后查询统计
+    sleep(Duration::from_millis(1000)).await;
+    let stats = dispatcher
+        .get_rate_limit_stats(().into_request())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        stats.rejected,
+        vec![RejectedCount {
+            rpc: "Moving".to_string(),
+            count: 10,
+        }]
+    );
+    assert_eq!(stats.player_buckets, 1);
+
+    dispatcher.shutdown_all_map_server().await;
+}
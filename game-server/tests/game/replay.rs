@@ -0,0 +1,220 @@
+use game_server::auth::{Claims, ClusterAuth, Scope};
+use game_server::cluster::FORWARDED_BY_HEADER;
+use game_server::dispatcher::Dispatcher;
+use game_server::replay::{self, ReplayConfig, Target};
+use game_server::util::Config;
+
+use common::proto::game_service::game_service_server::{GameService, GameServiceServer};
+use common::proto::game_service::recorded_request::Request as Recorded;
+use common::proto::game_service::{
+    MovingRequest, PlayerIdRequest, PlayerInfo, QueryRequest, TeleportRequest,
+};
+
+use test_kit::cluster::{bind_local, TEST_CLUSTER_SECRET};
+use test_kit::fixture::player;
+
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::{Code, IntoRequest};
+
+async fn start_dispatcher(record_path: &str) -> (Dispatcher, String) {
+    let dispatcher = Dispatcher::new(Config {
+        record_path: record_path.to_string(),
+        cluster_secret: TEST_CLUSTER_SECRET.to_string(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let (listeners, addrs) = bind_local(1).await.unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(GameServiceServer::new(dispatcher.clone()))
+            .serve_with_incoming(TcpListenerStream::new(
+                listeners.into_iter().next().unwrap(),
+            )),
+    );
+    (dispatcher, addrs[0].clone())
+}
+
+// 录制一个dispatcher收到的请求，重放到另一个dispatcher，最终状态相同
+#[tokio::test]
+async fn test_record_replay() {
+    crate::init_log();
+
+    let dir = std::env::temp_dir();
+    let pid = std::process::id();
+    let record_path = dir.join(format!("game-server-record-{pid}"));
+    let baseline_path = dir.join(format!("game-server-baseline-{pid}"));
+    let state_path = dir.join(format!("game-server-state-{pid}"));
+    let _ = std::fs::remove_file(&record_path);
+    let (origin, origin_addr) = start_dispatcher(&record_path.to_string_lossy()).await;
+    let (target, target_addr) = start_dispatcher("").await;
+
+    for i in 0..4 {
+        origin
+            .login(
+                PlayerInfo {
+                    money: 99, // 非默认值，重放后比较
+                    ..player(i, i as f32 * 100.0, -200.0)
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    // 重复登录失败，同样录制
+    assert!(origin
+        .login(player(0, 0.0, 0.0).into_request())
+        .await
+        .is_err());
+    origin
+        .moving(
+            MovingRequest {
+                player_id: 1,
+                dx: 10.0,
+                dy: 20.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    origin
+        .teleport(
+            TeleportRequest {
+                player_id: 2,
+                x: -500.0,
+                y: 300.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    origin
+        .query(QueryRequest::default().into_request())
+        .await
+        .unwrap();
+    origin
+        .logout(
+            PlayerIdRequest {
+                player_id: 3,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    // 其他dispatcher转发来的不录制，客户端自己带上转发标记的照常录制
+    let forwarded = |secret: &str| {
+        let mut request = PlayerIdRequest {
+            player_id: 0,
+            ..Default::default()
+        }
+        .into_request();
+        request.metadata_mut().insert(FORWARDED_BY_HEADER, 1.into());
+        ClusterAuth::new(secret).sign(request.metadata_mut());
+        request
+    };
+    origin
+        .heartbeat(forwarded(TEST_CLUSTER_SECRET))
+        .await
+        .unwrap();
+    origin.heartbeat(forwarded("forged")).await.unwrap();
+    // 认证失败的不录制，重放时结果不同
+    let mut request = PlayerIdRequest {
+        player_id: 1,
+        ..Default::default()
+    }
+    .into_request();
+    request.extensions_mut().insert(Claims {
+        player_id: 0,
+        scope: Scope::Player,
+        expire_at: 0,
+    });
+    assert_eq!(
+        origin.heartbeat(request).await.unwrap_err().code(),
+        Code::PermissionDenied
+    );
+
+    origin.recorder.flush().await;
+    let records = replay::load_records(&record_path.to_string_lossy()).unwrap();
+    assert_eq!(records.len(), 10);
+    assert!(matches!(
+        records[4].request,
+        Some(Recorded::Login(PlayerInfo { player_id: 0, .. }))
+    ));
+    assert!(matches!(records[8].request, Some(Recorded::Logout(_))));
+    assert!(matches!(records[9].request, Some(Recorded::Heartbeat(_))));
+    assert!(records.windows(2).all(|w| w[0].time_ms <= w[1].time_ms));
+    let bytes = std::fs::read(&record_path).unwrap();
+    assert!(replay::decode_records(&bytes[..bytes.len() - 1]).is_err());
+    assert_eq!(
+        replay::world_ids(&records).into_iter().collect::<Vec<_>>(),
+        [0]
+    );
+
+    // 原dispatcher的最终状态作为基线
+    let baseline = Target::connect(&origin_addr, "")
+        .await
+        .unwrap()
+        .snapshot([0])
+        .await
+        .unwrap();
+    assert_eq!(baseline.len(), 3);
+    assert_eq!((baseline[&1].x, baseline[&1].y), (110.0, -180.0));
+    let text = replay::format_state(&baseline);
+    assert_eq!(replay::parse_state(&text).unwrap(), baseline);
+    assert!(replay::parse_state("1 0 1 2 3 99").is_err());
+    std::fs::write(&baseline_path, text).unwrap();
+    origin.recorder.flush().await;
+
+    let config = ReplayConfig {
+        addr: target_addr.clone(),
+        path: record_path.to_string_lossy().into_owned(),
+        speed: 10.0,
+        state_path: state_path.to_string_lossy().into_owned(),
+        baseline_path: baseline_path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+    let report = replay::run(&config).await.unwrap();
+    // 取基线的query也被录制
+    assert_eq!(
+        (report.requests, report.failed, report.players),
+        (11, 1, 3),
+        "{report}"
+    );
+    assert!(report.diffs.is_empty(), "{report}");
+    assert_eq!(
+        std::fs::read_to_string(&state_path).unwrap(),
+        std::fs::read_to_string(&baseline_path).unwrap()
+    );
+
+    // 重放后状态不同时列出差异
+    target
+        .teleport(
+            TeleportRequest {
+                player_id: 0,
+                x: 50.0,
+                y: 50.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    let state = Target::connect(&target_addr, "")
+        .await
+        .unwrap()
+        .snapshot([0])
+        .await
+        .unwrap();
+    let diffs = replay::diff(&baseline, &state);
+    assert_eq!(diffs, ["- 0 0 0 -200 0 99 PLAYER\n+ 0 0 50 50 0 99 PLAYER"]);
+
+    for path in [record_path, baseline_path, state_path] {
+        std::fs::remove_file(path).unwrap();
+    }
+    origin.shutdown_all_map_server().await;
+    target.shutdown_all_map_server().await;
+}
@@ -0,0 +1,137 @@
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::game_service::{
+    game_service_server::GameService, EventKind, PlayerIdRequest, PlayerInfo, SayRequest,
+    TeleportRequest,
+};
+
+use futures::StreamExt;
+use tokio::time::{sleep, timeout, Duration};
+use tonic::IntoRequest;
+
+// say发给半径内其他玩家，玩家转移服务器后事件流继续有效
+#[tokio::test]
+async fn test_say() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10, // 第10个触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+
+    for i in 0..9 {
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x: 100.0 + i as f32,
+                    y: 200.0,
+                    money: 99,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    // 第10个触发expand
+    dispatcher
+        .login(
+            PlayerInfo {
+                player_id: 9,
+                x: -100.0,
+                y: 200.0,
+                money: 99,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+
+    sleep(Duration::from_millis(1000)).await;
+
+    let mut stream = dispatcher
+        .subscribe(
+            PlayerIdRequest {
+                player_id: 1,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner();
+
+    // 半径2.5内：0,1,2,3，不含自己
+    let delivered = dispatcher
+        .say(
+            SayRequest {
+                player_id: 2,
+                radius: 2.5,
+                text: "hello".to_string(),
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .delivered;
+    // 只有1订阅了
+    assert_eq!(delivered, 1);
+    let event = timeout(Duration::from_secs(1), stream.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(event.kind(), EventKind::Chat);
+    assert_eq!(event.from_player_id, 2);
+    assert_eq!(event.text, "hello");
+
+    // 转移到player 9所在服务器
+    dispatcher
+        .teleport(
+            TeleportRequest {
+                player_id: 1,
+                x: -101.0,
+                y: 200.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(300)).await;
+    let delivered = dispatcher
+        .say(
+            SayRequest {
+                player_id: 9,
+                radius: 2.0,
+                text: "world".to_string(),
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .delivered;
+    assert_eq!(delivered, 1);
+    let event = timeout(Duration::from_secs(1), stream.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(event.from_player_id, 9);
+    assert_eq!(event.text, "world");
+
+    dispatcher.shutdown_all_map_server().await;
+}
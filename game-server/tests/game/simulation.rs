@@ -0,0 +1,153 @@
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::game_service::{game_service_server::GameService, PlayerInfo, VelocityRequest};
+
+use tokio::time::{sleep, Duration};
+use tonic::IntoRequest;
+
+// 开启tick后，按速度移动的玩家跨出所在服务器时自动导出
+#[tokio::test]
+async fn test_simulated_player_cross_server() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10, // 第10个触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        tick_interval: 50,
+        simulation_sync_interval: 100,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+    tokio::spawn(dispatcher.clone().simulation_moniter());
+
+    for i in 0..9 {
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x: 100.0,
+                    y: 200.0,
+                    money: 99,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    // 第10个触发expand
+    dispatcher
+        .login(
+            PlayerInfo {
+                player_id: 9,
+                x: -100.0,
+                y: 200.0,
+                money: 99,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+
+    sleep(Duration::from_millis(1000)).await;
+
+    let (server9, ..) = dispatcher.get_server_of_player(&9).unwrap();
+    // 从第一象限向第二象限移动
+    dispatcher
+        .set_velocity(
+            VelocityRequest {
+                player_id: 1,
+                vx: -500.0,
+                vy: 0.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+
+    sleep(Duration::from_millis(1000)).await;
+
+    let (server, x, y, _) = dispatcher.get_server_of_player(&1).unwrap();
+    assert_eq!(server.server_id, server9.server_id);
+    assert!(x < 0.0);
+    assert_eq!(y, 200.0);
+    let count = server
+        .map_cli
+        .clone()
+        .get_overhead(())
+        .await
+        .unwrap()
+        .into_inner()
+        .count;
+    assert_eq!(count, 2);
+
+    dispatcher.shutdown_all_map_server().await;
+}
+
+// 跨出所在服务器由map-server的tick推送，不等定期同步
+#[tokio::test]
+async fn test_simulated_player_leaving_pushed() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10, // 第10个触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        tick_interval: 50,
+        simulation_sync_interval: 60_000,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+
+    for i in 0..10 {
+        let x = if i == 9 { -100.0 } else { 100.0 };
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x,
+                    y: 200.0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(1000)).await;
+    assert_eq!(dispatcher.get_all_servers().len(), 2);
+    // 扩容后再订阅，之后的定期同步在60s后
+    tokio::spawn(dispatcher.clone().simulation_moniter());
+    sleep(Duration::from_millis(200)).await;
+
+    let (server9, ..) = dispatcher.get_server_of_player(&9).unwrap();
+    dispatcher
+        .set_velocity(
+            VelocityRequest {
+                player_id: 1,
+                vx: -500.0,
+                vy: 0.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(800)).await;
+
+    let (server, x, ..) = dispatcher.get_server_of_player(&1).unwrap();
+    assert_eq!(server.server_id, server9.server_id);
+    assert!(x < 0.0);
+
+    dispatcher.shutdown_all_map_server().await;
+}
@@ -0,0 +1,123 @@
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::game_service::{
+    game_service_server::GameService, PlayerInfo, QueryRequest, TeleportRequest,
+};
+
+use tokio::time::{sleep, Duration};
+use tonic::IntoRequest;
+
+// teleport到另一台服务器的zone时导出用户，同服务器内直接修改坐标
+#[tokio::test]
+async fn test_teleport_cross_server() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10, // 第10个触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+
+    for i in 0..9 {
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x: 100.0,
+                    y: 200.0,
+                    money: 99,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    // 第10个触发expand
+    dispatcher
+        .login(
+            PlayerInfo {
+                player_id: 9,
+                x: -100.0,
+                y: 200.0,
+                money: 99,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+
+    sleep(Duration::from_millis(1000)).await;
+
+    let (server0, ..) = dispatcher.get_server_of_player(&0).unwrap();
+    let (server9, ..) = dispatcher.get_server_of_player(&9).unwrap();
+    assert_ne!(server0.server_id, server9.server_id);
+
+    // 从第一象限传送到第二象限
+    let coord = dispatcher
+        .teleport(
+            TeleportRequest {
+                player_id: 1,
+                x: -300.0,
+                y: 400.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!((coord.x, coord.y), (-300.0, 400.0));
+    let (server, x, y, _) = dispatcher.get_server_of_player(&1).unwrap();
+    assert_eq!(server.server_id, server9.server_id);
+    assert_eq!((x, y), (-300.0, 400.0));
+    let count = server
+        .map_cli
+        .clone()
+        .get_overhead(())
+        .await
+        .unwrap()
+        .into_inner()
+        .count;
+    assert_eq!(count, 2);
+
+    // 同服务器内传送
+    dispatcher
+        .teleport(
+            TeleportRequest {
+                player_id: 1,
+                x: -10.0,
+                y: 10.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    let players = dispatcher
+        .query(
+            QueryRequest {
+                xmin: -20.0,
+                xmax: 0.0,
+                ymin: 0.0,
+                ymax: 20.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    assert_eq!(players.len(), 1);
+    assert_eq!(players[0].player_id, 1);
+
+    dispatcher.shutdown_all_map_server().await;
+}
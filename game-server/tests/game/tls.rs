@@ -0,0 +1,99 @@
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::game_service::{game_service_server::GameService, PlayerInfo, QueryRequest};
+use common::proto::map_service::map_service_client::MapServiceClient;
+use common::tls::{self, TlsConfig};
+use common::{WORLD_X_MAX, WORLD_X_MIN, WORLD_Y_MAX, WORLD_Y_MIN};
+
+use tokio::time::{sleep, Duration};
+use tonic::IntoRequest;
+
+// tests/certs: ca.pem签发node.pem；rogue.pem由另一个CA签发
+// 证书SAN均为DNS:localhost，有效期100年
+#[tokio::test]
+async fn test_mutual_tls() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10, // 第10个触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        tls_ca_path: "tests/certs/ca.pem".to_string(),
+        tls_cert_path: "tests/certs/node.pem".to_string(),
+        tls_key_path: "tests/certs/node.key".to_string(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+
+    for i in 0..10 {
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x: if i % 2 == 0 { 100.0 } else { -100.0 },
+                    y: 200.0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(1000)).await;
+
+    // 扩容时map-server之间导出用户同样走TLS
+    let servers = dispatcher.get_all_servers();
+    assert_eq!(servers.len(), 2);
+    let infos = dispatcher
+        .query(
+            QueryRequest {
+                xmin: WORLD_X_MIN,
+                xmax: WORLD_X_MAX,
+                ymin: WORLD_Y_MIN,
+                ymax: WORLD_Y_MAX,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    assert_eq!(infos.len(), 10);
+
+    let addr = servers[0].addr.clone();
+    assert!(addr.starts_with("https://"));
+
+    // 不带证书
+    let plain = TlsConfig::load_optional("tests/certs/ca.pem", "", "", "");
+    assert!(plain.is_err());
+    let res = async {
+        let channel = tls::connect(addr.replacen("https", "http", 1), None).await?;
+        MapServiceClient::new(channel).shutdown(()).await?;
+        anyhow::Ok(())
+    }
+    .await;
+    assert!(res.is_err());
+
+    // 其他CA签发的客户端证书
+    let rogue = TlsConfig::load(
+        "tests/certs/ca.pem",
+        "tests/certs/rogue.pem",
+        "tests/certs/rogue.key",
+        "",
+    )
+    .unwrap();
+    let res = async {
+        let channel = tls::connect(addr.clone(), Some(&rogue)).await?;
+        MapServiceClient::new(channel).shutdown(()).await?;
+        anyhow::Ok(())
+    }
+    .await;
+    assert!(res.is_err());
+
+    dispatcher.shutdown_all_map_server().await;
+}
@@ -0,0 +1,145 @@
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::game_service::{
+    game_service_server::GameService, AoeRequest, ChangeWorldRequest, MovingRequest,
+};
+use common::{WorldId, DEFAULT_WORLD_ID};
+
+use test_kit::fixture::{player, player_at, query_world};
+
+use tokio::time::{sleep, Duration};
+use tonic::{Code, IntoRequest};
+
+const DUNGEON_WORLD_ID: WorldId = 1;
+
+// 各世界互相隔离，独立扩缩容；change_world在世界间转移玩家
+#[tokio::test]
+async fn test_world() {
+    crate::init_log();
+
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10, // 第10个触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        world_count: 2,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+    assert_eq!(dispatcher.get_all_servers().len(), 2);
+
+    let status = dispatcher
+        .login(player_at(2, 100, 0.0, 0.0, 0.0).into_request())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    dispatcher
+        .login(player(100, 100.0, 200.0).into_request())
+        .await
+        .unwrap();
+    // 副本中同一坐标的玩家，第10个触发副本扩容
+    for i in 0..10 {
+        let x = if i == 9 { -100.0 } else { 100.0 + i as f32 };
+        dispatcher
+            .login(player_at(DUNGEON_WORLD_ID, i, x, 200.0, 0.0).into_request())
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(1000)).await;
+    assert_eq!(dispatcher.get_all_servers().len(), 3);
+    assert_eq!(
+        dispatcher
+            .get_world_servers(DEFAULT_WORLD_ID)
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(
+        dispatcher
+            .get_world_servers(DUNGEON_WORLD_ID)
+            .unwrap()
+            .len(),
+        2
+    );
+
+    // query与aoe只作用于本世界
+    let infos = query_world(&dispatcher, DEFAULT_WORLD_ID).await;
+    assert_eq!(infos.len(), 1);
+    assert_eq!(query_world(&dispatcher, DUNGEON_WORLD_ID).await.len(), 10);
+    dispatcher
+        .aoe(
+            AoeRequest {
+                player_id: 100,
+                radius: 5.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    assert!(query_world(&dispatcher, DUNGEON_WORLD_ID)
+        .await
+        .iter()
+        .all(|p| p.money == 0));
+
+    // world_id与玩家所在世界不一致
+    let status = dispatcher
+        .moving(
+            MovingRequest {
+                player_id: 0,
+                dx: 1.0,
+                world_id: DEFAULT_WORLD_ID,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+
+    dispatcher
+        .change_world(
+            ChangeWorldRequest {
+                player_id: 100,
+                world_id: DEFAULT_WORLD_ID,
+                target_world_id: DUNGEON_WORLD_ID,
+                x: -150.0,
+                y: 200.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    assert!(query_world(&dispatcher, DEFAULT_WORLD_ID).await.is_empty());
+    let infos = query_world(&dispatcher, DUNGEON_WORLD_ID).await;
+    assert_eq!(infos.len(), 11);
+    let moved = infos.iter().find(|p| p.player_id == 100).unwrap();
+    assert_eq!(
+        (moved.x, moved.y, moved.world_id),
+        (-150.0, 200.0, DUNGEON_WORLD_ID)
+    );
+    let (server, ..) = dispatcher.get_server_of_player(&100).unwrap();
+    let (server9, ..) = dispatcher.get_server_of_player(&9).unwrap();
+    assert!(server == server9);
+
+    // 之后的请求要带新的world_id
+    dispatcher
+        .moving(
+            MovingRequest {
+                player_id: 100,
+                dx: 1.0,
+                world_id: DUNGEON_WORLD_ID,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+
+    dispatcher.shutdown_all_map_server().await;
+}
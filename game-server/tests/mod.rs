@@ -8,6 +8,7 @@ use common::proto::game_service::{game_service_server::GameService, PlayerInfo};
 
 use tonic::IntoRequest;
 
+#[allow(clippy::redundant_closure)]
 pub fn init_log() {
     use once_cell::sync::OnceCell;
 
@@ -24,6 +25,7 @@ async fn dispatcher_works() {
         min_players: 3,
         max_zone_depth: 10,
         scaling_interval: 10,
+        ..Default::default()
     })
     .await
     .unwrap();
@@ -34,6 +36,7 @@ async fn dispatcher_works() {
                 x: 100.0,
                 y: 200.0,
                 money: 99,
+                ..Default::default()
             }
             .into_request(),
         )
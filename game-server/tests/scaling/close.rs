@@ -1,89 +1,100 @@
-use game_server::dispatcher::Dispatcher;
+use common::proto::game_service::ScalingEventKind;
+
 use game_server::util::Config;
 
-use common::proto::game_service::{game_service_server::GameService, PlayerIdRequest, PlayerInfo};
+use test_kit::TestCluster;
 
-use tokio::time::{sleep, Duration};
-use tonic::IntoRequest;
+use tokio::time::{timeout, Duration};
 
-// 扩容测试
+// 缩容测试
 #[tokio::test]
 async fn close_idle_server() {
     crate::init_log();
 
-    let dispatcher = Dispatcher::new(Config {
+    let cluster = TestCluster::start(Config {
         max_players: 10, // 第10个触发expand
         min_players: 3,
         max_zone_depth: 10,
-        scaling_interval: 200,
+        ..Default::default()
     })
     .await
     .unwrap();
-    tokio::spawn(dispatcher.clone().scaling_moniter());
 
     for i in 0..9 {
-        dispatcher
-            .login(
-                PlayerInfo {
-                    player_id: i,
-                    x: 100.0,
-                    y: 200.0,
-                    money: 99,
-                }
-                .into_request(),
-            )
-            .await
-            .unwrap();
+        cluster.login(i, 100.0, 200.0).await.unwrap();
     }
     // 第10个触发expand
-    dispatcher
-        .login(
-            PlayerInfo {
-                player_id: 9,
-                x: -100.0,
-                y: 200.0,
-                money: 99,
-            }
-            .into_request(),
-        )
-        .await
-        .unwrap();
+    cluster.login(9, -100.0, 200.0).await.unwrap();
+    cluster.scaling_round().await.unwrap();
 
-    sleep(Duration::from_millis(1000)).await;
+    let topology = cluster.wait_for_servers(2).await.unwrap();
+    let (server, ..) = cluster.leader().get_server_of_player(&0).unwrap();
+    assert_eq!(topology.servers[&server.server_id].players, 9);
+    let (server, ..) = cluster.leader().get_server_of_player(&9).unwrap();
+    assert_eq!(topology.servers[&server.server_id].players, 1);
 
-    let (server, ..) = dispatcher.get_server_of_player(&0).unwrap();
-    let count = server
-        .map_cli
-        .clone()
-        .get_overhead(())
-        .await
-        .unwrap()
-        .into_inner()
-        .count;
+    // logout 1个触发缩容
+    cluster.logout(1).await.unwrap();
+    cluster.scaling_round().await.unwrap();
+    let topology = cluster.wait_for_servers(1).await.unwrap();
+    assert_eq!(topology.player_counts(), [9]);
+    let (server1, ..) = cluster.leader().get_server_of_player(&0).unwrap();
+    let (server2, ..) = cluster.leader().get_server_of_player(&9).unwrap();
+    assert_eq!(server1.server_id, server2.server_id);
+    cluster.assert_consistent().await;
 
-    assert_eq!(count, 9);
+    cluster.teardown().await;
+}
 
-    let (server, ..) = dispatcher.get_server_of_player(&9).unwrap();
-    let count = server
-        .map_cli
-        .clone()
-        .get_overhead(())
-        .await
-        .unwrap()
-        .into_inner()
-        .count;
+// 同一轮内多台server缩容，后处理的server按前面合并后的拓扑取zone，已关闭的跳过
+#[tokio::test]
+async fn close_idle_servers_in_one_round() {
+    crate::init_log();
+
+    let cluster = TestCluster::start(Config {
+        max_players: 10,
+        min_players: 3,
+        max_zone_depth: 10,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
 
-    assert_eq!(count, 1);
+    // 第1象限拆出一台，之后只留2个玩家
+    for i in 0..10 {
+        cluster.login(i, 100.0, 200.0).await.unwrap();
+    }
+    cluster.scaling_round().await.unwrap();
+    cluster.wait_for_servers(2).await.unwrap();
+    for i in 2..10 {
+        cluster.logout(i).await.unwrap();
+    }
+    // 第2象限再拆出一台，原server剩下3、4象限
+    for i in 10..20 {
+        cluster.login(i, -100.0, 200.0).await.unwrap();
+    }
+    cluster.scaling_round().await.unwrap();
+    cluster.wait_for_servers(3).await.unwrap();
 
-    // logout 1个触发缩容
-    dispatcher
-        .logout(PlayerIdRequest { player_id: 1 }.into_request())
+    // 三台都空闲，一轮内先后合并。按过期的zone处理时会把server合并到自身，一直转移不完
+    for i in 11..20 {
+        cluster.logout(i).await.unwrap();
+    }
+    timeout(Duration::from_secs(10), cluster.scaling_round())
         .await
+        .expect("scaling round timed out")
         .unwrap();
-    sleep(Duration::from_millis(1000)).await;
-    let (server1, ..) = dispatcher.get_server_of_player(&0).unwrap();
-    let (server2, ..) = dispatcher.get_server_of_player(&9).unwrap();
-    assert_eq!(server1.server_id, server2.server_id);
+    let topology = cluster.wait_for_servers(1).await.unwrap();
+    assert_eq!(topology.player_counts(), [3]);
+    let failures = cluster
+        .leader()
+        .scaling_log
+        .query(&Default::default(), &[])
+        .into_iter()
+        .filter(|event| event.kind() == ScalingEventKind::ScalingFailed)
+        .count();
+    assert_eq!(failures, 0);
+    cluster.assert_consistent().await;
 
-    dispatcher.shutdown_all_map_server().await;
+    cluster.teardown().await;
 }
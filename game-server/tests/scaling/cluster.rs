@@ -0,0 +1,146 @@
+use game_server::auth::ClusterAuth;
+use game_server::cluster::FORWARDED_BY_HEADER;
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::dispatcher_service::dispatcher_service_client::DispatcherServiceClient;
+use common::proto::game_service::PlayerInfo;
+
+use test_kit::cluster::TEST_CLUSTER_SECRET;
+use test_kit::wait::wait_until;
+use test_kit::TestCluster;
+
+use tokio::time::Duration;
+use tonic::transport::Channel;
+use tonic::{Code, IntoRequest};
+
+// 多个dispatcher的测试集群，玩家经0号转发到所属dispatcher，扩缩容后各dispatcher拓扑一致
+#[tokio::test]
+async fn test_cluster_scaling() {
+    crate::init_log();
+
+    let cluster = TestCluster::builder(Config {
+        max_players: 10, // 第10个触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        ..Default::default()
+    })
+    .dispatchers(3)
+    .timeout(Duration::from_secs(2))
+    .start()
+    .await
+    .unwrap();
+    cluster.wait_for_sync().await.unwrap();
+    assert_eq!(cluster.topology().await.unwrap().server_count(), 1);
+
+    for i in 0..10 {
+        let x = if i == 9 { -100.0 } else { 100.0 };
+        cluster.login(i, x, 200.0).await.unwrap();
+    }
+    assert!(cluster
+        .dispatchers
+        .iter()
+        .all(|dispatcher| dispatcher.player_map.len() < 10));
+    cluster.scaling_round().await.unwrap();
+    let topology = cluster.wait_for_servers(2).await.unwrap();
+    assert_eq!(topology.player_counts(), [9, 1]);
+    cluster.assert_consistent().await;
+
+    cluster.logout(1).await.unwrap();
+    cluster.scaling_round().await.unwrap();
+    let topology = cluster.wait_for_servers(1).await.unwrap();
+    assert_eq!(topology.total_players(), 9);
+    cluster.assert_consistent().await;
+
+    // 超时的错误带上等待的条件与最后一次的拓扑
+    let err = cluster.wait_for_servers(3).await.unwrap_err().to_string();
+    assert!(
+        err.contains("3 servers") && err.contains("players:9"),
+        "{err}"
+    );
+
+    cluster.teardown().await;
+}
+
+#[tokio::test]
+async fn test_wait_until() {
+    let mut polls = 0;
+    wait_until("third poll", Duration::from_secs(1), || {
+        polls += 1;
+        let done = polls >= 3;
+        async move { done }
+    })
+    .await
+    .unwrap();
+    assert_eq!(polls, 3);
+
+    let err = wait_until("never", Duration::from_millis(50), || async { false })
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("never"), "{err}");
+}
+
+// DispatcherService与转发标记只信任带集群密钥的请求
+#[tokio::test]
+async fn test_cluster_secret() {
+    crate::init_log();
+
+    let config = Config {
+        max_zone_depth: 10,
+        ..Default::default()
+    };
+    // 多个dispatcher时必须配置密钥
+    assert!(Dispatcher::new(Config {
+        dispatcher_addrs: "http://127.0.0.1:1,http://127.0.0.1:2".to_string(),
+        ..config.clone()
+    })
+    .await
+    .is_err());
+
+    let cluster = TestCluster::builder(config)
+        .dispatchers(2)
+        .start()
+        .await
+        .unwrap();
+    cluster.wait_for_sync().await.unwrap();
+
+    let mut cli = DispatcherServiceClient::connect(cluster.addrs[1].clone())
+        .await
+        .unwrap();
+    let status = cli.get_topology(()).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    let channel = Channel::from_shared(cluster.addrs[1].clone())
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let mut cli = DispatcherServiceClient::with_interceptor(
+        channel,
+        ClusterAuth::new(TEST_CLUSTER_SECRET).signer(),
+    );
+    cli.get_topology(()).await.unwrap();
+
+    // 客户端伪造转发标记，仍转发给玩家所属的dispatcher
+    let player_id = (0..)
+        .find(|&id| !cluster.dispatchers[0].cluster.is_local(id))
+        .unwrap();
+    let mut request = PlayerInfo {
+        player_id,
+        x: 100.0,
+        y: 200.0,
+        ..Default::default()
+    }
+    .into_request();
+    request.metadata_mut().insert(FORWARDED_BY_HEADER, 1.into());
+    cluster
+        .client(0)
+        .await
+        .unwrap()
+        .login(request)
+        .await
+        .unwrap();
+    assert!(!cluster.dispatchers[0].player_map.contains_key(&player_id));
+    assert!(cluster.dispatchers[1].player_map.contains_key(&player_id));
+
+    cluster.teardown().await;
+}
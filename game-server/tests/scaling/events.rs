@@ -0,0 +1,233 @@
+use game_server::dispatcher::Dispatcher;
+use game_server::rate_limit::RpcKind;
+use game_server::scaling_log::ScalingLog;
+use game_server::util::Config;
+
+use common::proto::game_service::{
+    game_service_server::GameService, PlayerIdRequest, PlayerInfo, ScalingEvent, ScalingEventKind,
+    ScalingEventsRequest,
+};
+use common::{Dimension, ZoneId};
+
+use test_kit::TestCluster;
+
+use tokio::time::{sleep, Duration};
+use tonic::{Code, IntoRequest};
+
+async fn get_events(dispatcher: &Dispatcher, filter: ScalingEventsRequest) -> Vec<ScalingEvent> {
+    dispatcher
+        .get_scaling_events(filter.into_request())
+        .await
+        .unwrap()
+        .into_inner()
+        .events
+}
+
+fn kinds(events: &[ScalingEvent]) -> Vec<ScalingEventKind> {
+    events.iter().map(|event| event.kind()).collect()
+}
+
+#[test]
+fn test_scaling_log_capacity() {
+    let log = ScalingLog::new(&Config {
+        scaling_log_capacity: 2,
+        ..Default::default()
+    })
+    .unwrap();
+    for player_count in 0..3 {
+        log.record(ScalingEvent {
+            player_count,
+            ..Default::default()
+        });
+    }
+    let events = log.query(&ScalingEventsRequest::default(), &[]);
+    assert_eq!(
+        events
+            .iter()
+            .map(|event| (event.seq, event.player_count))
+            .collect::<Vec<_>>(),
+        [(1, 1), (2, 2)]
+    );
+}
+
+// 扩容、缩容各阶段的事件，按server、zone、时间过滤
+#[tokio::test]
+async fn test_scaling_events() {
+    crate::init_log();
+
+    let path = std::env::temp_dir().join(format!("game-server-scaling-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let dispatcher = Dispatcher::new(Config {
+        max_players: 10, // 第10个触发expand
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 200,
+        scaling_log_path: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    tokio::spawn(dispatcher.clone().scaling_moniter());
+
+    for i in 0..10 {
+        let x = if i == 9 { -100.0 } else { 100.0 };
+        dispatcher
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x,
+                    y: 200.0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(1000)).await;
+
+    let events = get_events(&dispatcher, ScalingEventsRequest::default()).await;
+    assert_eq!(
+        kinds(&events),
+        [
+            ScalingEventKind::ExpandStarted,
+            ScalingEventKind::ZoneRegistered,
+            ScalingEventKind::PlayersTransferred,
+            ScalingEventKind::ExpandFinished,
+        ]
+    );
+    let finished = &events[3];
+    let (old_server, new_server) = (finished.server_ids[0], finished.server_ids[1]);
+    let new_zone = ZoneId::from_raw(finished.zone_ids[0]).unwrap();
+    assert_eq!(finished.player_count, 9);
+    assert_eq!(
+        dispatcher.get_server_of_player(&0).unwrap().0.server_id,
+        new_server
+    );
+    assert!(events.windows(2).all(|w| w[0].seq < w[1].seq));
+
+    // 只涉及原server的事件
+    let filter = ScalingEventsRequest {
+        server_ids: vec![old_server],
+        ..Default::default()
+    };
+    assert_eq!(get_events(&dispatcher, filter).await.len(), 4);
+    // 兄弟zone只与记录根节点的开始事件相关
+    let sibling = new_zone
+        .parent()
+        .unwrap()
+        .children()
+        .find(|&id| id != new_zone);
+    let filter = ScalingEventsRequest {
+        zone_ids: vec![sibling.unwrap().into()],
+        ..Default::default()
+    };
+    assert_eq!(
+        kinds(&get_events(&dispatcher, filter).await),
+        [ScalingEventKind::ExpandStarted]
+    );
+    let filter = ScalingEventsRequest {
+        zone_ids: vec![new_zone.child(1).into()],
+        ..Default::default()
+    };
+    assert_eq!(get_events(&dispatcher, filter).await.len(), 4);
+    // 时间范围与条数
+    let filter = ScalingEventsRequest {
+        start_ms: finished.time_ms + 1,
+        ..Default::default()
+    };
+    assert!(get_events(&dispatcher, filter).await.is_empty());
+    let filter = ScalingEventsRequest {
+        start_ms: events[0].time_ms,
+        end_ms: finished.time_ms + 1,
+        limit: 1,
+        ..Default::default()
+    };
+    assert_eq!(get_events(&dispatcher, filter).await, [finished.clone()]);
+    let filter = ScalingEventsRequest {
+        world_id: 1,
+        ..Default::default()
+    };
+    assert!(dispatcher
+        .get_scaling_events(filter.into_request())
+        .await
+        .is_err());
+
+    // 人数减少后合并回根节点
+    for i in 0..7 {
+        dispatcher
+            .logout(
+                PlayerIdRequest {
+                    player_id: i,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(1000)).await;
+    assert_eq!(dispatcher.get_all_servers().len(), 1);
+    let events = get_events(&dispatcher, ScalingEventsRequest::default()).await;
+    let merged = events.last().unwrap();
+    assert_eq!(merged.kind(), ScalingEventKind::MergeFinished);
+    assert_eq!(merged.zone_ids, [u64::from(ZoneId::root(Dimension::D2))]);
+    assert!(events.iter().all(|event| event.message.is_empty()));
+
+    // 文件中逐行追加了同样的事件
+    let lines = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(lines.lines().count(), events.len());
+    assert!(lines.lines().last().unwrap().contains("MergeFinished"));
+    std::fs::remove_file(&path).unwrap();
+
+    dispatcher.shutdown_all_map_server().await;
+}
+
+// 发到非leader的请求先在本地认证、限流，通过的才转发给leader
+#[tokio::test]
+async fn test_scaling_events_forwarded() {
+    crate::init_log();
+
+    let cluster = TestCluster::builder(Config {
+        max_zone_depth: 10,
+        global_rate_limit: 5,
+        ..Default::default()
+    })
+    .dispatchers(2)
+    .start()
+    .await
+    .unwrap();
+    cluster.wait_for_sync().await.unwrap();
+    let leader = cluster.leader();
+    let follower = cluster
+        .dispatchers
+        .iter()
+        .find(|dispatcher| !dispatcher.cluster.is_coordinator())
+        .unwrap();
+
+    let mut rejected = 0;
+    for _ in 0..20 {
+        let res = follower
+            .get_scaling_events(ScalingEventsRequest::default().into_request())
+            .await;
+        if let Err(status) = res {
+            assert_eq!(status.code(), Code::ResourceExhausted);
+            rejected += 1;
+        }
+    }
+    assert!(rejected > 0);
+    assert_eq!(
+        follower
+            .rate_limiter
+            .rejected_count(RpcKind::GetScalingEvents),
+        rejected
+    );
+    assert_eq!(
+        leader
+            .rate_limiter
+            .rejected_count(RpcKind::GetScalingEvents),
+        0
+    );
+
+    cluster.teardown().await;
+}
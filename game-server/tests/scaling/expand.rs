@@ -1,79 +1,68 @@
-use game_server::dispatcher::Dispatcher;
 use game_server::util::Config;
 
-use common::proto::game_service::{game_service_server::GameService, PlayerInfo};
-
-use tokio::time::{sleep, Duration};
-use tonic::IntoRequest;
+use test_kit::TestCluster;
 
 // 扩容测试
 #[tokio::test]
 async fn test_expand_overload_server() {
     // crate::init_log();
 
-    let dispatcher = Dispatcher::new(Config {
+    let cluster = TestCluster::start(Config {
         max_players: 10, // 第10个触发expand
         min_players: 3,
         max_zone_depth: 10,
-        scaling_interval: 200,
+        ..Default::default()
     })
     .await
     .unwrap();
-    tokio::spawn(dispatcher.clone().scaling_moniter());
 
     for i in 0..9 {
-        dispatcher
-            .login(
-                PlayerInfo {
-                    player_id: i,
-                    x: 100.0,
-                    y: 200.0,
-                    money: 99,
-                }
-                .into_request(),
-            )
-            .await
-            .unwrap();
+        cluster.login(i, 100.0, 200.0).await.unwrap();
     }
     // 第10个触发expand
-    dispatcher
-        .login(
-            PlayerInfo {
-                player_id: 9,
-                x: -100.0,
-                y: 200.0,
-                money: 99,
-            }
-            .into_request(),
-        )
-        .await
-        .unwrap();
+    cluster.login(9, -100.0, 200.0).await.unwrap();
+    cluster.scaling_round().await.unwrap();
 
-    sleep(Duration::from_millis(1000)).await;
+    let topology = cluster.wait_for_servers(2).await.unwrap();
+    let (server, ..) = cluster.leader().get_server_of_player(&0).unwrap();
+    assert_eq!(topology.servers[&server.server_id].players, 9);
+    let (server, ..) = cluster.leader().get_server_of_player(&9).unwrap();
+    assert_eq!(topology.servers[&server.server_id].players, 1);
+    cluster.assert_consistent().await;
 
-    let (server, ..) = dispatcher.get_server_of_player(&0).unwrap();
-    let count = server
-        .map_cli
-        .clone()
-        .get_overhead(())
-        .await
-        .unwrap()
-        .into_inner()
-        .count;
+    cluster.teardown().await;
+}
 
-    assert_eq!(count, 9);
+// 与正式运行一样由scaling_moniter定期扩缩容，不手动执行scaling_round
+#[tokio::test]
+async fn test_auto_scaling() {
+    crate::init_log();
 
-    let (server, ..) = dispatcher.get_server_of_player(&9).unwrap();
-    let count = server
-        .map_cli
-        .clone()
-        .get_overhead(())
-        .await
-        .unwrap()
-        .into_inner()
-        .count;
+    let cluster = TestCluster::builder(Config {
+        max_players: 10,
+        min_players: 3,
+        max_zone_depth: 10,
+        scaling_interval: 100,
+        ..Default::default()
+    })
+    .auto_scaling(true)
+    .start()
+    .await
+    .unwrap();
+
+    for i in 0..9 {
+        cluster.login(i, 100.0, 200.0).await.unwrap();
+    }
+    cluster.login(9, -100.0, 200.0).await.unwrap();
+    let topology = cluster.wait_for_servers(2).await.unwrap();
+    assert_eq!(topology.total_players(), 10);
+    cluster.assert_consistent().await;
 
-    assert_eq!(count, 1);
+    // logout后自动合并回一台
+    cluster.logout(1).await.unwrap();
+    let topology = cluster.wait_for_servers(1).await.unwrap();
+    assert_eq!(topology.player_counts(), [9]);
+    cluster.assert_consistent().await;
 
-    dispatcher.shutdown_all_map_server().await;
+    cluster.teardown().await;
 }
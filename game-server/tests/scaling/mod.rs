@@ -1,2 +1,5 @@
 pub mod close;
+pub mod cluster;
+pub mod events;
 pub mod expand;
+pub mod simulator;
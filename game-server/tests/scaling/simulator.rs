@@ -0,0 +1,99 @@
+use game_server::simulator::distribution::{Action, Distribution, Pattern, Synthetic, Trace};
+use game_server::simulator::{self, SimConfig};
+use game_server::util::Config;
+
+fn sim_config() -> SimConfig {
+    SimConfig {
+        duration: 20_000,
+        step: 1000,
+        players: 30,
+        pattern: "uniform".to_string(),
+        area: 900_000.0,
+        speed: 10_000.0,
+        ..Default::default()
+    }
+}
+
+// 同一种子结果相同，人数在中点达到峰值，结束时全部登出
+#[test]
+fn test_distribution() {
+    let config = sim_config();
+    let mut a = Synthetic::new(Pattern::Uniform, &config).unwrap();
+    let mut b = Synthetic::new(Pattern::Uniform, &config).unwrap();
+    let mut online = 0i64;
+    for t in (0..=config.duration).step_by(config.step as usize) {
+        let actions = a.step(t);
+        assert_eq!(actions, b.step(t));
+        for action in actions {
+            match action {
+                Action::Login(..) => online += 1,
+                Action::Logout(..) => online -= 1,
+                Action::Move(_, x, y) => assert!(x.abs() <= 900_000.0 && y.abs() <= 900_000.0),
+            }
+        }
+        if t == config.duration / 2 {
+            assert_eq!(online, 30);
+        }
+    }
+    assert_eq!(online, 0);
+    assert!("ring".parse::<Pattern>().is_err());
+    for area in [0.0, -1.0, f32::NAN] {
+        assert!(Synthetic::new(
+            Pattern::Hotspot,
+            &SimConfig {
+                area,
+                ..sim_config()
+            }
+        )
+        .is_err());
+    }
+
+    let mut trace = Trace::parse(
+        "# t player x y
+        0 1 10 20
+        0 2 -10 20
+        500 1 15 20
+        1500 2 logout
+        1500 1 logout",
+    )
+    .unwrap();
+    assert_eq!(trace.end_ms(), 1500);
+    assert_eq!(
+        trace.step(0),
+        [Action::Login(1, 10.0, 20.0), Action::Login(2, -10.0, 20.0)]
+    );
+    assert_eq!(trace.step(1000), [Action::Move(1, 15.0, 20.0)]);
+    assert_eq!(trace.step(1500), [Action::Logout(2), Action::Logout(1)]);
+    assert!(Trace::parse("0 1 10").is_err());
+    assert!(Trace::parse("10 1 10 10\n5 1 10 10").is_err());
+}
+
+// 人数先增后减，扩容后又合并回一台
+#[tokio::test]
+async fn test_simulator() {
+    crate::init_log();
+
+    let report = simulator::run(
+        Config {
+            max_players: 10,
+            min_players: 3,
+            max_zone_depth: 10,
+            scaling_interval: 2000,
+            scaling_log_capacity: 0, // 统计不依赖保留的事件
+            ..Default::default()
+        },
+        &sim_config(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(report.samples.len(), 10);
+    assert!(report.expands > 0 && report.merges > 0, "{report}");
+    assert!(report.transferred > 0);
+    assert_eq!(report.failures, 0);
+    let peak = report.samples.iter().map(|s| s.servers).max().unwrap();
+    assert!(peak > 1);
+    let last = report.samples.last().unwrap();
+    assert_eq!((last.players, last.servers), (0, 1), "{report}");
+    assert_eq!(report.to_string().lines().count(), report.samples.len() + 2);
+}
@@ -5,15 +5,26 @@ use common::proto::game_service::*;
 use common::*;
 
 use rayon::prelude::*;
-use tonic::{async_trait, Request, Response, Status};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{async_trait, IntoRequest, Request, Response, Status};
 use tracing::*;
 
 #[async_trait]
 impl GameService for MapServer {
+    type SubscribeStream = ReceiverStream<Result<PlayerEvent, Status>>;
+
     #[instrument(skip(self),fields(addr = %self.addr))]
     async fn login(&self, request: Request<PlayerInfo>) -> RPCResult<()> {
         async fn inner_login(server: MapServer, player: PlayerInfo) -> RPCResult<()> {
             let player_id = player.player_id;
+            if server.world_map.is_blocked(player.x, player.y) {
+                return Err(Status::invalid_argument(format!(
+                    "x:{} y:{} is blocked",
+                    player.x, player.y
+                )));
+            }
+            let _guard = server.lock_player(player_id);
             if server.player_map.contains_key(&player.player_id) {
                 return Err(Status::already_exists(format!(
                     "player_id:{} was already login",
@@ -21,7 +32,7 @@ impl GameService for MapServer {
                 )));
             }
 
-            let grid = xy_to_grid(player.x, player.y);
+            let grid = xyz_to_grid(player.x, player.y, player.z);
             server
                 .grid_player_map
                 .get_or_insert_with(grid, Default::default)
@@ -42,9 +53,10 @@ impl GameService for MapServer {
     #[instrument(skip(self),fields(addr = %self.addr,))]
     async fn logout(&self, request: Request<PlayerIdRequest>) -> RPCResult<()> {
         async fn inner_logout(server: MapServer, id: PlayerId) -> RPCResult<()> {
+            let guard = server.lock_player(id);
             if let Some(entry) = server.player_map.remove(&id) {
                 let p = entry.value();
-                let grid = xy_to_grid(p.x, p.y);
+                let grid = xyz_to_grid(p.x, p.y, p.z);
                 let entry = server.grid_player_map.get(&grid).ok_or_else(|| {
                     Status::unknown(format!("player_id:{id} not in the grid_player_map"))
                 })?;
@@ -54,6 +66,10 @@ impl GameService for MapServer {
                     entry.value().remove(&id);
                 }
             };
+            server.exporting.remove(&id);
+            drop(guard);
+            // 关闭事件流，导出时由dispatcher到新服务器重新订阅
+            server.close_event_stream(id);
             Ok(Response::new(()))
         }
 
@@ -68,42 +84,22 @@ impl GameService for MapServer {
     #[instrument(skip(self),fields(addr = %self.addr, player_id = %request.get_ref().player_id))]
     async fn moving(&self, request: Request<MovingRequest>) -> RPCResult<Coord> {
         async fn inner_moving(server: MapServer, request: MovingRequest) -> RPCResult<Coord> {
-            let MovingRequest { player_id, dx, dy } = request;
-            let mut player = server.get_player_info(&player_id).map_err_unknown()?;
-            let x0 = player.x;
-            let y0 = player.y;
-            player.x += dx;
-            player.y += dy;
-
-            let origin_grid = xy_to_grid(x0, y0);
-            let target_grid = xy_to_grid(player.x, player.y);
-            // 跨越grid，先删后插
-            if target_grid != origin_grid {
-                let entry = server
-                    .grid_player_map
-                    .get(&origin_grid)
-                    .ok_or("Not in grid_player_map")
-                    .map_err_unknown()?;
-                if entry.value().len() <= 1 {
-                    // set剩1个直接删set
-                    server.grid_player_map.remove(&origin_grid);
-                } else {
-                    entry.value().remove(&player_id);
-                }
-                server
-                    .grid_player_map
-                    .get_or_insert_with(target_grid, Default::default)
-                    .value()
-                    .insert(player_id);
-            }
-
-            let coord = Coord {
-                x: player.x,
-                y: player.y,
-            };
-            // 更新player_map
-            server.player_map.insert(player_id, player);
-            Ok(Response::new(coord))
+            let MovingRequest {
+                player_id,
+                dx,
+                dy,
+                dz,
+                ..
+            } = request;
+            let _guard = server.lock_player(player_id);
+            let player = server.get_player_info(&player_id).map_err_unknown()?;
+            // 超速或穿越障碍物时截断，只作用于xy平面
+            let (x, y) = server.world_map.clamp_move(player.x, player.y, dx, dy);
+            let z = player.z + dz;
+            server
+                .move_player_to(player, x, y, z)
+                .map_err_unknown()
+                .map(Response::new)
         }
 
         debug!("IN");
@@ -114,60 +110,87 @@ impl GameService for MapServer {
         res
     }
 
-    // 先找经过的grid，再逐点过滤
+    #[instrument(skip(self),fields(addr = %self.addr, player_id = %request.get_ref().player_id))]
+    async fn teleport(&self, request: Request<TeleportRequest>) -> RPCResult<Coord> {
+        async fn inner_teleport(server: MapServer, request: TeleportRequest) -> RPCResult<Coord> {
+            let TeleportRequest {
+                player_id, x, y, z, ..
+            } = request;
+            if server.world_map.is_blocked(x, y) {
+                return Err(Status::invalid_argument(format!("x:{x} y:{y} is blocked")));
+            }
+            let _guard = server.lock_player(player_id);
+            let player = server.get_player_info(&player_id).map_err_unknown()?;
+            server
+                .move_player_to(player, x, y, z)
+                .map_err_unknown()
+                .map(Response::new)
+        }
+
+        debug!("IN");
+        let res = tokio::spawn(inner_teleport(self.clone(), request.into_inner()))
+            .await
+            .map_err_unknown()?;
+        debug!(?res, "OUT");
+        res
+    }
+
+    #[instrument(skip(self),fields(addr = %self.addr, player_id = %request.get_ref().player_id))]
+    async fn set_velocity(&self, request: Request<VelocityRequest>) -> RPCResult<()> {
+        debug!("IN");
+        let VelocityRequest {
+            player_id,
+            vx,
+            vy,
+            vz,
+            ..
+        } = request.into_inner();
+        let _guard = self.lock_player(player_id);
+        let mut player = self.get_player_info(&player_id).map_err_unknown()?;
+        player.vx = vx;
+        player.vy = vy;
+        player.vz = vz;
+        self.player_map.insert(player_id, player);
+        Ok(Response::new(()))
+    }
+
+    // 先找经过的grid，再逐点过滤。areas非空时为多块的并集，公共边上的玩家只返回一次
     #[instrument(skip_all,fields(addr = %self.addr, aabb = ?request.get_ref()))]
     async fn query(&self, request: Request<QueryRequest>) -> RPCResult<QueryReply> {
         async fn inner_query(server: MapServer, request: QueryRequest) -> Vec<PlayerInfo> {
-            let QueryRequest {
-                xmin,
-                xmax,
-                ymin,
-                ymax,
-            } = request;
-            let aabb = AABB {
-                xmin,
-                xmax,
-                ymin,
-                ymax,
+            let to_aabb = |area: &QueryRequest| AABB {
+                xmin: area.xmin,
+                xmax: area.xmax,
+                ymin: area.ymin,
+                ymax: area.ymax,
+                zmin: area.zmin,
+                zmax: area.zmax,
             };
-            if (xmax - xmin) as usize / GRID_LENGTH * (ymax - ymin) as usize / GRID_LENGTH
-                >= server.player_map.len()
-            {
-                // grid数量比用户还多，不用它过滤了，直接遍历所有用户
-                server
-                    .player_map
-                    .iter()
-                    .filter_map(|entry| {
-                        let p = entry.value();
-                        if aabb.contains(p.x, p.y) {
-                            Some(p.clone())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<_>>()
+            let areas = if request.areas.is_empty() {
+                vec![to_aabb(&request)]
             } else {
-                let grids = aabb.get_grids_in_aabb();
-                grids
-                    .par_iter()
-                    .filter_map(|grid| {
-                        server
-                            .grid_player_map
-                            .get(grid)
-                            .map(|entry| entry.value().iter().map(|id| *id).collect::<Vec<_>>())
-                    })
-                    .flatten()
-                    .filter_map(|id| server.player_map.get(&id))
-                    .filter_map(|entry| {
-                        let p = entry.value();
-                        if aabb.contains(p.x, p.y) {
-                            Some(p.clone())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<_>>()
+                request.areas.iter().map(to_aabb).collect()
+            };
+            let mut player_ids = areas
+                .iter()
+                .flat_map(|aabb| server.get_player_ids_in_aabb(aabb))
+                .collect::<Vec<_>>();
+            if areas.len() > 1 {
+                player_ids.sort_unstable();
+                player_ids.dedup();
             }
+            player_ids
+                .into_par_iter()
+                .filter_map(|id| server.player_map.get(&id))
+                .filter_map(|entry| {
+                    let p = entry.value();
+                    if areas.iter().any(|aabb| aabb.contains_xyz(p.x, p.y, p.z)) {
+                        Some(p.clone())
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
         }
 
         debug!("IN");
@@ -184,43 +207,30 @@ impl GameService for MapServer {
         async fn inner_aoe(server: MapServer, request: AoeRequest) -> RPCResult<()> {
             let AoeRequest {
                 player_id,
-                coord: Some(Coord { x, y }),
+                coord: Some(Coord { x, y, z }),
                 radius,
+                ..
             } = request else {
-                return Err(Status::data_loss("Coord { x, y }"));
+                return Err(Status::data_loss("Coord { x, y, z }"));
             };
-            AABB {
-                xmin: x - radius,
-                xmax: x + radius,
-                ymin: y - radius,
-                ymax: y + radius,
-            }
-            .get_grids_in_aabb()
-            .into_par_iter()
-            .filter_map(|grid| {
-                server
-                    .grid_player_map
-                    .get(&grid)
-                    .map(|entry| entry.value().iter().map(|id| *id).collect::<Vec<_>>())
-            })
-            .flatten()
-            .filter_map(|id| {
-                // 过滤掉自己
-                if id != player_id {
-                    server
-                        .player_map
-                        .get(&id)
-                        .map(|entry| entry.value().clone())
-                } else {
-                    None
-                }
-            })
-            .for_each(|mut p| {
-                if (p.x - x) * (p.x - x) + (p.y - y) * (p.y - y) <= radius * radius {
-                    p.money += AOE_MONEY;
-                    server.player_map.insert(p.player_id, p);
-                }
-            });
+            server
+                .get_player_ids_in_aabb(&server.get_radius_aabb(x, y, z, radius))
+                .into_par_iter()
+                .filter(|id| *id != player_id) // 过滤掉自己
+                .for_each(|id| {
+                    let _guard = server.lock_player(id);
+                    // 正在导出的副本即将删除
+                    if server.exporting.contains(&id) {
+                        return;
+                    }
+                    let Ok(mut p) = server.get_player_info(&id) else {
+                        return;
+                    };
+                    if distance_square(&p, x, y, z) <= radius * radius {
+                        p.money += AOE_MONEY;
+                        server.player_map.insert(p.player_id, p);
+                    }
+                });
 
             Ok(Response::new(()))
         }
@@ -232,4 +242,187 @@ impl GameService for MapServer {
         debug!(?res, "OUT");
         res
     }
+
+    #[instrument(skip(self),fields(addr = %self.addr))]
+    async fn say(&self, request: Request<SayRequest>) -> RPCResult<DeliveryReply> {
+        async fn inner_say(server: MapServer, request: SayRequest) -> RPCResult<DeliveryReply> {
+            let SayRequest {
+                player_id,
+                radius,
+                text,
+                coord: Some(Coord { x, y, z }),
+                ..
+            } = request else {
+                return Err(Status::data_loss("Coord { x, y, z }"));
+            };
+            let delivered = server
+                .get_player_ids_in_aabb(&server.get_radius_aabb(x, y, z, radius))
+                .into_iter()
+                .filter(|id| *id != player_id)
+                .filter_map(|id| server.player_map.get(&id))
+                .filter(|entry| distance_square(entry.value(), x, y, z) <= radius * radius)
+                .filter(|entry| {
+                    server.send_event(
+                        *entry.key(),
+                        PlayerEvent {
+                            kind: EventKind::Chat.into(),
+                            from_player_id: player_id,
+                            text: text.clone(),
+                        },
+                    )
+                })
+                .count() as u32;
+            Ok(Response::new(DeliveryReply { delivered }))
+        }
+
+        debug!("IN");
+        let res = tokio::spawn(inner_say(self.clone(), request.into_inner()))
+            .await
+            .map_err_unknown()?;
+        debug!(?res, "OUT");
+        res
+    }
+
+    // 活跃时间由dispatcher维护，这里只确认玩家在本服务器
+    #[instrument(skip(self),fields(addr = %self.addr))]
+    async fn heartbeat(&self, request: Request<PlayerIdRequest>) -> RPCResult<()> {
+        let player_id = request.into_inner().player_id;
+        if self.player_map.contains_key(&player_id) {
+            Ok(Response::new(()))
+        } else {
+            Err(Status::not_found(format!(
+                "player_id:{player_id} not found"
+            )))
+        }
+    }
+
+    // 世界由dispatcher管理，map-server只负责其中一部分区域
+    #[instrument(skip(self),fields(addr = %self.addr))]
+    async fn change_world(&self, _request: Request<ChangeWorldRequest>) -> RPCResult<()> {
+        Err(Status::unimplemented(
+            "change_world is handled by dispatcher",
+        ))
+    }
+
+    // 扩缩容由dispatcher执行
+    #[instrument(skip(self),fields(addr = %self.addr))]
+    async fn get_scaling_events(
+        &self,
+        _request: Request<ScalingEventsRequest>,
+    ) -> RPCResult<ScalingEventsReply> {
+        Err(Status::unimplemented(
+            "scaling events are recorded by dispatcher",
+        ))
+    }
+
+    // 限流由dispatcher执行
+    #[instrument(skip(self),fields(addr = %self.addr))]
+    async fn get_rate_limit_stats(&self, _request: Request<()>) -> RPCResult<RateLimitStatsReply> {
+        Err(Status::unimplemented("rate limit is applied by dispatcher"))
+    }
+
+    // 与玩家登录相同，存入player_map与grid
+    #[instrument(skip(self),fields(addr = %self.addr))]
+    async fn spawn_npc(&self, request: Request<PlayerInfo>) -> RPCResult<()> {
+        let npc = PlayerInfo {
+            kind: EntityKind::Npc.into(),
+            ..request.into_inner()
+        };
+        self.login(npc.into_request()).await
+    }
+
+    #[instrument(skip(self),fields(addr = %self.addr))]
+    async fn despawn_npc(&self, request: Request<PlayerIdRequest>) -> RPCResult<()> {
+        let npc_id = request.get_ref().player_id;
+        match self.player_map.get(&npc_id) {
+            Some(entry) if entry.value().kind() == EntityKind::Npc => self.logout(request).await,
+            _ => Err(Status::not_found(format!("npc_id:{npc_id} not found"))),
+        }
+    }
+
+    // 只需遍历已订阅的玩家，areas为空时全部发送
+    #[instrument(skip(self),fields(addr = %self.addr))]
+    async fn broadcast(&self, request: Request<BroadcastRequest>) -> RPCResult<DeliveryReply> {
+        debug!("IN");
+        let BroadcastRequest { text, areas, .. } = request.into_inner();
+        let areas = areas
+            .into_iter()
+            .map(
+                |QueryRequest {
+                     xmin,
+                     ymin,
+                     xmax,
+                     ymax,
+                     zmin,
+                     zmax,
+                     ..
+                 }| AABB {
+                    xmin,
+                    xmax,
+                    ymin,
+                    ymax,
+                    zmin,
+                    zmax,
+                },
+            )
+            .collect::<Vec<_>>();
+        let player_ids = self
+            .event_tx_map
+            .iter()
+            .map(|entry| *entry.key())
+            .collect::<Vec<_>>();
+        let delivered = player_ids
+            .into_iter()
+            .filter(|id| {
+                areas.is_empty()
+                    || self.player_map.get(id).map_or(false, |entry| {
+                        let p = entry.value();
+                        areas.iter().any(|area| area.contains_xyz(p.x, p.y, p.z))
+                    })
+            })
+            .filter(|id| {
+                self.send_event(
+                    *id,
+                    PlayerEvent {
+                        kind: EventKind::Broadcast.into(),
+                        from_player_id: 0,
+                        text: text.clone(),
+                    },
+                )
+            })
+            .count() as u32;
+        debug!(?delivered, "OUT");
+        Ok(Response::new(DeliveryReply { delivered }))
+    }
+
+    // 重复订阅时替换旧的，旧事件流结束
+    #[instrument(skip(self),fields(addr = %self.addr))]
+    async fn subscribe(
+        &self,
+        request: Request<PlayerIdRequest>,
+    ) -> RPCResult<Self::SubscribeStream> {
+        debug!("IN");
+        let player_id = request.into_inner().player_id;
+        if !self.player_map.contains_key(&player_id) {
+            return Err(Status::not_found(format!(
+                "player_id:{player_id} not found"
+            )));
+        }
+        let (tx, rx) = mpsc::channel(EVENT_CHANNEL_SIZE);
+        self.event_tx_map.insert(player_id, tx);
+        if !self.player_map.contains_key(&player_id) {
+            // 期间被导出或登出
+            self.event_tx_map.remove(&player_id);
+            return Err(Status::not_found(format!(
+                "player_id:{player_id} not found"
+            )));
+        }
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+// 2D世界z都为0，与平面距离相同
+#[inline]
+fn distance_square(p: &PlayerInfo, x: f32, y: f32, z: f32) -> f32 {
+    (p.x - x) * (p.x - x) + (p.y - y) * (p.y - y) + (p.z - z) * (p.z - z)
 }
@@ -1,4 +1,4 @@
-use crate::server::MapServer;
+use crate::server::{LeavingWatcher, MapServer, LEAVING_CHANNEL_SIZE};
 
 use common::proto::game_service::game_service_server::GameService;
 use common::proto::game_service::*;
@@ -8,7 +8,8 @@ use common::*;
 
 use itertools::Itertools;
 use once_cell::sync::OnceCell;
-use tokio::sync::oneshot;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{async_trait, IntoRequest, Request, Response, Status};
 use tracing::*;
 
@@ -16,6 +17,8 @@ pub static mut SHUTDOWN_TX: OnceCell<oneshot::Sender<()>> = OnceCell::new();
 
 #[async_trait]
 impl MapService for MapServer {
+    type WatchLeavingStream = ReceiverStream<Result<PlayerInfo, Status>>;
+
     #[instrument(skip(self),fields(addr = %self.addr))]
     async fn export_player(&self, request: Request<ExportRequest>) -> RPCResult<()> {
         debug!("IN");
@@ -25,16 +28,38 @@ impl MapService for MapServer {
                 player_id,
                 addr,
                 coord,
+                world,
             } = request.into_inner();
             let mut target_cli = self.get_export_cli(addr).await.map_err_unknown()?;
-            let mut player = self.get_player_info(&player_id).map_err_unknown()?;
-            if let Some(Coord { x, y }) = coord {
+            let mut player = {
+                // tick替换玩家时不加锁读取可能读不到
+                // 取快照的同时标记，之后本地副本不再被修改，否则导入后登出时这些修改丢失
+                let _guard = self.lock_player(player_id);
+                let player = self.get_player_info(&player_id).map_err_unknown()?;
+                self.exporting.insert(player_id);
+                player
+            };
+            if let Some(Coord { x, y, z }) = coord {
                 player.x = x;
                 player.y = y;
+                player.z = z;
+            }
+            if let Some(TargetWorld { world_id }) = world {
+                player.world_id = world_id;
             }
-            target_cli.import_player(player).await?;
-            self.logout(PlayerIdRequest { player_id }.into_request())
-                .await
+            if let Err(status) = target_cli.import_player(player).await {
+                // 导入失败，玩家留在本server
+                self.exporting.remove(&player_id);
+                return Err(status);
+            }
+            self.logout(
+                PlayerIdRequest {
+                    player_id,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
         })
         .await
         .map_err_unknown()?
@@ -54,12 +79,18 @@ impl MapService for MapServer {
     ) -> RPCResult<ZonePlayersReply> {
         let depth = request.into_inner().depth;
         info!(?depth, "IN");
+        if depth == 0 || depth > ZoneId::max_depth(self.dimension) {
+            return Err(Status::invalid_argument(format!("depth:{depth}")));
+        }
         let self = self.clone();
         tokio::spawn(async move {
             self.player_map
                 .iter()
-                .map(|entry| (*entry.key(), (entry.value().x, entry.value().y)))
-                .into_group_map_by(|(_id, (x, y))| xy_to_zone_id(*x, *y, depth))
+                .map(|entry| {
+                    let p = entry.value();
+                    (*entry.key(), self.dimension.zone_id(p.x, p.y, p.z, depth))
+                })
+                .into_group_map_by(|(_id, zone_id)| *zone_id)
                 .into_iter()
                 .map(|(zone_id, value)| {
                     let (player_ids, _): (Vec<_>, Vec<_>) = value.into_iter().unzip();
@@ -73,7 +104,7 @@ impl MapService for MapServer {
         .map(|(zone_id, player_ids)| {
             info!("OUT: zone_id:{}, players:{}", zone_id, player_ids.len());
             Response::new(ZonePlayersReply {
-                zone_id,
+                zone_id: zone_id.into(),
                 player_ids,
             })
         })
@@ -102,9 +133,59 @@ impl MapService for MapServer {
 
     #[instrument(skip_all,fields(addr = %self.addr))]
     async fn get_overhead(&self, _request: Request<()>) -> RPCResult<OverheadReply> {
-        let count = self.player_map.len() as u32;
-        debug!(?count);
-        Ok(Response::new(OverheadReply { count }))
+        // 一次遍历分别计数，不用总数相减，并发登录登出时不会下溢
+        let (count, npc_count) =
+            self.player_map
+                .iter()
+                .fold((0, 0), |(count, npc_count), entry| {
+                    if entry.value().kind() == EntityKind::Npc {
+                        (count, npc_count + 1)
+                    } else {
+                        (count + 1, npc_count)
+                    }
+                });
+        debug!(?count, ?npc_count);
+        Ok(Response::new(OverheadReply { count, npc_count }))
+    }
+
+    #[instrument(skip_all,fields(addr = %self.addr))]
+    async fn get_moving_players(&self, _request: Request<()>) -> RPCResult<MovingPlayersReply> {
+        let infos: Vec<_> = self
+            .player_map
+            .iter()
+            .filter(|entry| {
+                let p = entry.value();
+                p.vx != 0.0 || p.vy != 0.0 || p.vz != 0.0
+            })
+            .map(|entry| entry.value().clone())
+            .collect();
+        debug!("OUT: {}", infos.len());
+        Ok(Response::new(MovingPlayersReply { infos }))
+    }
+
+    // 每个dispatcher一个订阅，断开后在下次tick时移除
+    #[instrument(skip_all,fields(addr = %self.addr))]
+    async fn watch_leaving(
+        &self,
+        request: Request<WatchLeavingRequest>,
+    ) -> RPCResult<Self::WatchLeavingStream> {
+        let zones = request
+            .into_inner()
+            .zone_ids
+            .into_iter()
+            .map(|raw| {
+                ZoneId::from_raw(raw)
+                    .filter(|zone_id| zone_id.dimension() == self.dimension)
+                    .ok_or_else(|| Status::invalid_argument(format!("zone_id:{raw:#x}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        info!(?zones, "IN");
+        let (tx, rx) = mpsc::channel(LEAVING_CHANNEL_SIZE);
+        self.leaving_watchers
+            .lock()
+            .unwrap()
+            .push(LeavingWatcher { zones, tx });
+        Ok(Response::new(ReceiverStream::new(rx)))
     }
 
     #[instrument(skip_all,fields(addr = %self.addr))]
@@ -3,37 +3,72 @@ mod server;
 
 use api::map_service::SHUTDOWN_TX;
 
+use common::launch::{self, DEFAULT_HOST, DEFAULT_PORT_RANGE};
 use common::proto::game_service::game_service_server::GameServiceServer;
 use common::proto::map_service::map_service_server::MapServiceServer;
-use common::MAP_PORT_ENV_NAME;
+use common::tls::TlsConfig;
+use common::world_map::WorldMap;
+use common::{
+    Dimension, ServerId, DIMENSION_ENV_NAME, MAP_HOST_ENV_NAME, MAP_PORT_ENV_NAME,
+    SERVER_ID_ENV_NAME, TICK_INTERVAL_ENV_NAME, WORLD_MAP_ENV_NAME,
+};
 
+use anyhow::{Context, Result};
+use tokio_stream::wrappers::TcpListenerStream;
 use tonic::transport::Server;
 use tracing::info;
 
+// 握手前出错直接退出，dispatcher等到进程退出即启动失败
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
-    let port =
-        std::env::var(MAP_PORT_ENV_NAME).unwrap_or_else(|_| panic!("Need env {MAP_PORT_ENV_NAME}"));
-    let addr = format!("127.0.0.1:{}", port);
-    let socket = addr.parse().unwrap();
+    // 地址、端口与server_id由dispatcher设定，绑定后把实际地址报告给dispatcher
+    let host = std::env::var(MAP_HOST_ENV_NAME).unwrap_or_else(|_| DEFAULT_HOST.to_string());
+    let ports = std::env::var(MAP_PORT_ENV_NAME).unwrap_or_else(|_| DEFAULT_PORT_RANGE.to_string());
+    let ports = launch::parse_port_range(&ports)
+        .with_context(|| format!("invalid env {MAP_PORT_ENV_NAME}"))?;
+    let server_id: ServerId = std::env::var(SERVER_ID_ENV_NAME)
+        .with_context(|| format!("need env {SERVER_ID_ENV_NAME}"))?
+        .parse()
+        .with_context(|| format!("invalid env {SERVER_ID_ENV_NAME}"))?;
+    let listener = launch::bind(&host, ports).await?;
+    let addr = listener.local_addr()?.to_string();
     info!("starting at {addr}");
 
-    let server_id = std::env::var(MAP_PORT_ENV_NAME)
-        .map(|s| s.parse().unwrap())
-        .unwrap_or(1);
-
-    let map_server = server::MapServer::new(server_id, addr);
+    let world_map = WorldMap::from_env(WORLD_MAP_ENV_NAME)?;
+    let tls = TlsConfig::from_env()?;
+    let dimension = Dimension::parse(&std::env::var(DIMENSION_ENV_NAME).unwrap_or_default())
+        .with_context(|| format!("invalid env {DIMENSION_ENV_NAME}"))?;
+    let map_server =
+        server::MapServer::new(server_id, addr.clone(), world_map, tls.clone(), dimension);
+    let tick_interval: u64 = match std::env::var(TICK_INTERVAL_ENV_NAME) {
+        Ok(s) => s
+            .parse()
+            .with_context(|| format!("invalid env {TICK_INTERVAL_ENV_NAME}"))?,
+        Err(_) => 0,
+    };
+    if tick_interval > 0 {
+        tokio::spawn(map_server.clone().simulation_tick(tick_interval));
+    }
     let (otx, orx) = tokio::sync::oneshot::channel();
     // Safety: 用一次就退出
     unsafe { SHUTDOWN_TX.get_or_init(|| otx) };
-    Server::builder()
+    let mut builder = Server::builder();
+    if let Some(tls) = &tls {
+        info!("TLS enabled");
+        builder = builder.tls_config(tls.server_config())?;
+    }
+    // 已绑定端口，输出后dispatcher即可连接
+    println!("{}", launch::ready_line(server_id, &addr));
+    builder
         .add_service(MapServiceServer::new(map_server.clone()))
         .add_service(GameServiceServer::new(map_server))
-        .serve_with_shutdown(socket, async move { orx.await.unwrap() })
-        .await
-        .unwrap();
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async move {
+            orx.await.unwrap()
+        })
+        .await?;
 
     info!("exit");
+    Ok(())
 }
@@ -1,14 +1,25 @@
-use common::proto::game_service::PlayerInfo;
+use common::proto::game_service::{Coord, PlayerEvent, PlayerInfo};
 use common::proto::map_service::map_service_client::MapServiceClient;
-use common::{GridId, PlayerId, ServerId};
+use common::tls::{self, TlsConfig};
+use common::world_map::WorldMap;
+use common::{
+    xyz_to_grid, Dimension, ErrHandle, GridId, PlayerId, ServerId, ZoneId, AABB, WORLD_X_MAX,
+    WORLD_X_MIN, WORLD_Y_MAX, WORLD_Y_MIN, WORLD_Z_MAX, WORLD_Z_MIN,
+};
 
 use anyhow::{Context, Result};
 use crossbeam_skiplist::{SkipMap, SkipSet};
+use tokio::sync::mpsc::{self, error::TrySendError};
 use tokio::sync::Mutex;
 use tonic::transport::Channel;
+use tonic::Status;
+use tracing::*;
 
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, MutexGuard};
+
+const PLAYER_LOCK_SHARDS: usize = 64;
+pub const LEAVING_CHANNEL_SIZE: usize = 1024; // 一次tick跨出zone的玩家较多，超出时丢弃
 
 /// 精确位置以player_map为准，grid作为加速结构按位置初筛
 #[derive(Default)]
@@ -16,8 +27,22 @@ pub struct InnerServer {
     pub server_id: ServerId,
     pub addr: String,
     pub player_map: SkipMap<PlayerId, PlayerInfo>,
-    pub grid_player_map: SkipMap<GridId, SkipSet<PlayerId>>, // (usize, usize): grid id
+    pub grid_player_map: SkipMap<GridId, SkipSet<PlayerId>>, // (usize, usize, usize): grid id，2D世界只有z=0一层
     pub export_addr_cli_cache: Mutex<Option<(String, MapServiceClient<Channel>)>>, // 导出用户时使用，导出完成清空。不会同时向两个服务器导出
+    pub world_map: WorldMap, // 静态地图，moving时检查障碍物与速度上限
+    pub event_tx_map: SkipMap<PlayerId, mpsc::Sender<Result<PlayerEvent, Status>>>, // 玩家事件流，登出/导出时关闭
+    pub tls: Option<TlsConfig>, // 导出用户时连接其他map-server使用
+    pub dimension: Dimension,   // 与所属世界相同
+    pub player_locks: Vec<std::sync::Mutex<()>>, // 按player_id分段，同一玩家的读改写串行
+    pub exporting: SkipSet<PlayerId>, // 已取快照正在导出的玩家，本地副本导入后删除，tick与aoe不再修改
+    pub leaving_watchers: std::sync::Mutex<Vec<LeavingWatcher>>, // 订阅tick跨出zone的dispatcher
+}
+
+/// dispatcher订阅时按它的拓扑给出本server的zone，tick把玩家移出这些zone时推送
+#[derive(Clone)]
+pub struct LeavingWatcher {
+    pub zones: Vec<ZoneId>,
+    pub tx: mpsc::Sender<Result<PlayerInfo, Status>>,
 }
 
 #[derive(Clone)]
@@ -33,17 +58,37 @@ impl Deref for MapServer {
 }
 
 impl MapServer {
-    pub fn new(server_id: ServerId, addr: String) -> Self {
+    pub fn new(
+        server_id: ServerId,
+        addr: String,
+        world_map: WorldMap,
+        tls: Option<TlsConfig>,
+        dimension: Dimension,
+    ) -> Self {
         Self {
             inner: InnerServer {
                 server_id,
                 addr,
+                world_map,
+                tls,
+                dimension,
+                player_locks: (0..PLAYER_LOCK_SHARDS)
+                    .map(|_| Default::default())
+                    .collect(),
                 ..Default::default()
             }
             .into(),
         }
     }
 
+    /// 读改写player_map前持有，RPC与tick对同一玩家串行
+    /// 持有后需重新读取玩家，期间被导出或登出的不能再写回
+    pub fn lock_player(&self, player_id: PlayerId) -> MutexGuard<'_, ()> {
+        self.player_locks[player_id as usize % self.player_locks.len()]
+            .lock()
+            .unwrap()
+    }
+
     pub fn get_player_info(&self, player_id: &PlayerId) -> Result<PlayerInfo> {
         self.player_map
             .get(player_id)
@@ -51,12 +96,197 @@ impl MapServer {
             .with_context(|| format!("player:{} no in cache", player_id))
     }
 
+    // 以(x,y,z)为中心、radius为半径的圆/球的外接AABB。2D世界z恒为0，不跨z层
+    pub fn get_radius_aabb(&self, x: f32, y: f32, z: f32, radius: f32) -> AABB {
+        let (zmin, zmax) = match self.dimension {
+            Dimension::D2 => (z, z),
+            Dimension::D3 => (z - radius, z + radius),
+        };
+        AABB {
+            xmin: x - radius,
+            xmax: x + radius,
+            ymin: y - radius,
+            ymax: y + radius,
+            zmin,
+            zmax,
+        }
+    }
+
+    // AABB经过的grid中的玩家，需调用方再逐点过滤
+    // grid数量比用户还多(例如大半径aoe)时不用它过滤了，直接遍历所有用户
+    // 2D世界只有z=0一层grid，zone范围的z是整个世界高度，只取这一层
+    pub fn get_player_ids_in_aabb(&self, aabb: &AABB) -> Vec<PlayerId> {
+        let aabb = match self.dimension {
+            Dimension::D2 => AABB {
+                zmin: 0.0,
+                zmax: 0.0,
+                ..aabb.clone()
+            },
+            Dimension::D3 => aabb.clone(),
+        };
+        if aabb.grid_count() >= self.player_map.len() {
+            self.player_map.iter().map(|entry| *entry.key()).collect()
+        } else {
+            aabb.get_grids_in_aabb()
+                .into_iter()
+                .filter_map(|grid| self.grid_player_map.get(&grid))
+                .flat_map(|entry| entry.value().iter().map(|id| *id).collect::<Vec<_>>())
+                .collect()
+        }
+    }
+
+    // 更新玩家坐标，跨越grid时先删后插，调用方需持有lock_player
+    pub fn move_player_to(&self, mut player: PlayerInfo, x: f32, y: f32, z: f32) -> Result<Coord> {
+        let player_id = player.player_id;
+        let origin_grid = xyz_to_grid(player.x, player.y, player.z);
+        let target_grid = xyz_to_grid(x, y, z);
+        if target_grid != origin_grid {
+            let entry = self
+                .grid_player_map
+                .get(&origin_grid)
+                .context("Not in grid_player_map")?;
+            if entry.value().len() <= 1 {
+                // set剩1个直接删set
+                self.grid_player_map.remove(&origin_grid);
+            } else {
+                entry.value().remove(&player_id);
+            }
+            self.grid_player_map
+                .get_or_insert_with(target_grid, Default::default)
+                .value()
+                .insert(player_id);
+        }
+
+        player.x = x;
+        player.y = y;
+        player.z = z;
+        self.player_map.insert(player_id, player);
+        Ok(Coord { x, y, z })
+    }
+
+    /// 按速度积分移动所有玩家，dt单位秒
+    /// 碰到障碍物或世界边界时停下并清零速度。跨出订阅的zone时推送给dispatcher，由它导出
+    /// 2D世界vz由dispatcher置0
+    /// 每个玩家在lock_player下重新读取并写回，不覆盖并发的moving等请求；正在导出的由导入的server继续移动
+    pub fn tick(&self, dt: f32) {
+        let watchers = {
+            let mut watchers = self.leaving_watchers.lock().unwrap();
+            watchers.retain(|watcher| !watcher.tx.is_closed());
+            watchers.clone()
+        };
+        for entry in self.player_map.iter() {
+            let player_id = *entry.key();
+            let _guard = self.lock_player(player_id);
+            // 期间被导出或登出
+            let Ok(mut player) = self.get_player_info(&player_id) else {
+                continue;
+            };
+            if (player.vx == 0.0 && player.vy == 0.0 && player.vz == 0.0)
+                || self.exporting.contains(&player_id)
+            {
+                continue;
+            }
+            let dx = player.vx * dt;
+            let dy = player.vy * dt;
+            let dz = player.vz * dt;
+            let (mut x, mut y) = self.world_map.clamp_move(player.x, player.y, dx, dy);
+            let mut z = player.z + dz;
+            if x >= WORLD_X_MAX
+                || y >= WORLD_Y_MAX
+                || z >= WORLD_Z_MAX
+                || x <= WORLD_X_MIN
+                || y < WORLD_Y_MIN
+                || z < WORLD_Z_MIN
+            {
+                (x, y, z) = (player.x, player.y, player.z);
+            }
+            if (x, y, z) != (player.x + dx, player.y + dy, player.z + dz) {
+                player.vx = 0.0;
+                player.vy = 0.0;
+                player.vz = 0.0;
+            }
+            let (from_x, from_y, from_z) = (player.x, player.y, player.z);
+            if self.move_player_to(player, x, y, z).log_err().is_err() {
+                continue;
+            }
+            for watcher in &watchers {
+                if self.in_zones(&watcher.zones, from_x, from_y, from_z)
+                    && !self.in_zones(&watcher.zones, x, y, z)
+                {
+                    self.notify_leaving(watcher, player_id);
+                }
+            }
+        }
+    }
+
+    fn in_zones(&self, zones: &[ZoneId], x: f32, y: f32, z: f32) -> bool {
+        zones
+            .iter()
+            .any(|&zone_id| self.dimension.zone_id(x, y, z, zone_id.depth()) == zone_id)
+    }
+
+    // 缓冲已满时丢弃，由dispatcher定期同步时导出
+    fn notify_leaving(&self, watcher: &LeavingWatcher, player_id: PlayerId) {
+        let Ok(player) = self.get_player_info(&player_id) else {
+            return;
+        };
+        if let Err(TrySendError::Full(_)) = watcher.tx.try_send(Ok(player)) {
+            warn!(?player_id, "leaving channel full, drop event");
+        }
+    }
+
+    // 开启tick时常驻运行
+    pub async fn simulation_tick(self, interval: u64) {
+        use tokio::time::{interval as tick_interval, Duration, MissedTickBehavior};
+
+        info!("simulation tick every {interval}ms");
+        let mut ticker = tick_interval(Duration::from_millis(interval));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last = ticker.tick().await;
+        loop {
+            let now = ticker.tick().await;
+            self.tick((now - last).as_secs_f32());
+            last = now;
+        }
+    }
+
+    // 投递到玩家事件流，未订阅、已断开或缓冲已满时返回false
+    pub fn send_event(&self, player_id: PlayerId, event: PlayerEvent) -> bool {
+        let Some(entry) = self.event_tx_map.get(&player_id) else {
+            return false;
+        };
+        match entry.value().try_send(Ok(event)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                warn!(?player_id, "event channel full, drop event");
+                false
+            }
+            Err(TrySendError::Closed(_)) => {
+                entry.remove();
+                false
+            }
+        }
+    }
+
+    // skiplist中删除的sender会延迟释放，不能靠drop结束事件流，主动发送错误结束
+    pub fn close_event_stream(&self, player_id: PlayerId) {
+        let Some(entry) = self.event_tx_map.remove(&player_id) else {
+            return;
+        };
+        let status = Status::unavailable(format!("player_id:{player_id} left server"));
+        if let Err(TrySendError::Full(status)) = entry.value().try_send(Err(status)) {
+            let tx = entry.value().clone();
+            tokio::spawn(async move { tx.send(status).await });
+        }
+    }
+
     pub async fn get_export_cli(&self, addr: String) -> Result<MapServiceClient<Channel>> {
         let mut guard = self.export_addr_cli_cache.lock().await;
         match &*guard {
             Some((cached_addr, cached_cli)) if cached_addr == &addr => Ok(cached_cli.clone()),
             _ => {
-                let map_cli = MapServiceClient::connect(addr.clone()).await?;
+                let channel = tls::connect(addr.clone(), self.tls.as_ref()).await?;
+                let map_cli = MapServiceClient::new(channel);
                 *guard = Some((addr, map_cli.clone()));
                 Ok(map_cli)
             }
@@ -10,7 +10,13 @@ use tonic::IntoRequest;
 #[tokio::test]
 async fn test_query() {
     crate::init_log();
-    let server = MapServer::new(1, "127.0.0.1:5001".to_string());
+    let server = MapServer::new(
+        1,
+        "127.0.0.1:5001".to_string(),
+        Default::default(),
+        None,
+        Default::default(),
+    );
     // (-1,-1) (0,0) (1,1) (2,2)
     let mut players = (0..4)
         .map(|i| PlayerInfo {
@@ -18,6 +24,7 @@ async fn test_query() {
             x: i as f32 - 1.0,
             y: i as f32 - 1.0,
             money: 0,
+            ..Default::default()
         })
         .collect::<Vec<_>>();
     for player in &players {
@@ -30,8 +37,10 @@ async fn test_query() {
                 coord: Some(Coord {
                     x: players[1].x,
                     y: players[1].y,
+                    ..Default::default()
                 }),
                 radius: 1.9,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -0,0 +1,92 @@
+use map_server::server::MapServer;
+
+use common::proto::game_service::game_service_server::GameService;
+use common::proto::game_service::*;
+
+use tokio_stream::StreamExt;
+use tonic::IntoRequest;
+
+#[tokio::test]
+async fn test_broadcast() {
+    crate::init_log();
+    let server = MapServer::new(
+        1,
+        "127.0.0.1:5001".to_string(),
+        Default::default(),
+        None,
+        Default::default(),
+    );
+    // (0,0) (10,10) (20,20)，2未订阅
+    for i in 0..3 {
+        server
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x: i as f32 * 10.0,
+                    y: i as f32 * 10.0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    let mut streams = Vec::new();
+    for i in 0..2 {
+        streams.push(
+            server
+                .subscribe(
+                    PlayerIdRequest {
+                        player_id: i,
+                        ..Default::default()
+                    }
+                    .into_request(),
+                )
+                .await
+                .unwrap()
+                .into_inner(),
+        );
+    }
+
+    let delivered = server
+        .broadcast(
+            BroadcastRequest {
+                text: "all".to_string(),
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .delivered;
+    assert_eq!(delivered, 2);
+    for stream in streams.iter_mut() {
+        let event = stream.next().await.unwrap().unwrap();
+        assert_eq!(event.kind(), EventKind::Broadcast);
+        assert_eq!(event.text, "all");
+    }
+
+    let delivered = server
+        .broadcast(
+            BroadcastRequest {
+                text: "area".to_string(),
+                areas: vec![QueryRequest {
+                    xmin: 5.0,
+                    ymin: 5.0,
+                    xmax: 30.0,
+                    ymax: 30.0,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .delivered;
+    assert_eq!(delivered, 1);
+    let event = streams[1].next().await.unwrap().unwrap();
+    assert_eq!(event.text, "area");
+}
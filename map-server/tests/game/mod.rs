@@ -1,3 +1,8 @@
 mod aoe;
+mod broadcast;
 mod moving;
+mod npc;
 mod query;
+mod say;
+mod simulation;
+mod teleport;
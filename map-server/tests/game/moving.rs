@@ -8,12 +8,19 @@ use tonic::IntoRequest;
 #[tokio::test]
 async fn test_moving() {
     crate::init_log();
-    let server = MapServer::new(1, "127.0.0.1:5001".to_string());
+    let server = MapServer::new(
+        1,
+        "127.0.0.1:5001".to_string(),
+        Default::default(),
+        None,
+        Default::default(),
+    );
     let player = PlayerInfo {
         player_id: 1,
         x: 0.0,
         y: 0.0,
         money: 0,
+        ..Default::default()
     };
     server.login(player.clone().into_request()).await.unwrap();
     server
@@ -22,6 +29,7 @@ async fn test_moving() {
                 player_id: 1,
                 dx: 1.0,
                 dy: -1.9,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -33,6 +41,65 @@ async fn test_moving() {
         x: 1.0,
         y: -1.9,
         money: 0,
+        ..Default::default()
     };
     assert_eq!(player, expect);
 }
+
+#[tokio::test]
+async fn test_moving_blocked() {
+    use common::world_map::WorldMap;
+
+    crate::init_log();
+    let world_map = WorldMap::parse("speed_limit 10\nrect 5 -100 6 100").unwrap();
+    let server = MapServer::new(
+        1,
+        "127.0.0.1:5001".to_string(),
+        world_map,
+        None,
+        Default::default(),
+    );
+    server
+        .login(
+            PlayerInfo {
+                player_id: 1,
+                x: 0.0,
+                y: 0.0,
+                money: 0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    // 超速截断为10，穿墙停在墙前
+    let coord = server
+        .moving(
+            MovingRequest {
+                player_id: 1,
+                dx: 100.0,
+                dy: 0.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(coord.x < 5.0 && coord.x > 4.9);
+    assert_eq!(server.player_map.get(&1).unwrap().value().x, coord.x);
+
+    assert!(server
+        .login(
+            PlayerInfo {
+                player_id: 2,
+                x: 5.5,
+                y: 0.0,
+                money: 0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .is_err());
+}
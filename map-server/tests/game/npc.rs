@@ -0,0 +1,100 @@
+use map_server::server::MapServer;
+
+use common::proto::game_service::game_service_server::GameService;
+use common::proto::game_service::*;
+use common::proto::map_service::map_service_server::MapService;
+
+use tonic::{Code, IntoRequest};
+
+#[tokio::test]
+async fn test_npc() {
+    crate::init_log();
+    let server = MapServer::new(
+        1,
+        "127.0.0.1:5001".to_string(),
+        Default::default(),
+        None,
+        Default::default(),
+    );
+    server
+        .login(
+            PlayerInfo {
+                player_id: 1,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    for i in 2..5 {
+        server
+            .spawn_npc(
+                PlayerInfo {
+                    player_id: i,
+                    x: i as f32,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+
+    // NPC与玩家分开计数
+    let overhead = server
+        .get_overhead(().into_request())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!((overhead.count, overhead.npc_count), (1, 3));
+
+    let infos = server
+        .query(
+            QueryRequest {
+                xmin: -10.0,
+                ymin: -10.0,
+                xmax: 10.0,
+                ymax: 10.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    assert_eq!(infos.len(), 4);
+    assert_eq!(
+        infos.iter().filter(|p| p.kind() == EntityKind::Npc).count(),
+        3
+    );
+
+    // 只能despawn NPC
+    let status = server
+        .despawn_npc(
+            PlayerIdRequest {
+                player_id: 1,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    server
+        .despawn_npc(
+            PlayerIdRequest {
+                player_id: 2,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    let overhead = server
+        .get_overhead(().into_request())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!((overhead.count, overhead.npc_count), (1, 2));
+}
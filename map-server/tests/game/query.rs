@@ -9,7 +9,13 @@ use tonic::IntoRequest;
 #[tokio::test]
 async fn test_query() {
     crate::init_log();
-    let server = MapServer::new(1, "127.0.0.1:5001".to_string());
+    let server = MapServer::new(
+        1,
+        "127.0.0.1:5001".to_string(),
+        Default::default(),
+        None,
+        Default::default(),
+    );
     // (0,0) (1,1) (2,2) (3,3)
     let players = (0..4)
         .map(|i| PlayerInfo {
@@ -17,6 +23,7 @@ async fn test_query() {
             x: i as f32,
             y: i as f32,
             money: 0,
+            ..Default::default()
         })
         .collect::<Vec<_>>();
     for player in &players {
@@ -29,6 +36,7 @@ async fn test_query() {
                 ymin: 1.0,
                 xmax: 2.0,
                 ymax: 2.0,
+                ..Default::default()
             }
             .into_request(),
         )
@@ -0,0 +1,98 @@
+use map_server::server::MapServer;
+
+use common::proto::game_service::game_service_server::GameService;
+use common::proto::game_service::*;
+
+use tokio_stream::StreamExt;
+use tonic::IntoRequest;
+
+#[tokio::test]
+async fn test_say() {
+    crate::init_log();
+    let server = MapServer::new(
+        1,
+        "127.0.0.1:5001".to_string(),
+        Default::default(),
+        None,
+        Default::default(),
+    );
+    // (0,0) (1,1) (2,2) (3,3)
+    for i in 0..4 {
+        server
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x: i as f32,
+                    y: i as f32,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    let mut streams = Vec::new();
+    for i in 0..4 {
+        streams.push(
+            server
+                .subscribe(
+                    PlayerIdRequest {
+                        player_id: i,
+                        ..Default::default()
+                    }
+                    .into_request(),
+                )
+                .await
+                .unwrap()
+                .into_inner(),
+        );
+    }
+    let delivered = server
+        .say(
+            SayRequest {
+                player_id: 1,
+                radius: 1.5,
+                text: "hi".to_string(),
+                coord: Some(Coord {
+                    x: 1.0,
+                    y: 1.0,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .delivered;
+    assert_eq!(delivered, 2);
+    for i in [0, 2] {
+        let event = streams[i].next().await.unwrap().unwrap();
+        assert_eq!(event.from_player_id, 1);
+        assert_eq!(event.text, "hi");
+    }
+
+    // 登出后事件流结束
+    server
+        .logout(
+            PlayerIdRequest {
+                player_id: 3,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(streams[3].next().await, Some(Err(_))));
+    assert!(server
+        .subscribe(
+            PlayerIdRequest {
+                player_id: 3,
+                ..Default::default()
+            }
+            .into_request()
+        )
+        .await
+        .is_err());
+}
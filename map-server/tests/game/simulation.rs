@@ -0,0 +1,312 @@
+use map_server::server::MapServer;
+
+use common::proto::game_service::game_service_server::GameService;
+use common::proto::game_service::*;
+use common::proto::map_service::map_service_server::MapService;
+use common::proto::map_service::{ExportRequest, WatchLeavingRequest};
+use common::world_map::WorldMap;
+use common::Dimension;
+
+use tokio::net::TcpListener;
+use tokio::time::{sleep, Duration};
+use tonic::IntoRequest;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn test_tick() {
+    crate::init_log();
+    let world_map = WorldMap::parse("rect 50 -100 60 100").unwrap();
+    let server = MapServer::new(
+        1,
+        "127.0.0.1:5001".to_string(),
+        world_map,
+        None,
+        Default::default(),
+    );
+    for i in 0..2 {
+        server
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x: 0.0,
+                    y: 0.0,
+                    money: 0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    server
+        .set_velocity(
+            VelocityRequest {
+                player_id: 1,
+                vx: 40.0,
+                vy: -200.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    let moving = server
+        .get_moving_players(().into_request())
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    assert_eq!(moving.len(), 1);
+    assert_eq!(moving[0].player_id, 1);
+
+    server.tick(0.5);
+    let player = server.get_player_info(&1).unwrap();
+    assert_eq!((player.x, player.y), (20.0, -100.0));
+    let player = server.get_player_info(&0).unwrap();
+    assert_eq!((player.x, player.y), (0.0, 0.0));
+    let res = server
+        .query(
+            QueryRequest {
+                xmin: 0.0,
+                ymin: -200.0,
+                xmax: 100.0,
+                ymax: -50.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    assert_eq!(res.len(), 1);
+
+    // 撞墙停下
+    server
+        .set_velocity(
+            VelocityRequest {
+                player_id: 1,
+                vx: 100.0,
+                vy: 0.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    server.tick(1.0);
+    let player = server.get_player_info(&1).unwrap();
+    assert!(player.x < 50.0 && player.x > 49.9);
+    assert_eq!((player.vx, player.vy), (0.0, 0.0));
+    let moving = server
+        .get_moving_players(().into_request())
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    assert!(moving.is_empty());
+}
+
+// tick与moving、logout并发：moving的位移不被tick覆盖，登出的玩家不会被写回
+#[tokio::test(flavor = "multi_thread")]
+async fn test_tick_concurrent() {
+    crate::init_log();
+    let server = MapServer::new(
+        1,
+        "127.0.0.1:5001".to_string(),
+        Default::default(),
+        None,
+        Default::default(),
+    );
+    for i in 0..100 {
+        server
+            .login(
+                PlayerInfo {
+                    player_id: i,
+                    x: 0.0,
+                    y: 0.0,
+                    vx: 1.0,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    let stop = Arc::new(AtomicBool::new(false));
+    let ticker = {
+        let server = server.clone();
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                server.tick(0.001);
+            }
+        })
+    };
+
+    for _ in 0..20 {
+        for i in 0..100 {
+            server
+                .moving(
+                    MovingRequest {
+                        player_id: i,
+                        dy: 1.0,
+                        ..Default::default()
+                    }
+                    .into_request(),
+                )
+                .await
+                .unwrap();
+        }
+    }
+    for i in 0..100 {
+        let _guard = server.lock_player(i);
+        assert_eq!(server.get_player_info(&i).unwrap().y, 20.0);
+    }
+
+    for i in 0..100 {
+        server
+            .logout(
+                PlayerIdRequest {
+                    player_id: i,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    stop.store(true, Ordering::Relaxed);
+    ticker.join().unwrap();
+    assert!(server.player_map.is_empty());
+    assert!(server.grid_player_map.is_empty());
+}
+
+// 导出期间tick与aoe不修改已取快照的本地副本，导入失败后玩家留在本server继续移动
+#[tokio::test(flavor = "multi_thread")]
+async fn test_tick_exporting() {
+    crate::init_log();
+    let server = MapServer::new(
+        1,
+        "127.0.0.1:5001".to_string(),
+        Default::default(),
+        None,
+        Default::default(),
+    );
+    for (player_id, vx) in [(0, 10.0), (1, 0.0)] {
+        server
+            .login(
+                PlayerInfo {
+                    player_id,
+                    vx,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+
+    // 目标只接受连接不应答，导入一直等待
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = format!("http://{}", listener.local_addr().unwrap());
+    let accept = tokio::spawn(async move { listener.accept().await.unwrap().0 });
+    let export = {
+        let server = server.clone();
+        tokio::spawn(async move {
+            server
+                .export_player(
+                    ExportRequest {
+                        player_id: 0,
+                        addr,
+                        ..Default::default()
+                    }
+                    .into_request(),
+                )
+                .await
+        })
+    };
+    while !server.exporting.contains(&0) {
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    server.tick(1.0);
+    server
+        .aoe(
+            AoeRequest {
+                player_id: 1,
+                coord: Some(Coord::default()),
+                radius: 20.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap();
+    let player = server.get_player_info(&0).unwrap();
+    assert_eq!((player.x, player.money), (0.0, 0));
+
+    // 断开连接，导入失败
+    drop(accept.await.unwrap());
+    assert!(export.await.unwrap().is_err());
+    assert!(!server.exporting.contains(&0));
+    server.tick(1.0);
+    assert_eq!(server.get_player_info(&0).unwrap().x, 10.0);
+}
+
+// tick把玩家移出订阅的zone时推送一次，zone内移动不推送
+#[tokio::test]
+async fn test_watch_leaving() {
+    crate::init_log();
+    let server = MapServer::new(
+        1,
+        "127.0.0.1:5001".to_string(),
+        Default::default(),
+        None,
+        Default::default(),
+    );
+    for (player_id, vx) in [(0, -100.0), (1, 10.0)] {
+        server
+            .login(
+                PlayerInfo {
+                    player_id,
+                    x: 10.0,
+                    y: 10.0,
+                    vx,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .unwrap();
+    }
+    let zone_id = Dimension::D2.zone_id(10.0, 10.0, 0.0, 2);
+    let mut rx = server
+        .watch_leaving(
+            WatchLeavingRequest {
+                zone_ids: vec![zone_id.into()],
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .into_inner();
+
+    server.tick(0.5);
+    let player = rx.try_recv().unwrap().unwrap();
+    assert_eq!((player.player_id, player.x), (0, -40.0));
+    assert!(rx.try_recv().is_err());
+    // 已在zone外，不重复推送
+    server.tick(0.5);
+    assert!(rx.try_recv().is_err());
+
+    let status = server
+        .watch_leaving(WatchLeavingRequest { zone_ids: vec![0] }.into_request())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
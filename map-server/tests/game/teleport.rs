@@ -0,0 +1,75 @@
+use map_server::server::MapServer;
+
+use common::proto::game_service::game_service_server::GameService;
+use common::proto::game_service::*;
+use common::world_map::WorldMap;
+
+use tonic::IntoRequest;
+
+#[tokio::test]
+async fn test_teleport() {
+    crate::init_log();
+    let world_map = WorldMap::parse("speed_limit 10\nrect 500 500 600 600").unwrap();
+    let server = MapServer::new(
+        1,
+        "127.0.0.1:5001".to_string(),
+        world_map,
+        None,
+        Default::default(),
+    );
+    let player = PlayerInfo {
+        player_id: 1,
+        x: 0.0,
+        y: 0.0,
+        money: 0,
+        ..Default::default()
+    };
+    server.login(player.clone().into_request()).await.unwrap();
+
+    // 绝对坐标，不受speed_limit限制
+    let coord = server
+        .teleport(
+            TeleportRequest {
+                player_id: 1,
+                x: 1000.0,
+                y: -300.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!((coord.x, coord.y), (1000.0, -300.0));
+    let res = server
+        .query(
+            QueryRequest {
+                xmin: 900.0,
+                ymin: -400.0,
+                xmax: 1100.0,
+                ymax: -200.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    assert_eq!(res.len(), 1);
+    assert_eq!(server.grid_player_map.len(), 1);
+
+    // 不能传送到障碍物里
+    assert!(server
+        .teleport(
+            TeleportRequest {
+                player_id: 1,
+                x: 550.0,
+                y: 550.0,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .is_err());
+}
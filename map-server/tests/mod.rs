@@ -1,5 +1,6 @@
 mod game;
 
+#[allow(clippy::redundant_closure)]
 pub fn init_log() {
     use once_cell::sync::OnceCell;
 
@@ -0,0 +1,344 @@
+use crate::fixture::player;
+use crate::topology::Topology;
+use crate::wait::{wait_for, wait_until, DEFAULT_TIMEOUT};
+
+use game_server::auth::TokenAuth;
+use game_server::dispatcher::Dispatcher;
+use game_server::util::Config;
+
+use common::proto::game_service::game_service_client::GameServiceClient;
+use common::proto::game_service::game_service_server::{GameService, GameServiceServer};
+use common::proto::game_service::{PlayerIdRequest, PlayerInfo};
+use common::{ErrHandle, PlayerId, ServerId, WorldId, ZoneId};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Server};
+use tonic::{IntoRequest, Status};
+use tracing::*;
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+// 没有配置集群密钥时使用
+pub const TEST_CLUSTER_SECRET: &str = "test-cluster-secret";
+
+/// # 进程内测试集群
+/// dispatcher在本进程内，GameService/DispatcherService绑定端口0由系统分配，同一台机器上的测试可以并行。
+/// 默认不启动scaling_moniter，测试调用[`TestCluster::scaling_round`]逐轮扩缩容，
+/// 再用wait_for_*等到拓扑满足条件，不依赖固定的sleep。
+/// 结束时调用[`TestCluster::teardown`]关闭map-server；直接drop只停止dispatcher的后台任务。
+pub struct TestCluster {
+    pub dispatchers: Vec<Dispatcher>,
+    pub addrs: Vec<String>, // 各dispatcher的地址，http://127.0.0.1:<port>
+    timeout: Duration,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+pub struct TestClusterBuilder {
+    config: Config,
+    dispatchers: usize,
+    auto_scaling: bool,
+    timeout: Duration,
+}
+
+impl TestClusterBuilder {
+    /// dispatcher个数，大于1时按一致性哈希划分玩家，0号为coordinator
+    pub fn dispatchers(mut self, count: usize) -> Self {
+        self.dispatchers = count.max(1);
+        self
+    }
+
+    /// 与正式运行一样按scaling_interval自动扩缩容
+    pub fn auto_scaling(mut self, enabled: bool) -> Self {
+        self.auto_scaling = enabled;
+        self
+    }
+
+    /// wait_for_*的超时
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 先绑定所有端口得到地址，再从最后一个dispatcher开始逐个创建并开始服务，
+    /// coordinator最后创建，接管时其他dispatcher已可以应答拉取拓扑
+    pub async fn start(self) -> Result<TestCluster> {
+        let (listeners, addrs) = bind_local(self.dispatchers).await?;
+        let dispatcher_addrs = if addrs.len() > 1 {
+            addrs.join(",")
+        } else {
+            String::new()
+        };
+        let cluster_secret = if self.config.cluster_secret.is_empty() {
+            TEST_CLUSTER_SECRET.to_string()
+        } else {
+            self.config.cluster_secret.clone()
+        };
+
+        let mut cluster = TestCluster {
+            dispatchers: vec![],
+            addrs,
+            timeout: self.timeout,
+            tasks: vec![],
+        };
+        for (dispatcher_id, listener) in listeners.into_iter().enumerate().rev() {
+            let dispatcher = Dispatcher::new(Config {
+                dispatcher_id: dispatcher_id as u32,
+                dispatcher_addrs: dispatcher_addrs.clone(),
+                cluster_secret: cluster_secret.clone(),
+                ..self.config.clone()
+            })
+            .await?;
+            cluster.spawn_tasks(&dispatcher, listener, self.auto_scaling);
+            cluster.dispatchers.insert(0, dispatcher);
+        }
+        info!(addrs = ?cluster.addrs, "test cluster started");
+        Ok(cluster)
+    }
+}
+
+/// 在127.0.0.1上绑定count个由系统分配的端口，返回listener与对应的地址(http://127.0.0.1:<port>)
+/// 需要事先知道地址、自己启动服务的测试持有listener，用serve_with_incoming服务，端口不会被占用
+pub async fn bind_local(count: usize) -> Result<(Vec<TcpListener>, Vec<String>)> {
+    let mut listeners = vec![];
+    for _ in 0..count {
+        listeners.push(TcpListener::bind("127.0.0.1:0").await?);
+    }
+    let addrs = listeners
+        .iter()
+        .map(|listener| Ok(format!("http://{}", listener.local_addr()?)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok((listeners, addrs))
+}
+
+impl TestCluster {
+    pub fn builder(config: Config) -> TestClusterBuilder {
+        TestClusterBuilder {
+            config,
+            dispatchers: 1,
+            auto_scaling: false,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// 一个dispatcher，手动扩缩容
+    pub async fn start(config: Config) -> Result<Self> {
+        Self::builder(config).start().await
+    }
+
+    // 与main相同的后台任务，服务地址已绑定，返回前即可连接
+    fn spawn_tasks(&mut self, dispatcher: &Dispatcher, listener: TcpListener, auto_scaling: bool) {
+        let mut builder = Server::builder();
+        let router = if dispatcher.config.auth_key.is_empty() {
+            builder.add_service(GameServiceServer::new(dispatcher.clone()))
+        } else {
+            let auth = TokenAuth::new(dispatcher.config.auth_key.as_str());
+            builder.add_service(GameServiceServer::with_interceptor(
+                dispatcher.clone(),
+                auth,
+            ))
+        };
+        let serve = router
+            .add_service(dispatcher.dispatcher_service())
+            .serve_with_incoming(TcpListenerStream::new(listener));
+        self.tasks.push(tokio::spawn(async move {
+            let _ = serve.await.log_err();
+        }));
+        self.tasks
+            .push(tokio::spawn(dispatcher.clone().topology_sync()));
+        if auto_scaling {
+            self.tasks
+                .push(tokio::spawn(dispatcher.clone().scaling_moniter()));
+        }
+        if dispatcher.config.tick_interval > 0 {
+            self.tasks
+                .push(tokio::spawn(dispatcher.clone().simulation_moniter()));
+        }
+        if dispatcher.config.idle_timeout > 0 {
+            self.tasks
+                .push(tokio::spawn(dispatcher.clone().idle_reaper()));
+        }
+    }
+
+    /// 当前的leader，没有时为0号
+    pub fn leader(&self) -> &Dispatcher {
+        self.dispatchers
+            .iter()
+            .find(|dispatcher| dispatcher.cluster.is_coordinator())
+            .unwrap_or(&self.dispatchers[0])
+    }
+
+    /// 连接第index个dispatcher，经过网络与认证
+    pub async fn client(&self, index: usize) -> Result<GameServiceClient<Channel>> {
+        Ok(GameServiceClient::connect(self.addrs[index].clone()).await?)
+    }
+
+    /// 从0号dispatcher登录，玩家归其他dispatcher时由它转发
+    pub async fn login(&self, player_id: PlayerId, x: f32, y: f32) -> Result<(), Status> {
+        self.dispatchers[0]
+            .login(
+                PlayerInfo {
+                    money: 99,
+                    ..player(player_id, x, y)
+                }
+                .into_request(),
+            )
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn logout(&self, player_id: PlayerId) -> Result<(), Status> {
+        self.dispatchers[0]
+            .logout(
+                PlayerIdRequest {
+                    player_id,
+                    ..Default::default()
+                }
+                .into_request(),
+            )
+            .await
+            .map(|_| ())
+    }
+
+    /// 所有dispatcher竞选一次，leader执行一轮扩缩容，之后等其他dispatcher同步到拓扑
+    pub async fn scaling_round(&self) -> Result<()> {
+        for dispatcher in &self.dispatchers {
+            if dispatcher.lead().await {
+                dispatcher.scaling_round().await;
+            }
+        }
+        self.wait_for_sync().await
+    }
+
+    pub async fn topology(&self) -> Result<Topology> {
+        Topology::capture(self.leader()).await
+    }
+
+    /// 每POLL_INTERVAL取一次拓扑，满足cond时返回，超时的错误带上最后一次的拓扑
+    pub async fn wait_for_topology(
+        &self,
+        what: &str,
+        cond: impl Fn(&Topology) -> bool,
+    ) -> Result<Topology> {
+        let last = Mutex::new(None);
+        let res = wait_for(what, self.timeout, || async {
+            match self.topology().await {
+                Ok(topology) if cond(&topology) => Some(Ok(topology)),
+                Ok(topology) => {
+                    *last.lock().unwrap() = Some(topology);
+                    None
+                }
+                Err(e) => Some(Err(e)),
+            }
+        })
+        .await;
+        match res {
+            Ok(res) => res,
+            Err(e) => match last.into_inner().unwrap() {
+                Some(topology) => Err(anyhow!("{e}, last topology:\n{topology}")),
+                None => Err(e),
+            },
+        }
+    }
+
+    /// 等到共有count台server且没有进行中的导出
+    pub async fn wait_for_servers(&self, count: usize) -> Result<Topology> {
+        self.wait_for_topology(&format!("{count} servers"), |topology| {
+            topology.server_count() == count && topology.is_settled()
+        })
+        .await
+    }
+
+    /// 等到各dispatcher的拓扑版本与leader相同
+    pub async fn wait_for_sync(&self) -> Result<()> {
+        wait_until("topology sync", self.timeout, || async {
+            let version = *self.leader().topology_version.lock().unwrap();
+            self.dispatchers
+                .iter()
+                .all(|dispatcher| *dispatcher.topology_version.lock().unwrap() == version)
+        })
+        .await
+    }
+
+    /// 拓扑稳定时调用，不一致则panic并输出原因与拓扑：
+    /// 1. 各dispatcher的zone到server的映射相同
+    /// 2. 每个玩家记录的server负责其坐标所在的zone
+    /// 3. 各map-server的人数之和等于各dispatcher记录的玩家数之和
+    pub async fn assert_consistent(&self) {
+        if let Err(e) = self.check_consistent().await {
+            let topology = self
+                .topology()
+                .await
+                .map_or_else(|e| format!("{e:#}"), |t| t.to_string());
+            panic!("inconsistent cluster: {e:#}\n{topology}");
+        }
+    }
+
+    async fn check_consistent(&self) -> Result<()> {
+        let zone_map = |dispatcher: &Dispatcher| {
+            dispatcher
+                .worlds
+                .iter()
+                .flat_map(|(&world_id, world)| {
+                    world.zone_server_map.iter().map(move |entry| {
+                        ((world_id, *entry.key()), entry.value().server.server_id)
+                    })
+                })
+                .collect::<BTreeMap<(WorldId, ZoneId), ServerId>>()
+        };
+        let leader = zone_map(self.leader());
+        for dispatcher in &self.dispatchers {
+            ensure!(
+                zone_map(dispatcher) == leader,
+                "dispatcher:{} zones differ from leader",
+                dispatcher.cluster.id
+            );
+        }
+
+        let mut players = 0;
+        for dispatcher in &self.dispatchers {
+            for entry in dispatcher.player_map.iter() {
+                let (server, x, y, z) = entry.value();
+                let (zone_id, zone_servers) = dispatcher
+                    .get_server_of_coord(server.world_id, *x, *y, *z)
+                    .with_context(|| format!("player:{} at ({x}, {y}, {z})", entry.key()))?;
+                ensure!(
+                    zone_servers.server.server_id == server.server_id,
+                    "player:{} recorded on server:{}, but zone:{zone_id} is on server:{}",
+                    entry.key(),
+                    server.server_id,
+                    zone_servers.server.server_id
+                );
+            }
+            players += dispatcher.player_map.len() as u32;
+        }
+
+        let topology = self.topology().await?;
+        let loaded = topology
+            .servers
+            .values()
+            .map(|server| server.players + server.npcs)
+            .sum::<u32>();
+        ensure!(
+            loaded == players,
+            "map-servers hold {loaded} players, dispatchers record {players}"
+        );
+        Ok(())
+    }
+
+    /// 停止dispatcher的后台任务并关闭所有map-server
+    pub async fn teardown(self) {
+        self.tasks.iter().for_each(|task| task.abort());
+        self.leader().shutdown_all_map_server().await;
+    }
+}
+
+impl Drop for TestCluster {
+    fn drop(&mut self) {
+        self.tasks.iter().for_each(|task| task.abort());
+    }
+}
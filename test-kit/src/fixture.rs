@@ -0,0 +1,64 @@
+use game_server::dispatcher::Dispatcher;
+
+use common::proto::game_service::game_service_server::GameService;
+use common::proto::game_service::{PlayerInfo, QueryRequest};
+use common::{PlayerId, WorldId, DEFAULT_WORLD_ID};
+
+use tonic::IntoRequest;
+
+// 测试中的玩家都在这个范围内
+const TEST_AREA: f32 = 1000.0;
+
+/// 默认世界z=0的玩家，其他字段用结构体更新语法指定
+pub fn player(player_id: PlayerId, x: f32, y: f32) -> PlayerInfo {
+    player_at(DEFAULT_WORLD_ID, player_id, x, y, 0.0)
+}
+
+pub fn player_at(world_id: WorldId, player_id: PlayerId, x: f32, y: f32, z: f32) -> PlayerInfo {
+    PlayerInfo {
+        player_id,
+        x,
+        y,
+        z,
+        world_id,
+        ..Default::default()
+    }
+}
+
+/// 默认世界的所有玩家(含NPC)，按player_id排序
+pub async fn query_all(dispatcher: &Dispatcher) -> Vec<PlayerInfo> {
+    query_world(dispatcher, DEFAULT_WORLD_ID).await
+}
+
+pub async fn query_world(dispatcher: &Dispatcher, world_id: WorldId) -> Vec<PlayerInfo> {
+    query_box(dispatcher, world_id, -TEST_AREA, TEST_AREA).await
+}
+
+/// xy为整个测试范围，z在[zmin, zmax]内。2D世界忽略z
+pub async fn query_box(
+    dispatcher: &Dispatcher,
+    world_id: WorldId,
+    zmin: f32,
+    zmax: f32,
+) -> Vec<PlayerInfo> {
+    let mut infos = dispatcher
+        .query(
+            QueryRequest {
+                xmin: -TEST_AREA,
+                xmax: TEST_AREA,
+                ymin: -TEST_AREA,
+                ymax: TEST_AREA,
+                zmin,
+                zmax,
+                world_id,
+                ..Default::default()
+            }
+            .into_request(),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .infos;
+    infos.sort_by_key(|p| p.player_id);
+    infos
+}
@@ -0,0 +1,12 @@
+//! # 测试工具
+//! 进程内启动dispatcher与map-server的测试集群[`TestCluster`]，按条件等待代替固定sleep，断言拓扑一致。
+//! 各测试共用的玩家构造与全图查询见[`fixture`]。
+//! map-server以内部对象启动时需开启game-server的map_server_inside feature：
+//! > cargo t --features game-server/map_server_inside
+pub mod cluster;
+pub mod fixture;
+pub mod topology;
+pub mod wait;
+
+pub use cluster::TestCluster;
+pub use topology::Topology;
@@ -0,0 +1,112 @@
+use game_server::dispatcher::Dispatcher;
+
+use common::proto::map_service::OverheadReply;
+use common::{ServerId, WorldId, ZoneId};
+
+use anyhow::{Context, Result};
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// 某一时刻的拓扑与各server负载：zone来自dispatcher的zone_server_map，人数来自各map-server的get_overhead
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Topology {
+    pub servers: BTreeMap<ServerId, ServerState>,
+    pub exporting: Vec<ServerId>, // 正在给zone导入用户的server，扩缩容进行中
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerState {
+    pub world_id: WorldId,
+    pub zones: Vec<ZoneId>,
+    pub players: u32,
+    pub npcs: u32,
+}
+
+impl Topology {
+    pub async fn capture(dispatcher: &Dispatcher) -> Result<Self> {
+        let mut topology = Self::default();
+        for world in dispatcher.worlds.values() {
+            for entry in world.zone_server_map.iter() {
+                topology.exporting.extend(
+                    entry
+                        .value()
+                        .exporting_server
+                        .as_ref()
+                        .map(|server| server.server_id),
+                );
+            }
+        }
+        topology.exporting.sort_unstable();
+        topology.exporting.dedup();
+        for server in dispatcher.get_all_servers() {
+            let OverheadReply { count, npc_count } = server
+                .map_cli
+                .clone()
+                .get_overhead(())
+                .await
+                .with_context(|| format!("get_overhead of server:{}", server.server_id))?
+                .into_inner();
+            let mut zones = server.zones.clone();
+            zones.sort_unstable();
+            topology.servers.insert(
+                server.server_id,
+                ServerState {
+                    world_id: server.world_id,
+                    zones,
+                    players: count,
+                    npcs: npc_count,
+                },
+            );
+        }
+        Ok(topology)
+    }
+
+    pub fn server_count(&self) -> usize {
+        self.servers.len()
+    }
+
+    pub fn world_server_count(&self, world_id: WorldId) -> usize {
+        self.servers
+            .values()
+            .filter(|server| server.world_id == world_id)
+            .count()
+    }
+
+    /// 各server的玩家数，降序
+    pub fn player_counts(&self) -> Vec<u32> {
+        let mut counts = self
+            .servers
+            .values()
+            .map(|server| server.players)
+            .collect::<Vec<_>>();
+        counts.sort_unstable_by(|a, b| b.cmp(a));
+        counts
+    }
+
+    pub fn total_players(&self) -> u32 {
+        self.servers.values().map(|server| server.players).sum()
+    }
+
+    /// 没有进行中的导出
+    pub fn is_settled(&self) -> bool {
+        self.exporting.is_empty()
+    }
+}
+
+// 每个server一行，断言失败时输出
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (server_id, server) in &self.servers {
+            writeln!(
+                f,
+                "server:{server_id} world:{} zones:{:?} players:{} npcs:{}",
+                server.world_id, server.zones, server.players, server.npcs
+            )?;
+        }
+        if !self.exporting.is_empty() {
+            writeln!(f, "exporting:{:?}", self.exporting)?;
+        }
+        Ok(())
+    }
+}
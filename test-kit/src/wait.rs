@@ -0,0 +1,38 @@
+use anyhow::{bail, Result};
+use tokio::time::{sleep, Duration, Instant};
+
+use std::future::Future;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// 每POLL_INTERVAL调用一次check，返回Some时结束，超时返回错误
+/// 条件满足立即返回，不必按最慢的情况sleep
+pub async fn wait_for<T, F, Fut>(what: &str, timeout: Duration, mut check: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<T>>,
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(value) = check().await {
+            return Ok(value);
+        }
+        if Instant::now() >= deadline {
+            bail!("timed out after {timeout:?} waiting for {what}");
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+pub async fn wait_until<F, Fut>(what: &str, timeout: Duration, mut cond: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    wait_for(what, timeout, || {
+        let fut = cond();
+        async move { fut.await.then_some(()) }
+    })
+    .await
+}